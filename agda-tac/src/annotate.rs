@@ -0,0 +1,29 @@
+/// Annotations are comments starting with this.
+pub const ANNOTATION: &str = "-- \u{21d2} ";
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The line with `text` as a trailing annotation, replacing an old one.
+pub fn annotated(line: &str, text: &str) -> String {
+    let line = strip(line).unwrap_or_default();
+    format!("{}  {}{}", line, ANNOTATION, one_line(text))
+}
+
+/// A line of its own for the value of an expression.
+pub fn annotation_line(expr: &str, value: &str) -> String {
+    format!("{}{} = {}", ANNOTATION, one_line(expr), one_line(value))
+}
+
+/// The line without its annotation, `None` if the line is nothing else.
+pub fn strip(line: &str) -> Option<String> {
+    if line.trim_start().starts_with(ANNOTATION) {
+        return None;
+    }
+    let marker = format!("  {}", ANNOTATION);
+    Some(match line.find(&marker) {
+        Some(i) => line[..i].trim_end().to_owned(),
+        None => line.to_owned(),
+    })
+}
@@ -1,8 +1,13 @@
+use std::fmt;
+use std::path::Path;
+
 use clap::{App, AppSettings};
 use minitt_util::cli::{cli_completion_generation, GenShellSubCommand};
 use structopt::StructOpt;
 
-#[derive(StructOpt)]
+use crate::config::Config;
+
+#[derive(StructOpt, Debug)]
 #[structopt(
     about,
     name = "agda-tac",
@@ -17,6 +22,34 @@ pub struct CliOptions {
     #[structopt(long, name = "path")]
     pub agda: Option<String>,
 
+    /// Ask Agda to look for modules in this directory too, may be given several times
+    #[structopt(short = "i", long, name = "dir", number_of_values = 1)]
+    pub include: Vec<String>,
+
+    /// Ask Agda not to use any library files
+    #[structopt(long)]
+    pub no_libraries: bool,
+
+    /// Ask Agda to put interface files next to the source files
+    #[structopt(long)]
+    pub local_interfaces: bool,
+
+    /// Ask Agda to cache the type-checking state between loads
+    #[structopt(long, conflicts_with = "no-caching")]
+    pub caching: bool,
+
+    /// Ask Agda not to cache the type-checking state between loads
+    #[structopt(long)]
+    pub no_caching: bool,
+
+    /// Ask Agda to exit at the first error, agda-tac stops with it
+    #[structopt(long)]
+    pub exit_on_error: bool,
+
+    /// Path to the config file (default: `agda-tac/config` in your config directory)
+    #[structopt(long, name = "config-file")]
+    pub config: Option<String>,
+
     /// Print all commands that `agda-tac` sends to `agda`
     #[structopt(long)]
     pub debug_command: bool,
@@ -25,12 +58,113 @@ pub struct CliOptions {
     #[structopt(short = "p", long)]
     pub plain: bool,
 
+    /// Disable colored output, but keep completion and hints
+    #[structopt(long)]
+    pub no_color: bool,
+
+    /// Screen-reader friendly output: no colors, symbols or spinners,
+    /// each block starts with what it is and the lines are short
+    #[structopt(long)]
+    pub a11y: bool,
+
+    /// Print the output as JSON objects, one per line
+    #[structopt(long)]
+    pub json: bool,
+
+    /// Talk JSON-RPC over stdio instead of running the REPL
+    #[structopt(long)]
+    pub json_rpc: bool,
+
+    /// With `--json-rpc`, check on Agda after this many quiet seconds,
+    /// and restart it if it doesn't answer
+    #[structopt(long, name = "seconds")]
+    pub heartbeat: Option<u64>,
+
+    /// Also write the output into this file (like `.agda-tac/info.txt`),
+    /// so it can be opened in an editor split
+    #[structopt(long, name = "info-file")]
+    pub info_file: Option<String>,
+
+    /// Write the errors and warnings of each load into this file
+    /// in Vim's errorformat, to be read by `:cfile`
+    #[structopt(long, name = "quickfix-file")]
+    pub vim_quickfix: Option<String>,
+
+    /// Write the diagnostics and remaining holes of each load into this file
+    /// as SARIF, for code-review tools
+    #[structopt(long, name = "sarif-file")]
+    pub sarif: Option<String>,
+
+    /// Also read commands from this named pipe (created if missing),
+    /// like `echo 'give 0 x' > pipe` from an editor
+    #[structopt(long, name = "fifo")]
+    pub listen: Option<String>,
+
+    /// Type `IOTCM` commands and see Agda's responses as they are, with timestamps,
+    /// for working on the protocol
+    #[structopt(long)]
+    pub raw: bool,
+
+    /// Start even if another agda-tac has the file open
+    #[structopt(long)]
+    pub force: bool,
+
     /// Print all responses that `agda` sends to `agda-tac`
     #[structopt(long)]
     pub debug_response: bool,
 
+    /// Let `quit` quit even with unsaved or unchecked edits, like `quit!`
+    #[structopt(long)]
+    pub no_confirm_exit: bool,
+
+    /// Ring the terminal bell when type-checking finishes successfully
+    #[structopt(long)]
+    pub bell_on_done: bool,
+
+    /// Ring the terminal bell when type-checking fails
+    #[structopt(long)]
+    pub bell_on_fail: bool,
+
     #[structopt(subcommand)]
-    completion: Option<GenShellSubCommand>,
+    pub command: Option<SubCommand>,
+}
+
+#[derive(StructOpt)]
+pub enum SubCommand {
+    #[structopt(flatten)]
+    Completion(GenShellSubCommand),
+    /// Writes a tags file of the definitions in the files, for go-to-definition in editors
+    Tags {
+        /// The files to load, they are not changed
+        #[structopt(name = "FILES", required = true)]
+        files: Vec<String>,
+        /// Where to write the tags (default: `tags`, or `TAGS` with `--etags`)
+        #[structopt(short = "o", long, name = "tags-file")]
+        output: Option<String>,
+        /// Write an Emacs TAGS file instead of a ctags file
+        #[structopt(long)]
+        etags: bool,
+    },
+}
+
+impl fmt::Debug for SubCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubCommand::Completion(GenShellSubCommand::Completion { shell }) => {
+                f.debug_struct("Completion").field("shell", shell).finish()
+            }
+            SubCommand::Tags {
+                files,
+                output,
+                etags,
+            } => f
+                .debug_struct("Tags")
+                .field("files", files)
+                .field("output", output)
+                .field("etags", etags)
+                .finish(),
+        }
+    }
 }
 
 fn app<'a, 'b>() -> App<'a, 'b> {
@@ -41,8 +175,98 @@ fn app<'a, 'b>() -> App<'a, 'b> {
     app.after_help(extra_help)
 }
 
-pub fn pre() -> CliOptions {
-    let args: CliOptions = CliOptions::from_clap(&app().get_matches());
-    cli_completion_generation(&args.completion, app);
-    args
+/// Fill in the options that are not on the command line from the environment:
+///
+/// + `$AGDA_TAC_OPTS`, flags like on the command line, split at whitespace
+/// + `$AGDA_TAC_AGDA`, the path of `agda`
+/// + `$AGDA_TAC_INCLUDE`, include directories separated like in `$PATH`
+/// + `$AGDA_TAC_PLAIN=1`, like `--plain`
+/// + `$NO_COLOR` or `$AGDA_TAC_COLOR=never`, like `--no-color`
+///
+/// The command line wins over the environment, include directories add up.
+pub fn merge_env(
+    args: &mut CliOptions,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let is_set = |key: &str| match var(key) {
+        Some(value) => !value.is_empty() && value != "0" && value != "false",
+        None => false,
+    };
+    if let Some(agda) = var("AGDA_TAC_AGDA").filter(|s| !s.is_empty()) {
+        args.agda = args.agda.take().or(Some(agda));
+    }
+    if let Some(dirs) = var("AGDA_TAC_INCLUDE") {
+        let dirs = std::env::split_paths(&dirs).map(|d| d.display().to_string());
+        let mut include: Vec<String> = dirs.filter(|d| !d.is_empty()).collect();
+        include.append(&mut args.include);
+        args.include = include;
+    }
+    args.plain |= is_set("AGDA_TAC_PLAIN");
+    args.no_color |= var("NO_COLOR").is_some() || var("AGDA_TAC_COLOR").as_deref() == Some("never");
+    let opts = match var("AGDA_TAC_OPTS") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let words = std::iter::once("agda-tac").chain(opts.split_whitespace());
+    let env = CliOptions::from_iter_safe(words).map_err(|e| e.message)?;
+    if env.file.is_some() || env.command.is_some() {
+        return Err("only flags are allowed".to_owned());
+    }
+    let mut include = env.include;
+    include.append(&mut args.include);
+    args.include = include;
+    args.agda = args.agda.take().or(env.agda);
+    args.config = args.config.take().or(env.config);
+    args.heartbeat = args.heartbeat.or(env.heartbeat);
+    args.info_file = args.info_file.take().or(env.info_file);
+    args.vim_quickfix = args.vim_quickfix.take().or(env.vim_quickfix);
+    args.sarif = args.sarif.take().or(env.sarif);
+    args.listen = args.listen.take().or(env.listen);
+    args.raw |= env.raw;
+    args.force |= env.force;
+    if !args.caching && !args.no_caching {
+        args.caching = env.caching;
+        args.no_caching = env.no_caching;
+    }
+    args.no_libraries |= env.no_libraries;
+    args.local_interfaces |= env.local_interfaces;
+    args.exit_on_error |= env.exit_on_error;
+    args.debug_command |= env.debug_command;
+    args.debug_response |= env.debug_response;
+    args.plain |= env.plain;
+    args.no_color |= env.no_color;
+    args.a11y |= env.a11y;
+    args.json |= env.json;
+    args.json_rpc |= env.json_rpc;
+    args.no_confirm_exit |= env.no_confirm_exit;
+    args.bell_on_done |= env.bell_on_done;
+    args.bell_on_fail |= env.bell_on_fail;
+    Ok(())
+}
+
+/// Fill in the options that are neither on the command line nor in the environment
+/// from the config file, which comes last.
+pub fn merge_config(args: &mut CliOptions, config: &Config) {
+    args.agda = args.agda.take().or_else(|| config.agda.clone());
+    let mut include = config.include.clone();
+    include.append(&mut args.include);
+    args.include = include;
+    args.plain |= config.plain;
+    args.no_color |= config.no_color;
+    args.no_confirm_exit |= config.no_confirm_exit;
+}
+
+/// The options and the config file they name.
+pub fn pre() -> (CliOptions, Config) {
+    let mut args: CliOptions = CliOptions::from_clap(&app().get_matches());
+    if let Err(e) = merge_env(&mut args, |key| std::env::var(key).ok()) {
+        eprintln!("Ignoring $AGDA_TAC_OPTS: {}", e);
+    }
+    let config = Config::load(args.config.as_ref().map(Path::new));
+    merge_config(&mut args, &config);
+    if let Some(SubCommand::Completion(GenShellSubCommand::Completion { shell })) = &args.command {
+        let completion = Some(GenShellSubCommand::Completion { shell: *shell });
+        cli_completion_generation(&completion, app);
+    }
+    (args, config)
 }
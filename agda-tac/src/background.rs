@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use agda_mode::agda::{AgdaOptions, ReplState};
+use agda_mode::base::InteractionPoint;
+use agda_mode::cmd::{AutoOptions, Cmd};
+use agda_mode::session::SharedAgda;
+use tokio::sync::Mutex;
+
+use crate::file_io::Monad;
+
+/// How long Auto may try on each goal, in milliseconds.
+const SPECULATIVE_TIMEOUT: u32 = 200;
+
+/// What a job of the background Agda found out.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Finding {
+    /// Auto solves the goal with the term.
+    Solvable {
+        goal: InteractionPoint,
+        term: String,
+    },
+    /// The background Agda broke down, a new one is started for the next job.
+    Broken(String),
+}
+
+/// How to start the process, and the process once it's started.
+#[derive(Clone)]
+struct Starter {
+    program: String,
+    options: AgdaOptions,
+    agda: Arc<Mutex<Option<SharedAgda>>>,
+}
+
+impl Starter {
+    /// The process, started with `file` if it's not running yet.
+    async fn agda(&self, file: &str) -> Monad<SharedAgda> {
+        let mut slot = self.agda.lock().await;
+        if let Some(agda) = &*slot {
+            return Ok(agda.clone());
+        }
+        let mut agda = ReplState::start_with(&self.program, file.to_owned(), &self.options).await?;
+        // The first load is read before the process is shared.
+        let _ = agda.next_all_goals_warnings().await?;
+        let agda = SharedAgda::new(agda);
+        *slot = Some(agda.clone());
+        Ok(agda)
+    }
+
+    async fn reset(&self) {
+        *self.agda.lock().await = None;
+    }
+}
+
+/// A second Agda process, for work that shouldn't change what the Agda
+/// of the session has loaded, or that the session shouldn't wait for.
+/// It's started the first time it's needed.
+pub struct Background {
+    starter: Starter,
+    /// Bumped when the goals change, so the jobs about the old ones stop.
+    generation: Arc<AtomicUsize>,
+    findings: Receiver<(usize, Finding)>,
+    sender: Sender<(usize, Finding)>,
+}
+
+impl Background {
+    pub fn new(program: String, options: AgdaOptions) -> Self {
+        let (sender, findings) = channel();
+        Self {
+            starter: Starter {
+                program,
+                options,
+                agda: Default::default(),
+            },
+            generation: Default::default(),
+            findings,
+            sender,
+        }
+    }
+
+    /// The process, started with `file` if it's not running yet.
+    pub async fn agda(&self, file: &str) -> Monad<SharedAgda> {
+        self.starter.agda(file).await
+    }
+
+    /// Forget a broken process, the next one is started when it's needed.
+    pub async fn reset(&self) {
+        self.starter.reset().await
+    }
+
+    /// The goals are numbered anew, what's found about the old ones is dropped.
+    pub fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// What the jobs found since the last call, about the current goals.
+    pub fn findings(&self) -> Vec<Finding> {
+        let current = self.generation.load(Ordering::SeqCst);
+        let findings = self.findings.try_iter();
+        let findings = findings.filter(|(generation, _)| *generation == current);
+        findings.map(|(_, finding)| finding).collect()
+    }
+
+    /// Try Auto on the `goals` of `contents` (the buffer of `file`) in a task of its own,
+    /// the solvable goals are [found](Self::findings) one by one.
+    pub fn speculate(&self, file: String, contents: String, goals: Vec<InteractionPoint>) {
+        let starter = self.starter.clone();
+        let generation = self.generation.clone();
+        let mine = generation.load(Ordering::SeqCst);
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let current = || generation.load(Ordering::SeqCst) == mine;
+            let found = |finding| drop(sender.send((mine, finding)));
+            let tried = speculate(&starter, file, &contents, goals, current, &found).await;
+            if let Err(e) = tried {
+                starter.reset().await;
+                found(Finding::Broken(e.to_string()));
+            }
+        });
+    }
+}
+
+/// Load a copy of the buffer, so the goals are numbered like the session's,
+/// and try Auto on them while they are current.
+async fn speculate(
+    starter: &Starter,
+    file: String,
+    contents: &str,
+    goals: Vec<InteractionPoint>,
+    current: impl Fn() -> bool,
+    found: &impl Fn(Finding),
+) -> Monad {
+    let shared = starter.agda(&file).await?;
+    let mut agda = shared.lock().await;
+    agda.set_file(file);
+    agda.load_shadow(contents).await?;
+    if agda.next_all_goals_warnings().await?.is_err() {
+        return Ok(());
+    }
+    let options = AutoOptions::new().timeout(SPECULATIVE_TIMEOUT);
+    for goal in goals {
+        if !current() {
+            break;
+        }
+        agda.command(Cmd::auto_one(goal, &options)).await?;
+        if let Ok(Some(term)) = agda.next_auto().await? {
+            found(Finding::Solvable { goal, term });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::file_io::save_lines;
+
+/// The default bookmarks file, `agda-tac/bookmarks` in the user's config directory.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("agda-tac").join("bookmarks"))
+}
+
+/// Expressions (or commands, when they start with `:`) kept across sessions,
+/// one `name = text` per line of the file.
+#[derive(Debug, Clone, Default)]
+pub struct Bookmarks {
+    /// Where they are written, nowhere if there's no config directory.
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, String>,
+}
+
+impl Bookmarks {
+    /// Lines that are not `name = text` are skipped.
+    pub fn parse(text: &str) -> BTreeMap<String, String> {
+        let entries = text.lines().filter_map(|line| {
+            let idx = line.find('=')?;
+            let (name, text) = (line[..idx].trim(), line[idx + 1..].trim());
+            if name.is_empty() || text.is_empty() {
+                None
+            } else {
+                Some((name.to_owned(), text.to_owned()))
+            }
+        });
+        entries.collect()
+    }
+
+    /// Read the bookmarks from `path`, a missing file has none.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let text = path.as_ref().and_then(|p| fs::read_to_string(p).ok());
+        let entries = text.as_ref().map_or_else(BTreeMap::new, |t| Self::parse(t));
+        Self { path, entries }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    /// The command of a bookmark, if it's one (without the `:`).
+    pub fn command(&self, name: &str) -> Option<&str> {
+        let text = self.get(name)?;
+        match text.find(':') {
+            Some(0) => Some(&text[1..]),
+            _ => None,
+        }
+    }
+
+    /// Add or replace a bookmark, and write the file.
+    /// Names are single words, like the names of aliases.
+    pub fn add(&mut self, name: &str, text: &str) -> io::Result<()> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=') {
+            let message = format!("`{}` is not a bookmark name", name);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        self.entries.insert(name.to_owned(), text.trim().to_owned());
+        self.save()
+    }
+
+    /// Remove a bookmark and write the file, returns if there was one.
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        if self.entries.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save().map(|()| true)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(name, text)| format!("{} = {}", name, text))
+            .collect();
+        save_lines(path, &lines)
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use agda_mode::base::{InteractionPoint, Intervals};
+use agda_mode::resp::Goal;
+
+use crate::diff::{diff, Change};
+use crate::holes::count_holes;
+use crate::render::GoalLine;
+
+/// Hash of the buffer content, used to tell whether the goals are outdated.
+pub fn buffer_hash(buf: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    buf.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A goal, together with the line its hole is on.
+#[derive(Debug, Clone)]
+struct CachedGoal {
+    line: usize,
+    goal: Goal,
+    stale: bool,
+    notes: Vec<String>,
+    /// What Auto found for it, if it was asked.
+    suggestion: Option<String>,
+}
+
+/// Goal types from the last successful load, keyed by the line of the hole.
+/// Edits to a line invalidate the goals on it, others stay usable.
+#[derive(Debug, Clone, Default)]
+pub struct GoalCache {
+    /// Hash of the buffer at the time of the load.
+    hash: u64,
+    goals: Vec<CachedGoal>,
+}
+
+impl GoalCache {
+    /// The goals with a range are on the line it starts on. For the others:
+    /// Agda numbers the holes in the order of appearance,
+    /// so the `n`th goal is on the line of the `n`th hole.
+    pub fn new(
+        buf: &[String],
+        goals: Vec<Goal>,
+        ranges: &HashMap<InteractionPoint, Intervals>,
+    ) -> Self {
+        let lines = buf
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| (0..count_holes(line)).map(move |_| i));
+        let goals = goals
+            .into_iter()
+            .zip(lines.chain(std::iter::repeat(buf.len())))
+            .map(|(goal, counted)| {
+                let start = ranges
+                    .get(&goal.interaction_point)
+                    .and_then(|range| range.first()?.start.line_col());
+                let line = match start {
+                    Some((line, _)) if line as usize <= buf.len() => line as usize - 1,
+                    _ => counted,
+                };
+                (goal, line)
+            })
+            .map(|(goal, line)| CachedGoal {
+                line,
+                goal,
+                stale: false,
+                notes: Vec::new(),
+                suggestion: None,
+            })
+            .collect();
+        Self {
+            hash: buffer_hash(buf),
+            goals,
+        }
+    }
+
+    /// Is the cache loaded from exactly this buffer?
+    pub fn is_fresh(&self, buf: &[String]) -> bool {
+        self.hash == buffer_hash(buf)
+    }
+
+    /// Goals that are not affected by edits since the last load.
+    pub fn goals(&self) -> Vec<GoalLine<'_>> {
+        self.goals
+            .iter()
+            .filter(|g| !g.stale)
+            .map(|g| GoalLine {
+                goal: &g.goal,
+                notes: &g.notes,
+            })
+            .collect()
+    }
+
+    /// Add a remark to the goal, shown in the goal list.
+    pub fn annotate(&mut self, ii: InteractionPoint, note: String) {
+        let goal = self
+            .goals
+            .iter_mut()
+            .find(|g| g.goal.interaction_point == ii);
+        if let Some(g) = goal {
+            g.notes.push(note)
+        }
+    }
+
+    /// Auto can solve the goal with `term`, see [`suggestion`](Self::suggestion).
+    pub fn suggest(&mut self, ii: InteractionPoint, term: String) {
+        let goal = self
+            .goals
+            .iter_mut()
+            .find(|g| g.goal.interaction_point == ii);
+        if let Some(g) = goal {
+            g.notes.push("auto \u{2713}".to_owned());
+            g.suggestion = Some(term);
+        }
+    }
+
+    /// The solution Auto found for a goal that's still there.
+    pub fn suggestion(&self, ii: InteractionPoint) -> Option<&str> {
+        let goal = self.goals.iter().find(|g| g.goal.interaction_point == ii);
+        goal.filter(|g| !g.stale)?.suggestion.as_deref()
+    }
+
+    pub fn stale_count(&self) -> usize {
+        self.goals.iter().filter(|g| g.stale).count()
+    }
+
+    /// The line `line_num` is modified or removed.
+    pub fn invalidate_line(&mut self, line_num: usize) {
+        for g in self.goals.iter_mut().filter(|g| g.line == line_num) {
+            g.stale = true;
+        }
+    }
+
+    /// A line is inserted before `line_num`.
+    pub fn insert_line(&mut self, line_num: usize) {
+        for g in self.goals.iter_mut().filter(|g| g.line >= line_num) {
+            g.line += 1;
+        }
+    }
+
+    /// The line `line_num` is removed.
+    pub fn remove_line(&mut self, line_num: usize) {
+        self.invalidate_line(line_num);
+        for g in self.goals.iter_mut().filter(|g| g.line > line_num) {
+            g.line -= 1;
+        }
+    }
+
+    /// The line of the hole of a goal, if it's still there.
+    pub fn line_of(&self, ii: InteractionPoint) -> Option<usize> {
+        let goal = self.goals.iter().find(|g| g.goal.interaction_point == ii);
+        goal.filter(|g| !g.stale).map(|g| g.line)
+    }
+
+    /// The line of the hole of a goal, and which hole of the line it is.
+    pub fn hole_of(&self, ii: InteractionPoint) -> Option<(usize, usize)> {
+        let line = self.line_of(ii)?;
+        let nth = self
+            .goals
+            .iter()
+            .filter(|g| g.line == line)
+            .position(|g| g.goal.interaction_point == ii)?;
+        Some((line, nth))
+    }
+
+    /// The goal of the `nth` hole on the line, the opposite of [`hole_of`](Self::hole_of).
+    pub fn goal_at(&self, line: usize, nth: usize) -> Option<InteractionPoint> {
+        let goal = self.goals.iter().filter(|g| g.line == line).nth(nth)?;
+        if goal.stale {
+            None
+        } else {
+            Some(goal.goal.interaction_point)
+        }
+    }
+
+    /// `ii` is solved by giving it an expression with the `new` goals in it,
+    /// and `buf` has the expression in place of the hole.
+    pub fn given(&mut self, ii: InteractionPoint, new: Vec<Goal>, buf: &[String]) {
+        let pos = match self
+            .goals
+            .iter()
+            .position(|g| g.goal.interaction_point == ii)
+        {
+            Some(pos) => pos,
+            None => return,
+        };
+        let line = self.goals.remove(pos).line;
+        let new = new.into_iter().map(|goal| CachedGoal {
+            line,
+            goal,
+            stale: false,
+            notes: Vec::new(),
+            suggestion: None,
+        });
+        self.goals.splice(pos..pos, new);
+        // We made the edit ourselves, the other holes on the line are still there.
+        for g in self.goals.iter_mut().filter(|g| g.line == line) {
+            g.stale = false;
+        }
+        self.hash = buffer_hash(buf);
+    }
+
+    /// Goals of the `previous` load that are gone now.
+    /// Agda renumbers the goals on each load, so they are matched by type, in order.
+    pub fn solved_since(&self, previous: &GoalCache) -> Vec<Goal> {
+        fn types(cache: &GoalCache) -> Vec<&str> {
+            cache
+                .goals
+                .iter()
+                .map(|g| g.goal.goal_type.as_str())
+                .collect()
+        }
+        let mut old = previous.goals.iter();
+        let mut solved = Vec::new();
+        for change in diff(&types(previous), &types(self)) {
+            match change {
+                Change::Same(_) => drop(old.next()),
+                Change::Removed(_) => solved.extend(old.next().map(|g| g.goal.clone())),
+                Change::Added(_) => {}
+            }
+        }
+        solved
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use agda_mode::base::Rewrite;
+
+use crate::file_io::SavePolicy;
+use crate::holes::HoleStyle;
+
+/// The default config file, `agda-tac/config` in the user's config directory.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("agda-tac").join("config"))
+}
+
+/// How much to normalise, for each kind of command.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Rewrites {
+    /// `rewrite-goals`, for goal types.
+    pub goals: Rewrite,
+    /// `rewrite-infer`, for inferred types.
+    pub infer: Rewrite,
+    /// `rewrite-context`, for the types in a context.
+    pub context: Rewrite,
+    /// `rewrite-search`, for the types of search results.
+    pub search: Rewrite,
+}
+
+/// User configuration, written as `key = value` lines.
+/// Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// `hole = {!  !}`
+    pub hole_style: HoleStyle,
+    /// `fix-whitespace = true`, fix tabs and such before loading.
+    pub fix_whitespace: bool,
+    /// `rewrite-infer = normalised` and such.
+    pub rewrite: Rewrites,
+    /// `alias g = give`, `g 0 x` is `give 0 x` then.
+    pub aliases: BTreeMap<String, String>,
+    /// `macro qed = solve; reload; goals`, commands run one after another.
+    pub macros: BTreeMap<String, Vec<String>>,
+    /// `ignore-warning = shadows a binding`, warnings of Agda with the text
+    /// are not shown. It may be given several times.
+    pub ignored_warnings: Vec<String>,
+    /// `speculative-auto = true`, try Auto on every goal after a load,
+    /// on a copy of the file, and mark the goals it solves.
+    pub speculative_auto: bool,
+    /// `scope-first = true`, check only the scope of the file before type-checking it,
+    /// so scope errors show up without waiting for the type checker.
+    /// The type-check goes on while the next command is typed.
+    pub scope_first: bool,
+    /// `auto-import = true`, when `give` fails as a name is not in scope,
+    /// add the import of the standard library it suggests and give again.
+    pub auto_import: bool,
+    /// `stdlib = ~/agda-stdlib/src`, the sources of the standard library,
+    /// where the names are looked up beyond the often used ones.
+    pub stdlib: Option<PathBuf>,
+    /// `no-confirm-exit = true`, `quit` even with edits that are not
+    /// saved or checked, like `quit!`.
+    pub no_confirm_exit: bool,
+    /// `save = write` or `save = exit`, when edits are written to the file.
+    /// By default that's right away.
+    pub save: SavePolicy,
+    /// `separate-open = true`, `fmt-imports` keeps `import M` and `open M`
+    /// apart instead of merging them into `open import M`.
+    pub separate_open: bool,
+    /// `verbosity = 1`, only show Agda's running info up to this debug level,
+    /// also without a spinner then, at most once a second.
+    pub verbosity: Option<i32>,
+    /// `running-log = agda.log`, append all of Agda's running info to the file.
+    pub running_log: Option<PathBuf>,
+    /// `agda = ~/.cabal/bin/agda`, like `--agda`, read at start.
+    pub agda: Option<String>,
+    /// `include = src`, like `-i src`, read at start. It may be given several times.
+    pub include: Vec<String>,
+    /// `plain = true`, like `--plain`, read at start.
+    pub plain: bool,
+    /// `no-color = true`, like `--no-color`, read at start.
+    pub no_color: bool,
+}
+
+/// A line may expand to at most this many commands.
+const MAX_EXPANSION: usize = 64;
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("`{}` is not a boolean", value)),
+    }
+}
+
+fn parse_rewrite(value: &str) -> Result<Rewrite, String> {
+    match value.to_lowercase().replace('-', "").as_str() {
+        "asis" => Ok(Rewrite::AsIs),
+        "instantiated" => Ok(Rewrite::Instantiated),
+        "headnormal" => Ok(Rewrite::HeadNormal),
+        "simplified" => Ok(Rewrite::Simplified),
+        "normalised" | "normalized" => Ok(Rewrite::Normalised),
+        _ => Err(format!(
+            "`{}` is not a rewrite, try `as-is`, `simplified` or `normalised`",
+            value
+        )),
+    }
+}
+
+/// Names of aliases and macros are single words.
+fn command_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with(':') {
+        Err(format!("`{}` is not a command name", name))
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", num + 1, msg);
+            let idx = line
+                .find('=')
+                .ok_or_else(|| err("expected `key = value`"))?;
+            let (key, value) = (line[..idx].trim(), line[idx + 1..].trim());
+            config.set(key, value).map_err(|e| err(&e))?;
+        }
+        Ok(config)
+    }
+
+    /// Set one option.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut words = key.splitn(2, ' ');
+        match (words.next(), words.next()) {
+            (Some("alias"), Some(name)) => {
+                let name = command_name(name)?;
+                if value.is_empty() {
+                    return Err(format!("`{}` is an alias of nothing", name));
+                }
+                self.aliases.insert(name, value.to_owned());
+                return Ok(());
+            }
+            (Some("macro"), Some(name)) => {
+                let name = command_name(name)?;
+                let commands: Vec<String> = value
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect();
+                if commands.is_empty() {
+                    return Err(format!("`{}` is a macro of nothing", name));
+                }
+                self.macros.insert(name, commands);
+                return Ok(());
+            }
+            _ => {}
+        }
+        match key {
+            "hole" => {
+                self.hole_style = HoleStyle::parse(value)
+                    .ok_or_else(|| format!("`{}` is not a hole, try `?` or `{{! !}}`", value))?
+            }
+            "fix-whitespace" => self.fix_whitespace = parse_bool(value)?,
+            "rewrite-goals" => self.rewrite.goals = parse_rewrite(value)?,
+            "rewrite-infer" => self.rewrite.infer = parse_rewrite(value)?,
+            "rewrite-context" => self.rewrite.context = parse_rewrite(value)?,
+            "rewrite-search" => self.rewrite.search = parse_rewrite(value)?,
+            "save" => {
+                self.save = SavePolicy::parse(value).ok_or_else(|| {
+                    format!(
+                        "`{}` is not a save policy, try `always`, `write` or `exit`",
+                        value
+                    )
+                })?
+            }
+            "verbosity" => {
+                let level = value
+                    .parse()
+                    .map_err(|_| format!("`{}` is not a level", value))?;
+                self.verbosity = Some(level)
+            }
+            "running-log" if value.is_empty() => self.running_log = None,
+            "running-log" => self.running_log = Some(PathBuf::from(value)),
+            "separate-open" => self.separate_open = parse_bool(value)?,
+            "agda" if value.is_empty() => self.agda = None,
+            "agda" => self.agda = Some(value.to_owned()),
+            "include" if value.is_empty() => return Err("include which directory?".to_owned()),
+            "include" => self.include.push(value.to_owned()),
+            "plain" => self.plain = parse_bool(value)?,
+            "no-color" => self.no_color = parse_bool(value)?,
+            "no-confirm-exit" => self.no_confirm_exit = parse_bool(value)?,
+            "speculative-auto" => self.speculative_auto = parse_bool(value)?,
+            "scope-first" => self.scope_first = parse_bool(value)?,
+            "auto-import" => self.auto_import = parse_bool(value)?,
+            "stdlib" if value.is_empty() => self.stdlib = None,
+            "stdlib" => self.stdlib = Some(PathBuf::from(value)),
+            "ignore-warning" if value.is_empty() => return Err("ignore which warning?".to_owned()),
+            "ignore-warning" => self.ignored_warnings.push(value.to_owned()),
+            _ => return Err(format!("unknown option `{}`", key)),
+        }
+        Ok(())
+    }
+
+    /// Define an alias from the REPL, like `g = give`,
+    /// or a macro if there are several commands, like `qed = solve; reload`.
+    pub fn define(&mut self, definition: &str) -> Result<(), String> {
+        let idx = definition
+            .find('=')
+            .ok_or_else(|| "expected `name = command`".to_owned())?;
+        let (name, value) = (definition[..idx].trim(), definition[idx + 1..].trim());
+        let kind = if value.contains(';') {
+            "macro"
+        } else {
+            "alias"
+        };
+        self.set(&format!("{} {}", kind, name), value)
+    }
+
+    /// The commands a line stands for, after expanding aliases and macros.
+    /// Like in a shell, a name is not expanded again inside its own expansion,
+    /// so `alias goals = goals --all` works and recursion stops.
+    pub fn expand(&self, line: &str) -> Result<Vec<String>, String> {
+        let mut commands = Vec::new();
+        self.expand_into(line, &mut Vec::new(), &mut commands)?;
+        Ok(commands)
+    }
+
+    fn expand_into(
+        &self,
+        line: &str,
+        expanding: &mut Vec<String>,
+        commands: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let line = line.trim();
+        let bare = line.trim_start_matches(':');
+        let (name, args) = match bare.find(char::is_whitespace) {
+            Some(i) => (&bare[..i], &bare[i..]),
+            None => (bare, ""),
+        };
+        let expanded = if expanding.iter().any(|e| e == name) {
+            None
+        } else if let Some(alias) = self.aliases.get(name) {
+            expanding.push(name.to_owned());
+            Some(self.expand_into(&format!("{}{}", alias, args), expanding, commands))
+        } else if let Some(body) = self.macros.get(name) {
+            expanding.push(name.to_owned());
+            let result = body
+                .iter()
+                .try_for_each(|c| self.expand_into(c, expanding, commands));
+            Some(result)
+        } else {
+            None
+        };
+        match expanded {
+            Some(result) => {
+                expanding.pop();
+                result
+            }
+            None if commands.len() >= MAX_EXPANSION => {
+                Err(format!("`{}` expands to too many commands", line))
+            }
+            None => {
+                commands.push(line.to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    /// Read the config from `path`, or from the [default path](self::default_path).
+    /// A missing file is the default config, a broken one is reported.
+    pub fn load(path: Option<&Path>) -> Self {
+        let explicit = path.is_some();
+        let path = match path.map(Path::to_path_buf).or_else(default_path) {
+            Some(path) => path,
+            None => return Default::default(),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                if explicit {
+                    eprintln!("Cannot read config {}: {}", path.display(), e);
+                }
+                return Default::default();
+            }
+        };
+        Self::parse(&text).unwrap_or_else(|e| {
+            eprintln!("Ignoring config {}: {}", path.display(), e);
+            Default::default()
+        })
+    }
+}
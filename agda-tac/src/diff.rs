@@ -0,0 +1,103 @@
+/// A piece of a diff, like a word of an expression.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Change<T> {
+    Same(T),
+    Removed(T),
+    Added(T),
+}
+
+/// Split an expression into words, parentheses and braces are words on their own.
+pub fn words(expr: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in expr.char_indices() {
+        let single = "(){}".contains(c);
+        if c.is_whitespace() || single {
+            if let Some(s) = start.take() {
+                words.push(&expr[s..i]);
+            }
+            if single {
+                words.push(&expr[i..i + c.len_utf8()]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        words.push(&expr[s..]);
+    }
+    words
+}
+
+/// Word-level diff from `old` to `new`.
+pub fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<Change<&'a str>> {
+    diff(&words(old), &words(new))
+}
+
+/// Diff from `old` to `new`, by the longest common subsequence.
+/// Expressions and goal lists are short, so the quadratic table is fine.
+pub fn diff<T: PartialEq + Copy>(old: &[T], new: &[T]) -> Vec<Change<T>> {
+    let (n, m) = (old.len(), new.len());
+    // `lcs[i][j]` is the length of the LCS of `old[i..]` and `new[j..]`.
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut changes = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|&w| Change::Removed(w)));
+    changes.extend(new[j..].iter().map(|&w| Change::Added(w)));
+    changes
+}
+
+/// Show a diff like `git diff --word-diff`, with `[-removed-]` and `{+added+}`.
+pub fn word_diff_line(changes: &[Change<&str>]) -> String {
+    let mut line = String::new();
+    let mut i = 0;
+    while i < changes.len() {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        // Consecutive changes of the same kind are grouped.
+        let (open, close) = match changes[i] {
+            Change::Same(w) => {
+                line.push_str(w);
+                i += 1;
+                continue;
+            }
+            Change::Removed(_) => ("[-", "-]"),
+            Change::Added(_) => ("{+", "+}"),
+        };
+        let group: Vec<&str> = changes[i..]
+            .iter()
+            .take_while(|c| std::mem::discriminant(*c) == std::mem::discriminant(&changes[i]))
+            .map(|c| match c {
+                Change::Same(w) | Change::Removed(w) | Change::Added(w) => *w,
+            })
+            .collect();
+        i += group.len();
+        line.push_str(open);
+        line.push_str(&group.join(" "));
+        line.push_str(close);
+    }
+    line
+}
@@ -1,3 +1,7 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
 use rustyline::completion::Completer;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
@@ -6,7 +10,43 @@ use rustyline::{CompletionType, Config, Context, Editor, Helper};
 use crate::input::UserInput;
 
 pub struct CliEditor {
-    // TODO
+    /// Names to complete after the command, see [`Repl::names`](crate::file_io::Repl::names).
+    pub names: Rc<RefCell<BTreeSet<String>>>,
+}
+
+impl CliEditor {
+    /// Complete the word before `pos`: a command if it's the first word,
+    /// otherwise one of the names.
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line
+            .chars()
+            .enumerate()
+            .find(|(_, i)| !i.is_whitespace())
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        let word = line[..pos].rfind(char::is_whitespace).map(|i| i + 1);
+        match word {
+            Some(word) if word > start => {
+                let prefix = &line[word..pos];
+                let names = self.names.borrow();
+                let names = names.iter().filter(|n| n.starts_with(prefix));
+                (word, names.cloned().collect())
+            }
+            _ => {
+                let subs = if pos > start {
+                    &line[start..pos]
+                } else {
+                    &line[start..]
+                };
+                let base = UserInput::values()
+                    .iter()
+                    .filter(|s| s.starts_with(subs))
+                    .map(|&s| s.to_owned())
+                    .collect();
+                (start, base)
+            }
+        }
+    }
 }
 
 impl Completer for CliEditor {
@@ -18,23 +58,7 @@ impl Completer for CliEditor {
         pos: usize,
         _: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let start = line
-            .chars()
-            .enumerate()
-            .find(|(_, i)| !i.is_whitespace())
-            .map(|(i, _)| i)
-            .unwrap_or(0);
-        let subs = if pos > start {
-            &line[start..pos]
-        } else {
-            &line[start..]
-        };
-        let base = UserInput::values()
-            .iter()
-            .filter(|s| s.starts_with(subs))
-            .map(|&s| s.to_owned())
-            .collect();
-        Ok((start, base))
+        Ok(self.candidates(line, pos))
     }
 }
 
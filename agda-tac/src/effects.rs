@@ -0,0 +1,253 @@
+use crate::annotate::strip;
+use crate::cache::GoalCache;
+use crate::config::Config;
+use crate::file_io::SavePolicy;
+use crate::holes::{collapse_holes, expand_holes, HoleStyle};
+use crate::input::{GoalRange, UserInput};
+use crate::interact::help;
+use crate::lint::{fix, lint};
+use crate::pragmas::{add_flag, is_warning_flag, pragma, warning_flags};
+
+/// Something the driver does for a command, in order.
+#[derive(Debug, Clone)]
+pub enum Effect<'a> {
+    /// Add a line to the end of the buffer.
+    AppendLine(String),
+    /// Replace a line of the buffer.
+    SetLine(usize, String),
+    RemoveLine(usize),
+    /// Insert a line before a line of the buffer.
+    InsertLine(usize, String),
+    /// Write the buffer to the file, if the save policy says so.
+    Sync,
+    /// Write the buffer to the file.
+    Write,
+    /// Load the file and show the goals,
+    /// just the goals if it's unchanged since the last load.
+    Reload,
+    /// Load the file even if it's unchanged, the modules it imports may be edited.
+    ForceReload,
+    /// A command that talks with Agda, left to the driver.
+    Query(UserInput<'a>),
+    /// Show the goals from the cache.
+    ShowGoals(GoalRange),
+    Info(String),
+    Warning(String),
+    Complain(String),
+    /// Run a shell command in the directory of the file.
+    Shell(&'a str),
+    /// Write the buffer to the file that's gone, and load it.
+    Recreate,
+    /// Work on another file with the buffer, and load it.
+    Rebind(&'a str),
+    /// Stop Agda and the REPL.
+    Exit,
+}
+
+/// Decides what a command does from the state of the REPL,
+/// without any terminal or Agda process, so it can be tested.
+pub struct ReplCore<'a> {
+    pub buffer: &'a [String],
+    pub config: &'a Config,
+    pub goals: &'a GoalCache,
+    pub is_plain: bool,
+    /// The buffer has edits that are not written to the file.
+    pub unsaved: bool,
+    /// The file is deleted or moved away since it was read.
+    pub missing: bool,
+    /// The file next to it that has the buffer, where it's moved probably.
+    pub moved_to: Option<String>,
+}
+
+impl<'a> ReplCore<'a> {
+    pub fn effects<'i>(&self, input: UserInput<'i>) -> Vec<Effect<'i>> {
+        use UserInput::*;
+        // Writing the buffer would bring the file back behind the user's back,
+        // and loading it fails, so nothing else but saying what to do.
+        let allowed = matches!(input, Recreate | Rebind(_) | Shell(_) | Help | Exit(true));
+        if self.missing && !allowed {
+            return vec![Effect::Complain(self.missing_message())];
+        }
+        match input {
+            Define(function_name) => {
+                let hole = self.config.hole_style.marker();
+                vec![
+                    Effect::AppendLine(format!("{} : {}", function_name, hole)),
+                    Effect::AppendLine(format!("{} = {}", function_name, hole)),
+                    Effect::Sync,
+                    Effect::Reload,
+                ]
+            }
+            Reload(false) => vec![Effect::Reload],
+            Reload(true) => vec![Effect::ForceReload],
+            Write if self.unsaved => vec![Effect::Write, Effect::Info("Written.".to_owned())],
+            Write => vec![Effect::Info("Nothing to write.".to_owned())],
+            ListGoals(range) => {
+                let mut effects = vec![Effect::ShowGoals(range)];
+                let stale = self.goals.stale_count();
+                if stale > 0 {
+                    let message = format!("{} goal(s) are affected by edits, use `reload`.", stale);
+                    effects.push(Effect::Info(message));
+                } else if !self.goals.is_fresh(self.buffer) {
+                    let message = "The file has changed since the last load, use `reload`.";
+                    effects.push(Effect::Info(message.to_owned()));
+                }
+                effects
+            }
+            ConvertHoles(expand) => {
+                let style = match self.config.hole_style {
+                    HoleStyle::Question => HoleStyle::Braces { padding: 1 },
+                    style => style,
+                };
+                let effects = if expand {
+                    self.rewrite(|l| expand_holes(l, style))
+                } else {
+                    self.rewrite(collapse_holes)
+                };
+                self.or_else(effects, "No holes to convert.")
+            }
+            Lint(false) => {
+                let lints = lint(self.buffer);
+                if lints.is_empty() {
+                    return vec![Effect::Info("No whitespace problems.".to_owned())];
+                }
+                lints
+                    .iter()
+                    .map(|l| Effect::Warning(l.to_string()))
+                    .collect()
+            }
+            Lint(true) => self.or_else(self.rewrite(fix), "No whitespace problems."),
+            StripAnnotations => {
+                // From the bottom, so the line numbers stay right.
+                let lines = self.buffer.iter().enumerate().rev();
+                let edits = lines
+                    .filter_map(|(i, line)| match strip(line) {
+                        None => Some(Effect::RemoveLine(i)),
+                        Some(new) if &new != line => Some(Effect::SetLine(i, new)),
+                        Some(_) => None,
+                    })
+                    .collect();
+                self.or_else(edits, "No annotations.")
+            }
+            Alias("") => {
+                let aliases = self.config.aliases.iter();
+                let aliases = aliases.map(|(name, alias)| format!("alias {} = {}", name, alias));
+                let macros = self.config.macros.iter();
+                let macros =
+                    macros.map(|(name, body)| format!("macro {} = {}", name, body.join("; ")));
+                let lines: Vec<String> = aliases.chain(macros).collect();
+                if lines.is_empty() {
+                    vec![Effect::Info("No aliases.".to_owned())]
+                } else {
+                    vec![Effect::Info(lines.join("\n"))]
+                }
+            }
+            Warnings("") => {
+                let flags = warning_flags(self.buffer);
+                let message = if flags.is_empty() {
+                    "No warning flags, Agda's defaults are used.".to_owned()
+                } else {
+                    format!("Warning flags: {}", flags.join(" "))
+                };
+                vec![Effect::Info(message)]
+            }
+            Warnings(flag) if !is_warning_flag(flag) => {
+                let message = format!(
+                    "Wait, `{}` is not a warning flag, like `-Wno-UnreachableClauses`.",
+                    flag
+                );
+                vec![Effect::Complain(message)]
+            }
+            Warnings(flag) if warning_flags(self.buffer).contains(&flag) => {
+                vec![Effect::Info(format!("`{}` is already there.", flag))]
+            }
+            Warnings(flag) => {
+                let edit = match add_flag(self.buffer, flag) {
+                    Some((line_num, line)) => Effect::SetLine(line_num, line),
+                    None => Effect::InsertLine(0, pragma(flag)),
+                };
+                vec![edit, Effect::Sync, Effect::Reload]
+            }
+            Give(..) | Intro(..) | Trace(..) | Profile | Accept(..) | Solve | Occurrences(..)
+            | Apply(..) | Pick(..) | ExportGoals(..) | Preview(..) | Split(..) | ApplySplit
+            | ProjectGoals | FmtImports | Type(..) | Context(..) | Infer(..) | Contents(..)
+            | Search(..) | DiffExpr(..) | Meta(..) | Alias(..) | BookmarkAdd(..)
+            | BookmarkRemove(..) | BookmarkRun(..) | Bookmarks | Set(..) | Annotate(..)
+            | AnnotateGoal(..) | Last(..) | Stderr => {
+                vec![Effect::Query(input)]
+            }
+            Shell("") => vec![Effect::Complain("Wait, run which command?".to_owned())],
+            Shell(command) if self.unsaved => {
+                let message = "The edits are not written yet, `write` first if it should see them.";
+                vec![Effect::Info(message.to_owned()), Effect::Shell(command)]
+            }
+            Shell(command) => vec![Effect::Shell(command)],
+            Recreate if self.missing => vec![Effect::Recreate],
+            Recreate => vec![Effect::Info(
+                "The file is there, nothing to recreate.".to_owned(),
+            )],
+            Rebind(file) if self.missing => vec![Effect::Rebind(file)],
+            Rebind(_) => vec![Effect::Complain(
+                "Wait, the file is still there, I'm staying with it.".to_owned(),
+            )],
+            // TODO: info for commands.
+            Help => vec![Effect::Info(help(self.is_plain).to_owned())],
+            Unknown(Some(err)) => vec![Effect::Complain(format!("Wait, {}", err))],
+            Unknown(None) => vec![Effect::Complain("Sorry, I don't understand.".to_owned())],
+            // The edits are written on exit then.
+            Exit(false) if self.unsaved && self.config.save == SavePolicy::OnExit => {
+                vec![Effect::Write, Effect::Exit]
+            }
+            Exit(false) if !self.config.no_confirm_exit && self.unsaved => {
+                let message = "Wait, there are unsaved edits, use `quit!` to quit anyway.";
+                vec![Effect::Complain(message.to_owned())]
+            }
+            Exit(false) if !self.config.no_confirm_exit && self.goals.stale_count() > 0 => {
+                let message = "Wait, some edits are not checked yet, \
+                               `reload` or use `quit!` to quit anyway.";
+                vec![Effect::Complain(message.to_owned())]
+            }
+            Exit(_) => vec![Effect::Exit],
+        }
+    }
+
+    fn missing_message(&self) -> String {
+        let moved = match &self.moved_to {
+            Some(file) => format!(
+                " It looks moved to `{}`, `rebind {}` follows it.",
+                file, file
+            ),
+            None => " `rebind <file>` follows it to its new place.".to_owned(),
+        };
+        format!(
+            "Wait, the file is deleted or moved.{} \
+             `recreate` writes it back from the buffer, `quit!` leaves it.",
+            moved
+        )
+    }
+
+    /// Rewrite the lines that `f` changes.
+    fn rewrite(&self, f: impl Fn(&str) -> String) -> Vec<Effect<'static>> {
+        let lines = self.buffer.iter().enumerate();
+        lines
+            .filter_map(|(i, line)| {
+                let new = f(line);
+                if &new == line {
+                    None
+                } else {
+                    Some(Effect::SetLine(i, new))
+                }
+            })
+            .collect()
+    }
+
+    /// Save and reload after the edits, or tell that nothing changed.
+    fn or_else<'i>(&self, mut edits: Vec<Effect<'i>>, nothing: &str) -> Vec<Effect<'i>> {
+        if edits.is_empty() {
+            return vec![Effect::Info(nothing.to_owned())];
+        }
+        edits.push(Effect::Sync);
+        edits.push(Effect::Reload);
+        edits
+    }
+}
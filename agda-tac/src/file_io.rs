@@ -1,21 +1,153 @@
 use agda_mode::agda::ReplState;
-use std::fs::File;
-use std::io::{self, Seek, SeekFrom, Write};
+use agda_mode::base::{InteractionPoint, Intervals};
+use agda_mode::resp::Goal;
+
+use crate::background::Background;
+use crate::bookmarks::Bookmarks;
+use crate::cache::GoalCache;
+use crate::config::Config;
+use crate::effects::ReplCore;
+use crate::holes::has_hole;
+use crate::lock::Lock;
+use crate::render::{Ansi, Renderer};
+use crate::solutions::Conflict;
+use crate::split::PendingSplit;
+use crate::stdlib::NameIndex;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 pub type Monad<T = ()> = io::Result<T>;
 
-pub fn init_module(mut file: String) -> Monad<(File, PathBuf, String)> {
-    // Extracted as variable to make the borrow checker happy
+/// Where the content is written before it replaces `path`.
+/// It's in the same directory, so the rename stays on one file system.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    path.with_file_name(format!(".{}.agda-tac-tmp", name))
+}
+
+/// Write a file via a temporary file and a rename, so a crash or Ctrl-C
+/// in the middle never leaves the user with a truncated file.
+pub fn write_atomically(path: &Path, write: impl FnOnce(&mut File) -> Monad) -> Monad {
+    let tmp = temp_path(path);
+    let written = File::create(&tmp).and_then(|mut f| {
+        write(&mut f)?;
+        f.flush()?;
+        f.sync_all()
+    });
+    match written {
+        Ok(()) => fs::rename(&tmp, path),
+        Err(e) => {
+            // The original file is untouched, don't leave the garbage around.
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// When the edits of the buffer are written to the file.
+/// Until then, the buffer is loaded from a shadow copy.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SavePolicy {
+    /// After every command that edits the buffer.
+    #[default]
+    Always,
+    /// Only on `write`.
+    OnWrite,
+    /// On `write`, and when quitting.
+    OnExit,
+}
+
+impl SavePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "always" => Some(SavePolicy::Always),
+            "write" => Some(SavePolicy::OnWrite),
+            "exit" => Some(SavePolicy::OnExit),
+            _ => None,
+        }
+    }
+}
+
+/// How the lines of a file end, so saving the file doesn't change it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// The style of the first line break, `Lf` if there's none.
+    pub fn detect(text: &str) -> Self {
+        match text.find('\n') {
+            Some(i) if text[..i].ends_with('\r') => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// The lines of a file without their endings, so columns from Agda
+/// and hole rewriting work the same on CRLF files.
+pub fn split_lines(text: &str) -> (Vec<String>, LineEnding) {
+    let lines = text.lines().map(ToOwned::to_owned).collect();
+    (lines, LineEnding::detect(text))
+}
+
+/// Write the lines to `path` atomically, see [`write_atomically`](self::write_atomically).
+pub fn save_lines(path: &Path, lines: &[String]) -> Monad {
+    save_lines_with(path, lines, LineEnding::Lf)
+}
+
+/// Like [`save_lines`](self::save_lines), ending the lines with `ending`.
+pub fn save_lines_with(path: &Path, lines: &[String], ending: LineEnding) -> Monad {
+    write_atomically(path, |f| {
+        for line in lines {
+            f.write_all(line.as_bytes())?;
+            f.write_all(ending.as_str().as_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+/// The file of the module, `.agda` is added if it's not there.
+pub fn module_path(mut file: String) -> PathBuf {
     if !file.ends_with(".agda") {
         file.push_str(".agda")
     }
-    let path = Path::new(&file);
-    if path.exists() {
-        eprintln!("I don't want to work with existing files, sorry.");
-        std::process::exit(1);
+    PathBuf::from(file)
+}
+
+/// Open the module `file` for a session: take its [lock](Lock) first,
+/// so another session on it is told before anything is written,
+/// then create the module if it doesn't exist.
+/// Gives the absolute path, or the process id of the agda-tac having the file.
+pub fn open_module(file: String, force: bool) -> Monad<Result<(PathBuf, Lock), u32>> {
+    let path = module_path(file);
+    let lock = match Lock::acquire(&path, force)? {
+        Ok(lock) => lock,
+        Err(pid) => return Ok(Err(pid)),
+    };
+    if !path.exists() {
+        init_module(&path)?;
     }
-    let mut f = File::create(path)?;
+    Ok(Ok((path.canonicalize()?, lock)))
+}
+
+/// Create the module with just its header.
+pub fn init_module(path: &Path) -> Monad {
     let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
     let mod_name = path
         .file_name()
@@ -26,32 +158,138 @@ pub fn init_module(mut file: String) -> Monad<(File, PathBuf, String)> {
         .expect("File does not have a name");
     // TODO: check if it's a valid module name
     let first_line = format!("module {} where", mod_name);
-    f.write(first_line.as_bytes())?;
-    f.write("\n".as_bytes())?;
-    f.flush()?;
-    Ok((f, path.to_path_buf().canonicalize()?, first_line))
+    save_lines(path, std::slice::from_ref(&first_line))
 }
 
 pub struct Repl {
     pub agda: ReplState,
-    pub file: File,
     pub path: PathBuf,
+    /// Of `path`, so no other agda-tac works on it.
+    pub lock: Option<Lock>,
     file_buf: Vec<String>,
     last_line: usize,
     pub is_plain: bool,
+    pub config: Config,
+    pub render: Box<dyn Renderer>,
+    pub bell_on_done: bool,
+    pub bell_on_fail: bool,
+    /// Goals from the last successful load.
+    pub goals: GoalCache,
+    /// The line endings of the file when it was read.
+    pub line_ending: LineEnding,
+    /// Show a spinner while Agda is loading.
+    pub spinner: bool,
+    /// Colors in the prompt, in the rich mode.
+    pub color: bool,
+    /// How many errors the last load had, for the prompt.
+    pub errors: usize,
+    /// A load is over, so the prompt can tell how it went.
+    pub has_loaded: bool,
+    /// A type-check is sent to Agda, but its goals are not read yet.
+    pub checking: bool,
+    /// The buffer is edited since it was last written or read.
+    unsaved: bool,
+    /// What the last successful load checked, see [`load_hash`](Self::load_hash).
+    loaded: Option<u64>,
+    /// The last `split`, if it's not applied yet.
+    pub split: Option<PendingSplit>,
+    /// Conflicting solutions of the last `apply`, the first one is picked next.
+    pub conflicts: Vec<Conflict<InteractionPoint>>,
+    pub bookmarks: Bookmarks,
+    /// Names from searches and module contents, completed in the editor.
+    pub names: Rc<RefCell<BTreeSet<String>>>,
+    /// Where the names of the standard library are, made on the first scope error.
+    pub stdlib: Option<NameIndex>,
+    /// For loading other files without changing what `agda` has loaded.
+    pub background: Background,
 }
 
 impl Repl {
-    pub fn new(agda: ReplState, file: File, path: PathBuf) -> Self {
+    pub fn new(agda: ReplState, path: PathBuf) -> Self {
         let file_buf = Vec::with_capacity(5);
         Self {
             agda,
-            file,
             path,
+            lock: None,
             file_buf,
             last_line: 0,
             is_plain: false,
+            config: Default::default(),
+            render: Box::new(Ansi),
+            bell_on_done: false,
+            bell_on_fail: false,
+            goals: Default::default(),
+            line_ending: Default::default(),
+            spinner: false,
+            color: false,
+            errors: 0,
+            has_loaded: false,
+            checking: false,
+            unsaved: false,
+            loaded: None,
+            split: None,
+            conflicts: Vec::new(),
+            bookmarks: Default::default(),
+            names: Default::default(),
+            stdlib: None,
+            background: Background::new("agda".to_owned(), Default::default()),
+        }
+    }
+
+    /// The state the commands are decided on.
+    pub fn core(&self) -> ReplCore<'_> {
+        let missing = !self.path.exists();
+        let moved_to = if missing {
+            let name = |p: PathBuf| p.file_name()?.to_str().map(ToOwned::to_owned);
+            self.moved_to().and_then(name)
+        } else {
+            None
+        };
+        ReplCore {
+            buffer: &self.file_buf,
+            config: &self.config,
+            goals: &self.goals,
+            is_plain: self.is_plain,
+            unsaved: self.unsaved,
+            missing,
+            moved_to,
+        }
+    }
+
+    /// The `.agda` file next to where the file was with the same lines as the buffer,
+    /// if it has no edits. That's where the file is moved, very likely.
+    pub fn moved_to(&self) -> Option<PathBuf> {
+        if self.unsaved {
+            return None;
+        }
+        let entries = fs::read_dir(self.path.parent()?).ok()?;
+        let mut paths: Vec<_> = entries.filter_map(|e| Some(e.ok()?.path())).collect();
+        paths.sort();
+        paths.into_iter().find(|path| {
+            let text = match path.extension() {
+                Some(e) if e == "agda" => fs::read_to_string(path),
+                _ => return false,
+            };
+            matches!(text, Ok(text) if split_lines(&text).0 == self.file_buf)
+        })
+    }
+
+    /// The buffer is about `path` from now on, like after the file is moved there.
+    /// Its edits stay, otherwise it's read from the new file.
+    /// The [lock](Lock) of `path` is taken, or the process id of the agda-tac having it.
+    pub fn rebind(&mut self, path: PathBuf) -> Monad<Result<(), u32>> {
+        let lock = match Lock::acquire(&path, false)? {
+            Ok(lock) => lock,
+            Err(pid) => return Ok(Err(pid)),
+        };
+        self.lock = Some(lock);
+        self.agda.set_file(path.to_string_lossy().into_owned());
+        self.path = path;
+        self.set_loaded(false);
+        if !self.unsaved {
+            self.read_buffer()?;
         }
+        Ok(Ok(()))
     }
 
     pub fn any_goals_in_buffer(&self) -> bool {
@@ -59,79 +297,160 @@ impl Repl {
     }
 
     pub fn append_line_buffer(&mut self, line: String) {
-        // TODO: support {!!}?
-        if self.any_goals_in_buffer() && !line.contains("?") {
+        if self.any_goals_in_buffer() && !has_hole(&line) {
             self.last_line += 1;
         }
+        self.unsaved = true;
         self.file_buf.push(line)
     }
 
-    pub fn pop_line_buffer(&mut self) -> Option<String> {
-        if self.any_goals_in_buffer() && self.last_line > 0 {
-            self.last_line -= 1;
-        }
-        self.file_buf.pop()
+    /// Remember the goals of a successful load of the current buffer,
+    /// with the ranges of their holes if Agda sent them.
+    pub fn cache_goals(&mut self, goals: Vec<Goal>, ranges: &HashMap<InteractionPoint, Intervals>) {
+        self.goals = GoalCache::new(&self.file_buf, goals, ranges)
+    }
+
+    /// The goal is solved, with the `new` goals in its place.
+    pub fn cache_given(&mut self, ii: InteractionPoint, new: Vec<Goal>) {
+        self.goals.given(ii, new, &self.file_buf)
     }
 
     pub fn set_line_buffer(&mut self, line_num: usize, line: String) {
-        if line.contains("?") {
+        if has_hole(&line) {
             self.last_line = line_num.min(self.last_line);
         }
+        self.goals.invalidate_line(line_num);
+        self.unsaved = true;
         self.file_buf[line_num] = line
     }
 
     pub fn insert_line_buffer(&mut self, line_num: usize, line: String) {
-        if line.contains("?") {
+        if has_hole(&line) {
             self.last_line = line_num.min(self.last_line);
         }
+        self.goals.insert_line(line_num);
+        self.unsaved = true;
         self.file_buf.insert(line_num, line)
     }
 
+    pub fn remove_line_buffer(&mut self, line_num: usize) -> String {
+        if line_num < self.last_line {
+            self.last_line -= 1;
+        }
+        self.goals.remove_line(line_num);
+        self.unsaved = true;
+        self.file_buf.remove(line_num)
+    }
+
     pub fn get_line_buffer(&mut self, line_num: usize) -> &String {
         &self.file_buf[line_num]
     }
 
-    fn flush_file(&mut self) -> Monad {
-        self.file.flush()
+    pub fn buffer(&self) -> &[String] {
+        &self.file_buf
+    }
+
+    /// The buffer as it would be saved.
+    pub fn contents(&self) -> String {
+        let ending = self.line_ending.as_str();
+        self.file_buf
+            .iter()
+            .map(|line| line.clone() + ending)
+            .collect()
+    }
+
+    /// Hash of what a load checks: the buffer if it has unsaved edits, the file otherwise
+    /// (which an editor may have written). `None` if the file can't be read.
+    pub fn load_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        if self.unsaved {
+            self.contents().as_bytes().hash(&mut hasher);
+        } else {
+            fs::read(&self.path).ok()?.hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+
+    /// Remember if Agda checked what a load checks now, or forget it,
+    /// when Agda loads something else.
+    pub fn set_loaded(&mut self, loaded: bool) {
+        self.loaded = if loaded { self.load_hash() } else { None };
+    }
+
+    /// Would a load check the same as the last successful one.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.is_some() && self.loaded == self.load_hash()
+    }
+
+    /// Rewrite every line of the buffer with `f`, returns if anything changed.
+    /// The file is not synced.
+    pub fn rewrite_lines(&mut self, f: impl Fn(&str) -> String) -> bool {
+        let mut changed = false;
+        for line_num in 0..self.file_buf.len() {
+            let line = f(&self.file_buf[line_num]);
+            if line != self.file_buf[line_num] {
+                self.set_line_buffer(line_num, line);
+                changed = true;
+            }
+        }
+        changed
     }
 
-    pub fn append_line(&mut self, line: String) -> Monad {
-        Self::append_line_to_file(&mut self.file, &line)?;
-        self.flush_file()?;
-        self.append_line_buffer(line);
+    /// Complete these names in the editor from now on.
+    pub fn remember_names(&mut self, names: impl IntoIterator<Item = String>) {
+        self.names.borrow_mut().extend(names)
+    }
+
+    /// Does the buffer have edits that are not in the file.
+    pub fn is_unsaved(&self) -> bool {
+        self.unsaved
+    }
+
+    /// Read the buffer back from the file, after someone else edited it.
+    pub fn read_buffer(&mut self) -> Monad {
+        let text = fs::read_to_string(&self.path)?;
+        let (lines, line_ending) = split_lines(&text);
+        self.file_buf = lines;
+        self.line_ending = line_ending;
+        self.unsaved = false;
+        self.last_line = self.file_buf.iter().take_while(|l| !has_hole(l)).count();
         Ok(())
     }
 
-    fn append_line_to_file(file: &mut File, line: &str) -> Monad {
-        file.write(line.as_bytes())?;
-        file.write("\n".as_bytes())?;
+    /// The buffer is done with a command, write it to the file
+    /// if the [save policy](SavePolicy) says so.
+    pub fn sync_buffer(&mut self) -> Monad {
+        if self.config.save == SavePolicy::Always {
+            return self.write_buffer();
+        }
+        self.recalculate_last_line();
         Ok(())
     }
 
-    fn clear_file(&mut self) -> Monad {
-        let file = &mut self.file;
-        file.sync_all()?;
-        file.set_len(0)?;
-        file.seek(SeekFrom::Start(0))?;
+    /// Write the buffer to the file, whatever the save policy is.
+    pub fn write_buffer(&mut self) -> Monad {
+        save_lines_with(&self.path, &self.file_buf, self.line_ending)?;
+        self.unsaved = false;
+        self.recalculate_last_line();
         Ok(())
     }
 
-    pub fn sync_buffer(&mut self) -> Monad {
-        self.clear_file()?;
+    /// Write the buffer if the save policy waits for the end of the session.
+    pub fn write_on_exit(&mut self) -> Monad {
+        if self.unsaved && self.config.save == SavePolicy::OnExit {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn recalculate_last_line(&mut self) {
         let mut recalculated_last_line = 0usize;
-        let mut found_goal = false;
         for line in self.file_buf.iter() {
-            Self::append_line_to_file(&mut self.file, &line)?;
-            if !found_goal {
-                if line.contains("?") {
-                    found_goal = true;
-                } else {
-                    recalculated_last_line += 1;
-                }
+            if has_hole(line) {
+                break;
             }
+            recalculated_last_line += 1;
         }
-        self.flush_file()?;
         self.last_line = recalculated_last_line;
-        Ok(())
     }
 }
@@ -0,0 +1,165 @@
+/// How new holes are written into the file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum HoleStyle {
+    /// `?`
+    #[default]
+    Question,
+    /// `{!!}` with `padding` spaces inside.
+    Braces { padding: usize },
+}
+
+impl HoleStyle {
+    /// Parse things like `?`, `{!!}` or `{!   !}`.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s == "?" {
+            return Some(HoleStyle::Question);
+        }
+        if !s.starts_with("{!") || !s.ends_with("!}") || s.len() < 4 {
+            return None;
+        }
+        let inside = &s[2..s.len() - 2];
+        if inside.chars().all(|c| c == ' ') {
+            Some(HoleStyle::Braces {
+                padding: inside.len(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn marker(self) -> String {
+        match self {
+            HoleStyle::Question => "?".to_owned(),
+            HoleStyle::Braces { padding } => format!("{{!{}!}}", " ".repeat(padding)),
+        }
+    }
+}
+
+fn is_delimiter(c: Option<char>) -> bool {
+    match c {
+        None => true,
+        Some(c) => c.is_whitespace() || "(){};".contains(c),
+    }
+}
+
+/// Byte ranges of the `{! !}` holes in a line, nested ones are part of the outer hole.
+/// An unclosed hole goes to the end of the line.
+fn brace_holes(line: &str) -> Vec<(usize, usize)> {
+    let mut holes = Vec::new();
+    let mut i = 0;
+    while let Some(start) = line[i..].find("{!").map(|s| s + i) {
+        let mut depth = 0;
+        let mut j = start;
+        let end = loop {
+            match (line[j..].find("{!"), line[j..].find("!}")) {
+                (Some(open), Some(close)) if open < close => {
+                    depth += 1;
+                    j += open + 2;
+                }
+                (_, Some(close)) => {
+                    depth -= 1;
+                    j += close + 2;
+                    if depth == 0 {
+                        break j;
+                    }
+                }
+                (_, None) => break line.len(),
+            }
+        };
+        holes.push((start, end));
+        i = end;
+    }
+    holes
+}
+
+/// Byte offsets of the `?` holes in a line,
+/// ignoring line comments and the text in `{! !}` holes.
+fn question_holes(line: &str) -> Vec<usize> {
+    let code = line.find("--").map_or(line, |i| &line[..i]);
+    let braces = brace_holes(line);
+    code.char_indices()
+        .filter(|&(i, c)| {
+            c == '?'
+                && is_delimiter(code[..i].chars().last())
+                && is_delimiter(code[i + 1..].chars().next())
+                && !braces.iter().any(|&(start, end)| start < i && i < end)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Byte ranges of the holes (of either style) in the line, in order.
+pub fn hole_spans(line: &str) -> Vec<(usize, usize)> {
+    let mut spans = brace_holes(line);
+    spans.extend(question_holes(line).into_iter().map(|i| (i, i + 1)));
+    spans.sort();
+    spans
+}
+
+/// How many holes (of either style) are there in the line.
+pub fn count_holes(line: &str) -> usize {
+    hole_spans(line).len()
+}
+
+/// Which hole of the line is at the 1-based (character) column.
+pub fn hole_at(line: &str, column: usize) -> Option<usize> {
+    let offset = line
+        .char_indices()
+        .nth(column.checked_sub(1)?)
+        .map(|(i, _)| i)?;
+    hole_spans(line)
+        .iter()
+        .position(|&(start, end)| start <= offset && offset < end)
+}
+
+/// The 1-based (character) column of the `n`th hole of the line.
+pub fn hole_column(line: &str, n: usize) -> Option<usize> {
+    let (start, _) = *hole_spans(line).get(n)?;
+    Some(line[..start].chars().count() + 1)
+}
+
+/// Put `text` in place of the `n`th hole of the line.
+pub fn replace_hole(line: &str, n: usize, text: &str) -> Option<String> {
+    let (start, end) = *hole_spans(line).get(n)?;
+    Some(format!("{}{}{}", &line[..start], text, &line[end..]))
+}
+
+pub fn has_hole(line: &str) -> bool {
+    count_holes(line) > 0
+}
+
+/// Rewrite all `?` holes in the line with `style`.
+pub fn expand_holes(line: &str, style: HoleStyle) -> String {
+    let marker = style.marker();
+    let mut result = String::with_capacity(line.len());
+    let mut last = 0;
+    for i in question_holes(line) {
+        result.push_str(&line[last..i]);
+        result.push_str(&marker);
+        last = i + 1;
+    }
+    result.push_str(&line[last..]);
+    result
+}
+
+/// Rewrite all empty `{! !}` holes in the line as `?`.
+pub fn collapse_holes(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find("{!") {
+        let after = &rest[start + 2..];
+        let end = match after.find("!}") {
+            Some(end) => end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        if after[..end].trim().is_empty() {
+            result.push('?');
+        } else {
+            result.push_str(&rest[start..start + end + 4]);
+        }
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
@@ -0,0 +1,71 @@
+use crate::project::imported_module;
+
+/// `open M` and the rest of the line, like `using (x)`.
+fn opened_module(line: &str) -> Option<(&str, &str)> {
+    let mut words = line.splitn(3, ' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some("open"), Some(module), rest) if module != "import" => {
+            Some((module, rest.unwrap_or("").trim()))
+        }
+        _ => None,
+    }
+}
+
+fn is_import(line: &str) -> bool {
+    imported_module(line).is_some() || opened_module(line).is_some()
+}
+
+/// The first run of `import` and `open` lines, as `start..end`.
+/// Statements over several lines are not supported, so it's `None` with them.
+pub fn import_block(lines: &[String]) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|l| is_import(l))?;
+    let len = lines[start..].iter().take_while(|l| is_import(l)).count();
+    let block = &lines[start..start + len];
+    let balanced = |l: &String| l.matches('(').count() == l.matches(')').count();
+    // An indented line after the block continues the last statement.
+    let continued = match lines.get(start + len) {
+        Some(l) => l.starts_with(char::is_whitespace) && !l.trim().is_empty(),
+        None => false,
+    };
+    if block.iter().all(balanced) && !continued {
+        Some((start, start + len))
+    } else {
+        None
+    }
+}
+
+/// Sort the imports by module name and drop the duplicates.
+/// `import M` followed by `open M` is merged into `open import M`, unless `separate_open`.
+/// The other `open`s stay after the imports, in their order,
+/// since they may open an alias of an import.
+pub fn format_imports(block: &[String], separate_open: bool) -> Vec<String> {
+    let block: Vec<String> = block
+        .iter()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    let mut imports: Vec<String> = Vec::new();
+    let mut opens: Vec<String> = Vec::new();
+    for line in &block {
+        match opened_module(line) {
+            Some((module, rest)) if !separate_open => {
+                let plain = format!("import {}", module);
+                match imports.iter().position(|l| *l == plain) {
+                    Some(i) => imports[i] = format!("open {} {}", plain, rest).trim().to_owned(),
+                    None => opens.push(line.clone()),
+                }
+            }
+            Some(_) => opens.push(line.clone()),
+            None => imports.push(line.clone()),
+        }
+    }
+    imports.sort_by(|a, b| (imported_module(a), a).cmp(&(imported_module(b), b)));
+    imports.dedup();
+    let mut seen = Vec::new();
+    opens.retain(|l| {
+        let new = !seen.contains(l);
+        seen.push(l.clone());
+        new
+    });
+    imports.extend(opens);
+    imports
+}
@@ -1,17 +1,151 @@
-use agda_mode::base::InteractionPoint;
+use agda_mode::base::{InteractionPoint, UseForce};
 
 /// Parsed user input.
 #[derive(Debug, Clone, Copy)]
 pub enum UserInput<'a> {
     Define(&'a str),
-    Give(InteractionPoint, &'a str),
-    Reload,
+    /// `WithForce` for skipping the termination and positivity checks, like `give! 0 f x`.
+    Give(InteractionPoint, &'a str, UseForce),
+    /// `true` for loading even if nothing changed since the last load.
+    Reload(bool),
+    /// Write the buffer to the file, whatever the save policy is.
+    Write,
+    /// Goal type, `true` for comparing the normalised type with the type as is.
+    Type(InteractionPoint, bool),
+    /// The context of a goal, with the module parameters on their own.
+    /// `true` for only the entries the goal type needs.
+    Context(InteractionPoint, bool),
+    /// Normalise two expressions and compare them.
+    DiffExpr(&'a str, &'a str),
+    /// Check the expression against the goal, showing the type checker's debug output.
+    Trace(InteractionPoint, &'a str),
+    /// Load the file with Agda's profile on, showing the slowest definitions.
+    Profile,
+    /// Let Agda introduce a lambda or a constructor in the goal.
+    Intro(InteractionPoint),
+    /// Give the solution Auto found for the goal in the background.
+    Accept(InteractionPoint),
+    /// Let Agda solve the goals it can, and give the solutions to their holes.
+    Solve,
+    /// Give the terms of a solutions file to their holes.
+    Apply(&'a str),
+    /// Give the term with this number (from 1) to the hole of the next conflict of `apply`.
+    Pick(usize),
+    /// Where a name is used in the file, as of the last load.
+    Occurrences(&'a str),
+    /// Add a warning flag to the `OPTIONS` pragmas, or list them.
+    Warnings(&'a str),
+    /// Check the file with the line added, without writing it.
+    Preview(&'a str),
+    /// Case split on a variable, `true` for writing the clauses without asking.
+    Split(InteractionPoint, &'a str, bool),
+    /// Write the clauses of the last `split`.
+    ApplySplit,
+    /// Write the goals with their positions and contexts to a JSON file.
+    ExportGoals(&'a str),
+    /// Define an alias or a macro for this session, like `g = give`.
+    /// Without a definition, list them.
+    Alias(&'a str),
+    /// Change an option of the config for this session, like `set verbosity 1`.
+    Set(&'a str, &'a str),
+    /// The type of an expression, in the top level scope.
+    Infer(&'a str),
+    /// The names in a module and their types.
+    Contents(&'a str),
+    /// Names in scope whose types mention all of the given names.
+    Search(&'a str),
+    /// The type of a hidden meta, like `_12`, and the constraints on it.
+    Meta(&'a str),
+    /// Keep an expression (or a `:command`) across sessions under a name.
+    BookmarkAdd(&'a str, &'a str),
+    BookmarkRemove(&'a str),
+    /// Show the normal form of a bookmarked expression.
+    BookmarkRun(&'a str),
+    /// List the bookmarks.
+    Bookmarks,
+    /// Write the value of the expression into the file as a comment.
+    Annotate(&'a str),
+    /// Write the type of the goal as a comment on its line.
+    AnnotateGoal(InteractionPoint),
+    StripAnnotations,
+    /// Sort and merge the imports, and check that the file still loads.
+    FmtImports,
+    ListGoals(GoalRange),
+    /// The goals of the file and of the files of the project it imports.
+    ProjectGoals,
+    Stderr,
+    /// Show the last display info of some kind again.
+    Last(&'a str),
+    /// `true` for `{! !}`, `false` for `?`.
+    ConvertHoles(bool),
+    /// `true` for fixing the problems.
+    Lint(bool),
     Help,
-    Exit,
+    /// Run a shell command in the directory of the file, like `:! git diff`.
+    Shell(&'a str),
+    /// Write the buffer to the file again, after it's deleted.
+    Recreate,
+    /// Work on the file at its new place, relative to the directory of the file.
+    Rebind(&'a str),
+    /// `true` for quitting even with edits that are not saved or checked.
+    Exit(bool),
     Unknown(Option<&'a str>),
 }
 
-static VALUES: &[&str] = &["help", "define", "fill", "give", "reload", "exit", "quit"];
+/// Which part of the goal list to show.
+#[derive(Debug, Clone, Copy)]
+pub enum GoalRange {
+    /// The first few goals, see [`GOAL_LIST_LIMIT`](self::GOAL_LIST_LIMIT).
+    First,
+    All,
+    /// Goals whose interaction points are in `start..end`.
+    Between(InteractionPoint, InteractionPoint),
+}
+
+/// How many goals are shown before the list is cut.
+pub const GOAL_LIST_LIMIT: usize = 20;
+
+static VALUES: &[&str] = &[
+    "help",
+    "define",
+    "fill",
+    "give",
+    "type",
+    "context",
+    "infer",
+    "search",
+    "contents",
+    "diff-expr",
+    "annotate",
+    "annotate-goal",
+    "meta",
+    "bookmark",
+    "alias",
+    "set",
+    "apply",
+    "accept",
+    "solve",
+    "intro",
+    "trace",
+    "profile",
+    "export-goals",
+    "preview",
+    "split",
+    "warnings",
+    "occurrences",
+    "reload",
+    "recreate",
+    "rebind",
+    "write",
+    "goals",
+    "holes",
+    "lint",
+    "fmt-imports",
+    "last",
+    "stderr",
+    "exit",
+    "quit",
+];
 
 impl<'a> UserInput<'a> {
     pub fn values() -> &'static [&'static str] {
@@ -21,28 +155,285 @@ impl<'a> UserInput<'a> {
 
 impl<'a> From<&'a str> for UserInput<'a> {
     fn from(line: &'a str) -> Self {
+        // Commands may be written as `:reload` as well.
+        let line = line.trim_start_matches(':');
         if line == "help" {
             UserInput::Help
+        } else if let Some(command) = line.strip_prefix('!') {
+            UserInput::Shell(command.trim())
         } else if line.starts_with("define") {
             UserInput::Define(line.trim_start_matches("define").trim_start())
         } else if line.starts_with("fill") || line.starts_with("give") {
-            let s = line
-                .trim_start_matches("fill")
-                .trim_start_matches("give")
-                .trim_start();
+            let s = line.trim_start_matches("fill").trim_start_matches("give");
+            let force = if s.starts_with('!') {
+                UseForce::WithForce
+            } else {
+                UseForce::WithoutForce
+            };
+            let s = s.trim_start_matches('!').trim_start();
             match s.find(" ") {
                 None => UserInput::Unknown(Some("please specify a goal.")),
                 Some(idx) => match s[..idx].trim().parse::<InteractionPoint>() {
-                    Ok(i) => UserInput::Give(i, s[idx..].trim()),
+                    Ok(i) => UserInput::Give(i, s[idx..].trim(), force),
                     Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
                 },
             }
+        } else if line.starts_with("type") {
+            let s = line.trim_start_matches("type").trim();
+            let (s, both) = if s.ends_with("--both") {
+                (s.trim_end_matches("--both").trim(), true)
+            } else {
+                (s, false)
+            };
+            match s.parse::<InteractionPoint>() {
+                Ok(i) => UserInput::Type(i, both),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("context") {
+            let s = line.trim_start_matches("context").trim();
+            let (s, relevant) = if s.ends_with("--relevant") {
+                (s.trim_end_matches("--relevant").trim(), true)
+            } else {
+                (s, false)
+            };
+            match s.parse() {
+                Ok(i) => UserInput::Context(i, relevant),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("diff-expr") {
+            let s = line.trim_start_matches("diff-expr").trim();
+            let (e1, rest) = split_expr(s).unwrap_or_default();
+            match split_expr(rest) {
+                Some((e2, "")) if !e1.is_empty() && !e2.is_empty() => UserInput::DiffExpr(e1, e2),
+                _ => UserInput::Unknown(Some("please give two expressions, like `(f x) (g y)`.")),
+            }
+        } else if line.starts_with("trace") {
+            let s = line.trim_start_matches("trace").trim_start();
+            match s.find(' ') {
+                None => UserInput::Unknown(Some("trace what? Try `trace 0 suc n`.")),
+                Some(idx) => match s[..idx].parse() {
+                    Ok(i) => UserInput::Trace(i, s[idx..].trim()),
+                    Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+                },
+            }
+        } else if line.starts_with("intro") {
+            match line.trim_start_matches("intro").trim().parse() {
+                Ok(i) => UserInput::Intro(i),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("accept") {
+            match line.trim_start_matches("accept").trim().parse() {
+                Ok(i) => UserInput::Accept(i),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("apply --pick") {
+            match line.trim_start_matches("apply --pick").trim().parse() {
+                Ok(n) => UserInput::Pick(n),
+                Err(_) => UserInput::Unknown(Some("pick which term? Try `apply --pick 1`.")),
+            }
+        } else if line.starts_with("apply") {
+            match line.trim_start_matches("apply").trim() {
+                "" => UserInput::Unknown(Some("apply which file? Try `apply solutions.json`.")),
+                path => UserInput::Apply(path),
+            }
+        } else if line.starts_with("occurrences") {
+            let name = line.trim_start_matches("occurrences").trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                UserInput::Unknown(Some("occurrences of which name? Try `occurrences foo`."))
+            } else {
+                UserInput::Occurrences(name)
+            }
+        } else if line.starts_with("warnings") {
+            UserInput::Warnings(line.trim_start_matches("warnings").trim())
+        } else if line.starts_with("preview") {
+            match line.trim_start_matches("preview").trim() {
+                "" => UserInput::Unknown(Some("preview what? Try `preview f = zero`.")),
+                code => UserInput::Preview(code),
+            }
+        } else if line == "split --apply" {
+            UserInput::ApplySplit
+        } else if line.starts_with("split") {
+            let s = line.trim_start_matches("split").trim();
+            let (s, auto) = if s.ends_with("--auto") {
+                (s.trim_end_matches("--auto").trim(), true)
+            } else {
+                (s, false)
+            };
+            // Without a variable, Agda introduces the missing patterns.
+            let (goal, variable) = match s.find(' ') {
+                Some(idx) => (&s[..idx], s[idx..].trim()),
+                None => (s, ""),
+            };
+            match goal.parse() {
+                Ok(i) => UserInput::Split(i, variable, auto),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("export-goals") {
+            match line.trim_start_matches("export-goals").trim() {
+                "" => {
+                    UserInput::Unknown(Some("export to which file? Try `export-goals goals.json`."))
+                }
+                path => UserInput::ExportGoals(path),
+            }
+        } else if line.starts_with("set ") {
+            let s = line.trim_start_matches("set").trim();
+            let idx = s.find(|c: char| c == '=' || c.is_whitespace());
+            match idx {
+                Some(idx) => {
+                    let value = s[idx..].trim().trim_start_matches('=');
+                    UserInput::Set(&s[..idx], value.trim())
+                }
+                None => UserInput::Unknown(Some("set what? Try `set verbosity 1`.")),
+            }
+        } else if line.starts_with("alias") {
+            UserInput::Alias(line.trim_start_matches("alias").trim())
+        } else if line.starts_with("infer") {
+            match line.trim_start_matches("infer").trim() {
+                "" => UserInput::Unknown(Some("infer what? Try `infer 1 + 1`.")),
+                expr => UserInput::Infer(expr),
+            }
+        } else if line.starts_with("contents") {
+            match line.trim_start_matches("contents").trim() {
+                "" => {
+                    UserInput::Unknown(Some("contents of which module? Try `contents Data.Nat`."))
+                }
+                module => UserInput::Contents(module),
+            }
+        } else if line.starts_with("search") {
+            match line.trim_start_matches("search").trim() {
+                "" => UserInput::Unknown(Some("search for what? Try `search Nat`.")),
+                names => UserInput::Search(names),
+            }
+        } else if line.starts_with("meta") {
+            let meta = line.trim_start_matches("meta").trim();
+            if meta.len() > 1
+                && meta.starts_with('_')
+                && meta[1..].chars().all(|c| c.is_ascii_digit())
+            {
+                UserInput::Meta(meta)
+            } else {
+                UserInput::Unknown(Some("Metas look like `_12`."))
+            }
+        } else if line.starts_with("bookmark") {
+            parse_bookmark(line.trim_start_matches("bookmark").trim())
+        } else if line.starts_with("annotate-goal") {
+            match line.trim_start_matches("annotate-goal").trim().parse() {
+                Ok(i) => UserInput::AnnotateGoal(i),
+                Err(_) => UserInput::Unknown(Some("I cannot parse the goal number.")),
+            }
+        } else if line.starts_with("annotate") {
+            match line.trim_start_matches("annotate").trim() {
+                "" => UserInput::Unknown(Some("annotate what? Try `annotate 1 + 1`.")),
+                "--strip" => UserInput::StripAnnotations,
+                expr => UserInput::Annotate(expr),
+            }
+        } else if line == "profile" {
+            UserInput::Profile
+        } else if line == "solve" {
+            UserInput::Solve
+        } else if line == "recreate" {
+            UserInput::Recreate
+        } else if line.starts_with("rebind") {
+            match line.trim_start_matches("rebind").trim() {
+                "" => UserInput::Unknown(Some("rebind to which file? Try `rebind B.agda`.")),
+                file => UserInput::Rebind(file),
+            }
         } else if line == "reload" {
-            UserInput::Reload
+            UserInput::Reload(false)
+        } else if line == "reload!" {
+            UserInput::Reload(true)
+        } else if line == "write" {
+            UserInput::Write
+        } else if line.starts_with("goals") {
+            let s = line.trim_start_matches("goals").trim();
+            if s.is_empty() {
+                UserInput::ListGoals(GoalRange::First)
+            } else if s == "--all" {
+                UserInput::ListGoals(GoalRange::All)
+            } else if s == "--all-files" {
+                UserInput::ProjectGoals
+            } else {
+                parse_goal_range(s)
+                    .map(UserInput::ListGoals)
+                    .unwrap_or(UserInput::Unknown(Some("I cannot parse the goal range.")))
+            }
+        } else if line.starts_with("holes") {
+            match line.trim_start_matches("holes").trim() {
+                "--expand" => UserInput::ConvertHoles(true),
+                "--collapse" => UserInput::ConvertHoles(false),
+                _ => UserInput::Unknown(Some("use `holes --expand` or `holes --collapse`.")),
+            }
+        } else if line == "fmt-imports" {
+            UserInput::FmtImports
+        } else if line == "lint" {
+            UserInput::Lint(false)
+        } else if line == "lint --fix" {
+            UserInput::Lint(true)
+        } else if line.starts_with("last") {
+            match line.trim_start_matches("last").trim() {
+                "" => UserInput::Unknown(Some("last what? Try `last error`.")),
+                kind => UserInput::Last(kind),
+            }
+        } else if line == "stderr" {
+            UserInput::Stderr
         } else if line == "exit" || line == "quit" {
-            UserInput::Exit
+            UserInput::Exit(false)
+        } else if line == "exit!" || line == "quit!" {
+            UserInput::Exit(true)
         } else {
             UserInput::Unknown(None)
         }
     }
 }
+
+/// `add <name> <expr>`, `remove <name>`, `run <name>` or `list`.
+fn parse_bookmark(s: &str) -> UserInput<'_> {
+    let (command, rest) = split_word(s);
+    match command {
+        "" | "list" => UserInput::Bookmarks,
+        "add" => match split_word(rest) {
+            ("", _) | (_, "") => UserInput::Unknown(Some("try `bookmark add two suc (suc zero)`.")),
+            (name, text) => UserInput::BookmarkAdd(name, text),
+        },
+        "remove" if !rest.is_empty() => UserInput::BookmarkRemove(rest),
+        "run" if !rest.is_empty() => UserInput::BookmarkRun(rest),
+        _ => UserInput::Unknown(Some("use `bookmark add`, `run`, `remove` or `list`.")),
+    }
+}
+
+/// The first word, and the rest.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(idx) => (&s[..idx], s[idx..].trim()),
+        None => (s, ""),
+    }
+}
+
+/// Parse things like `10..20`.
+fn parse_goal_range(s: &str) -> Option<GoalRange> {
+    let idx = s.find("..")?;
+    let start = s[..idx].trim().parse().ok()?;
+    let end = s[idx + 2..].trim().parse().ok()?;
+    Some(GoalRange::Between(start, end))
+}
+
+/// Split off the first expression, which is either a word or in parentheses.
+/// The parentheses are removed.
+pub fn split_expr(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with('(') {
+        let end = s.find(char::is_whitespace).unwrap_or(s.len());
+        return Some((&s[..end], s[end..].trim()));
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some((&s[1..i], s[i + 1..].trim()));
+        }
+    }
+    None
+}
@@ -1,10 +1,13 @@
 use std::io::{self, Write};
+use std::path::PathBuf;
 
 use rustyline::error::ReadlineError;
 
 use crate::editor::CliEditor;
 use crate::file_io::Repl;
-use crate::repl::{line, poll_goals};
+use crate::listen::Incoming;
+use crate::render::prompt_badge;
+use crate::repl::{exit_code, line, poll_goals, settle, show_findings};
 
 const LAMBDA_LT: &str = "\u{03bb}> ";
 const RICH_HELP: &str =
@@ -24,42 +27,93 @@ pub fn help(plain: bool) -> &'static str {
     }
 }
 
+/// The prompt of the rich mode, with the state of the last load.
+/// The goals of a failed load are out of date, only the errors are counted then.
+fn prompt(agda: &Repl) -> String {
+    if !agda.has_loaded {
+        return LAMBDA_LT.to_owned();
+    }
+    let goals = if agda.errors == 0 {
+        Some(agda.goals.goals().len())
+    } else {
+        None
+    };
+    let badge = prompt_badge(goals, agda.errors, agda.color);
+    format!("{} {}", badge, LAMBDA_LT)
+}
+
 /// `interact::ion` stands for `interaction`.
-pub async fn ion(mut agda: Repl) -> io::Result<()> {
-    poll_goals(&mut agda.agda).await?;
+/// Returns the [exit code](crate::repl::exit_code) of the session.
+pub async fn ion(mut agda: Repl) -> io::Result<i32> {
+    poll_goals(&mut agda).await?;
     if agda.is_plain {
         let stdin = io::stdin();
         loop {
+            show_findings(&mut agda);
             print!("> ");
             io::stdout().flush()?;
             let mut next = String::new();
             stdin.read_line(&mut next)?;
             if line(&mut agda, next.trim()).await? {
-                break Ok(());
+                break;
             }
         }
     } else {
-        let editor = CliEditor {};
+        let editor = CliEditor {
+            names: agda.names.clone(),
+        };
         let mut r = editor.into_editor();
         loop {
-            match r.readline(LAMBDA_LT) {
+            show_findings(&mut agda);
+            match r.readline(&prompt(&agda)) {
                 Ok(input) => {
                     let trim = input.trim();
                     r.add_history_entry(trim);
                     if line(&mut agda, trim).await? {
-                        break Ok(());
+                        break;
                     }
                 }
                 Err(ReadlineError::Interrupted) => {}
                 Err(ReadlineError::Eof) => {
-                    println!("Interrupted by Ctrl-d");
-                    break Ok(());
+                    agda.render.info("Interrupted by Ctrl-d");
+                    settle(&mut agda).await?;
+                    agda.write_on_exit()?;
+                    break;
                 }
                 Err(err) => {
-                    println!("Error: {:?}", err);
-                    break Ok(());
+                    agda.render.error(&format!("{:?}", err));
+                    break;
                 }
             }
         }
     }
+    Ok(exit_code(&agda))
+}
+
+/// Take commands from the terminal and from a named pipe,
+/// so editor plugins can drive the same session.
+pub async fn listen(mut agda: Repl, fifo: PathBuf) -> io::Result<i32> {
+    let mut incoming = crate::listen::listen(fifo)?;
+    poll_goals(&mut agda).await?;
+    loop {
+        show_findings(&mut agda);
+        print!("> ");
+        io::stdout().flush()?;
+        let next = match incoming.recv().await {
+            Some(Incoming::Terminal(next)) => next,
+            Some(Incoming::Pipe(next)) => {
+                println!("{}", next);
+                next
+            }
+            None => {
+                settle(&mut agda).await?;
+                agda.write_on_exit()?;
+                break;
+            }
+        };
+        if line(&mut agda, next.trim()).await? {
+            break;
+        }
+    }
+    Ok(exit_code(&agda))
 }
@@ -0,0 +1,92 @@
+use std::fmt::{Display, Error as FmtError, Formatter};
+
+/// Whitespace that confuses Agda's layout rule or lexer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LintKind {
+    Tab,
+    TrailingWhitespace,
+    /// Byte order mark.
+    Bom,
+    /// Non-breaking spaces and friends.
+    OddWhitespace(char),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Lint {
+    /// 0-based.
+    pub line: usize,
+    /// 0-based, in characters.
+    pub column: usize,
+    pub kind: LintKind,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "{}:{}: ", self.line + 1, self.column + 1)?;
+        match self.kind {
+            LintKind::Tab => f.write_str("tab character, Agda's layout rule doesn't like it"),
+            LintKind::TrailingWhitespace => f.write_str("trailing whitespace"),
+            LintKind::Bom => f.write_str("byte order mark"),
+            LintKind::OddWhitespace(c) => write!(f, "unusual whitespace {:?}", c),
+        }
+    }
+}
+
+const BOM: char = '\u{feff}';
+const TAB_WIDTH: usize = 8;
+
+fn is_odd_whitespace(c: char) -> bool {
+    c.is_whitespace() && c != ' ' && c != '\t' || c == BOM
+}
+
+pub fn lint(lines: &[String]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for (line_num, line) in lines.iter().enumerate() {
+        let mut push = |column, kind| {
+            lints.push(Lint {
+                line: line_num,
+                column,
+                kind,
+            })
+        };
+        for (column, c) in line.chars().enumerate() {
+            match c {
+                '\t' => push(column, LintKind::Tab),
+                BOM => push(column, LintKind::Bom),
+                c if is_odd_whitespace(c) => push(column, LintKind::OddWhitespace(c)),
+                _ => {}
+            }
+        }
+        let trimmed = line.trim_end();
+        if trimmed.len() < line.len() {
+            push(trimmed.chars().count(), LintKind::TrailingWhitespace)
+        }
+    }
+    lints
+}
+
+/// Expand tabs, remove BOMs and trailing whitespace,
+/// and replace other whitespace with spaces.
+pub fn fix(line: &str) -> String {
+    let mut fixed = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.trim_end().chars() {
+        match c {
+            '\t' => {
+                let width = TAB_WIDTH - column % TAB_WIDTH;
+                fixed.push_str(&" ".repeat(width));
+                column += width;
+            }
+            BOM => {}
+            c if is_odd_whitespace(c) => {
+                fixed.push(' ');
+                column += 1;
+            }
+            c => {
+                fixed.push(c);
+                column += 1;
+            }
+        }
+    }
+    fixed
+}
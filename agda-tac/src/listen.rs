@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// A line of user input, and where it comes from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Incoming {
+    Terminal(String),
+    Pipe(String),
+}
+
+/// Forward the lines of `read` to `tx`, until either side is closed.
+/// Returns `false` if it's the channel that was closed.
+pub fn forward<R: BufRead>(
+    read: R,
+    tx: &mut UnboundedSender<Incoming>,
+    wrap: fn(String) -> Incoming,
+) -> bool {
+    for line in read.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if tx.try_send(wrap(line)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Create the named pipe unless it's already there.
+// `io::Error::other` is too new for us.
+#[allow(clippy::io_other_error)]
+fn make_fifo(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let status = Command::new("mkfifo").arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "mkfifo failed"))
+    }
+}
+
+/// Read commands from both the terminal and the named pipe `fifo`.
+/// The pipe is reopened whenever a writer closes it, so every
+/// `echo 'give 0 x' > fifo` is picked up.
+pub fn listen(fifo: PathBuf) -> io::Result<UnboundedReceiver<Incoming>> {
+    make_fifo(&fifo)?;
+    let (tx, rx) = unbounded_channel();
+    let mut terminal = tx.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        forward(stdin.lock(), &mut terminal, Incoming::Terminal);
+    });
+    let mut pipe = tx;
+    thread::spawn(move || {
+        while let Ok(file) = File::open(&fifo) {
+            if !forward(BufReader::new(file), &mut pipe, Incoming::Pipe) {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
@@ -0,0 +1,126 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The lock file of a module, next to it like the temporary file of a write.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    path.with_file_name(format!(".{}.agda-tac-lock", name))
+}
+
+/// Open the lock file with an advisory lock on it, `None` if another process has it.
+/// The system lets go of it when the process ends, so a crash leaves no lock behind.
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<Option<File>> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // The process id of the one having it is read before it's ours.
+            .truncate(false)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => Ok(None),
+                _ => Err(e),
+            };
+        }
+        // The session that had it may have removed it after it was opened here,
+        // then the lock is on a file nobody else looks at.
+        let (open, now) = match fs::metadata(path) {
+            Ok(now) => (file.metadata()?, now),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if open.dev() == now.dev() && open.ino() == now.ino() {
+            return Ok(Some(file));
+        }
+    }
+}
+
+/// Like the one on unix, here the file can't be opened for writing while it's open.
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 1;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    let opened = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(FILE_SHARE_READ)
+        .open(path);
+    match opened {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Without a way to lock files, a lock left by a crash needs `--force`.
+#[cfg(not(any(unix, windows)))]
+fn open_locked(path: &Path) -> io::Result<Option<File>> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The file is open in this agda-tac, the lock file is removed when it's dropped.
+/// It holds the process id, to tell who has the file.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    /// `None` when the lock is another agda-tac's, and this one is forced.
+    file: Option<File>,
+}
+
+impl Lock {
+    /// Take the lock of `file`, or the process id of the agda-tac having it.
+    /// With `force`, it goes on without the lock.
+    pub fn acquire(file: &Path, force: bool) -> io::Result<Result<Lock, u32>> {
+        let path = lock_path(file);
+        match open_locked(&path)? {
+            Some(mut file) => {
+                file.set_len(0)?;
+                write!(file, "{}", std::process::id())?;
+                file.flush()?;
+                Ok(Ok(Lock {
+                    path,
+                    file: Some(file),
+                }))
+            }
+            None if force => Ok(Ok(Lock { path, file: None })),
+            None => {
+                let text = fs::read_to_string(&path).unwrap_or_default();
+                Ok(Err(text.trim().parse().unwrap_or(0)))
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => return,
+        };
+        // An open file can't be removed there.
+        if cfg!(windows) {
+            drop(file);
+            let _ = fs::remove_file(&self.path);
+        } else {
+            // Nothing to do about it on the way out.
+            let _ = fs::remove_file(&self.path);
+            drop(file);
+        }
+    }
+}
@@ -1,32 +1,129 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::background::Background;
+use crate::bookmarks::Bookmarks;
 use crate::file_io::Repl;
-use agda_mode::agda::ReplState;
+use agda_mode::agda::{AgdaInternalError, AgdaOptions, ReplState};
 use agda_mode::base::{debug_command, debug_response};
 
+/// Results written into the file as comments.
+mod annotate;
 /// Clap cli argument things.
 mod args;
+/// Another Agda, for work the session shouldn't wait for.
+mod background;
+/// Expressions kept across sessions.
+mod bookmarks;
+/// Goal types from previous loads.
+mod cache;
+/// User configuration file.
+mod config;
+/// Word-level diff of expressions.
+mod diff;
 /// Rustyline completion & hints & things.
 mod editor;
+/// What commands do, decided without Agda.
+mod effects;
 /// Buffer & file, for Agda interaction.
 mod file_io;
+/// Hole markers in the source.
+mod holes;
+/// Sorting the imports of the file.
+mod imports;
 /// Parse user input as a structural "command".
 mod input;
 /// Basic info about interaction, like `help`, read line & print things, etc.
 mod interact;
+/// Whitespace checks before loading.
+mod lint;
+/// Commands from a named pipe, for editor plugins.
+mod listen;
+/// One agda-tac per file at a time.
+mod lock;
+/// Where a name is used, out of Agda's highlighting.
+mod occurrences;
+/// Warning flags in `OPTIONS` pragmas.
+mod pragmas;
+/// Where the checking time goes, from Agda's profile.
+mod profile;
+/// Files of the modules the file imports.
+mod project;
+/// `IOTCM` commands typed by hand, for working on the protocol.
+mod raw;
+/// Output of the interaction, in different styles.
+mod render;
 /// Implementation of interaction.
 mod repl;
+/// JSON-RPC over stdio, for editor plugins.
+mod rpc;
+/// Names bound by the module header.
+mod scope;
+/// Shell commands run from the REPL.
+mod shell;
+/// Goals exported to a file, and hole fillings read from one.
+mod solutions;
+/// Shows that Agda is thinking.
+mod spinner;
+/// Case splits, shown before they are written.
+mod split;
+/// Where names of the standard library are defined, for imports.
+mod stdlib;
+/// ctags and etags files of the definitions, out of Agda's highlighting.
+mod tags;
+/// The type checker's debug output while checking a term.
+mod trace;
+
+#[cfg(test)]
+mod tests;
 
-const FAIL_WRITE: &str = "Failed to create Agda module file";
+const FAIL_OPEN: &str = "Failed to lock or create the Agda module file";
+const FAIL_READ: &str = "Failed to read the Agda module file";
 const FAIL: &str = "Failed to start Agda";
 const FAIL_CMD: &str = "Failed to evaluate Agda command";
+const FAIL_WRITE_TAGS: &str = "Failed to write the tags";
+const FAIL_WRITE_LOG: &str = "Failed to open the running info log";
 
 #[tokio::main]
 async fn main() {
-    let args = args::pre();
+    let (args, config) = args::pre();
     unsafe {
         debug_command(args.debug_command);
         debug_response(args.debug_response);
     };
-    let agda_program = args.agda.as_ref().map_or("agda", |s| &*s);
+    let agda_program = args.agda.as_ref().map_or("agda", |s| s);
+    let mut options = AgdaOptions::new()
+        .no_libraries(args.no_libraries)
+        .local_interfaces(args.local_interfaces);
+    if args.caching || args.no_caching {
+        options = options.caching(args.caching);
+    }
+    options = options.exit_on_error(args.exit_on_error);
+    for dir in &args.include {
+        options = options.arg(format!("--include-path={}", dir));
+    }
+    if let Some(args::SubCommand::Tags {
+        files,
+        output,
+        etags,
+    }) = &args.command
+    {
+        let default = if *etags { "TAGS" } else { "tags" };
+        let output = Path::new(output.as_ref().map_or(default, |s| s));
+        let written = tags::write_tags(agda_program, &options, files, output, *etags).await;
+        if let Err(e) = written {
+            eprintln!("{}: {}", FAIL_WRITE_TAGS, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.raw {
+        if let Err(e) = raw::run(agda_program, &options).await {
+            eprintln!("{}: {}", FAIL, e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let file = match args.file {
         Some(file) => file,
         None => {
@@ -34,7 +131,21 @@ async fn main() {
             std::process::exit(1);
         }
     };
-    let (f, path, first_line) = file_io::init_module(file).expect(FAIL_WRITE);
+    // Two sessions on a file would overwrite each other's edits.
+    let (path, lock) = match file_io::open_module(file, args.force) {
+        Ok(Ok(opened)) => opened,
+        Ok(Err(pid)) => {
+            eprintln!(
+                "Another agda-tac (process {}) has the file open, use `--force` to start anyway.",
+                pid
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}: {}", FAIL_OPEN, e);
+            std::process::exit(1);
+        }
+    };
     let abs_path = match path.to_str() {
         None => {
             eprintln!("The given file name has some problems.");
@@ -42,9 +153,61 @@ async fn main() {
         }
         Some(f) => f.to_owned(),
     };
-    let repl_state = ReplState::start(agda_program, abs_path).await.expect(FAIL);
-    let mut repl_state = Repl::new(repl_state, f, path);
-    repl_state.is_plain = args.plain;
-    repl_state.append_line_buffer(first_line);
-    interact::ion(repl_state).await.expect(FAIL_CMD);
+    let repl_state = ReplState::start_with(agda_program, abs_path, &options)
+        .await
+        .expect(FAIL);
+    let mut repl_state = Repl::new(repl_state, path);
+    repl_state.lock = Some(lock);
+    repl_state.background = Background::new(agda_program.to_owned(), options.clone());
+    repl_state.config = config;
+    repl_state.config.no_confirm_exit = args.no_confirm_exit;
+    repl::apply_config(&mut repl_state).expect(FAIL_WRITE_LOG);
+    repl_state.bookmarks = Bookmarks::load(bookmarks::default_path());
+    repl_state.is_plain = args.plain || args.a11y;
+    repl_state.spinner = !repl_state.is_plain && !args.json && !args.json_rpc;
+    let plain = args.plain || args.no_color;
+    repl_state.color = !plain && !args.a11y && !args.json;
+    repl_state.render = render::from_flags(plain, args.json, args.a11y);
+    if let Some(info_file) = args.info_file {
+        let inner = std::mem::replace(&mut repl_state.render, Box::new(render::Plain));
+        repl_state.render = Box::new(render::InfoFile::new(inner, info_file.into()));
+    }
+    if let Some(quickfix) = args.vim_quickfix {
+        let inner = std::mem::replace(&mut repl_state.render, Box::new(render::Plain));
+        let file = repl_state.path.clone();
+        let quickfix = render::Quickfix::new(inner, file, quickfix.into());
+        repl_state.render = Box::new(quickfix);
+    }
+    if let Some(sarif) = args.sarif {
+        let inner = std::mem::replace(&mut repl_state.render, Box::new(render::Plain));
+        let file = repl_state.path.clone();
+        repl_state.render = Box::new(render::Sarif::new(inner, file, sarif.into()));
+    }
+    repl_state.bell_on_done = args.bell_on_done;
+    repl_state.bell_on_fail = args.bell_on_fail;
+    // The buffer starts in sync with the file.
+    repl_state.read_buffer().expect(FAIL_READ);
+    let result = if args.json_rpc {
+        let restart = args.heartbeat.map(|seconds| rpc::Restart {
+            program: agda_program.to_owned(),
+            options,
+            every: Duration::from_secs(seconds),
+        });
+        rpc::serve(repl_state, restart).await.map(|()| 0)
+    } else if let Some(fifo) = args.listen {
+        interact::listen(repl_state, fifo.into()).await
+    } else {
+        interact::ion(repl_state).await
+    };
+    // `exit` doesn't run destructors, the lock went with the `Repl`.
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => match AgdaInternalError::of(&e) {
+            Some(fatal) => {
+                eprintln!("{}", fatal);
+                std::process::exit(1)
+            }
+            None => panic!("{}: {:?}", FAIL_CMD, e),
+        },
+    }
 }
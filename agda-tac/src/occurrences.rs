@@ -0,0 +1,60 @@
+use agda_mode::resp::AspectHighlight;
+
+/// A use (or the definition) of a name, at a 1-based line and (character) column.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Occurrence {
+    pub line: usize,
+    pub column: usize,
+    /// What Agda thinks it is, like `function` or `bound`.
+    pub atoms: Vec<String>,
+}
+
+/// The line and column of a 1-based character offset, like in Agda's ranges.
+/// Every line ends with `ending` characters.
+pub fn position(buffer: &[String], ending: usize, offset: usize) -> Option<(usize, usize)> {
+    let mut start = 1;
+    for (line_num, line) in buffer.iter().enumerate() {
+        let len = line.chars().count();
+        if offset < start + len {
+            return Some((line_num, offset.checked_sub(start)?));
+        }
+        start += len + ending;
+    }
+    None
+}
+
+/// The highlighted tokens of `buffer` that are exactly `name`, in order.
+pub fn occurrences(
+    buffer: &[String],
+    ending: usize,
+    highlighting: &[AspectHighlight],
+    name: &str,
+) -> Vec<Occurrence> {
+    let width = name.chars().count();
+    let mut found: Vec<Occurrence> = highlighting
+        .iter()
+        .filter(|h| (h.range.end.offset - h.range.start.offset) as usize == width)
+        .filter_map(|h| {
+            let (line_num, column) = position(buffer, ending, h.range.start.offset as usize)?;
+            let text: String = buffer[line_num].chars().skip(column).take(width).collect();
+            if text != name {
+                return None;
+            }
+            Some(Occurrence {
+                line: line_num + 1,
+                column: column + 1,
+                atoms: h.atoms.clone(),
+            })
+        })
+        .collect();
+    found.sort_by_key(|o| (o.line, o.column));
+    // Agda may highlight a token more than once.
+    found.dedup_by(|a, b| {
+        let same = (a.line, a.column) == (b.line, b.column);
+        if same {
+            b.atoms.append(&mut a.atoms);
+        }
+        same
+    });
+    found
+}
@@ -0,0 +1,48 @@
+/// Is `flag` an option about warnings, like `-Wno-UnreachableClauses` or `--warning=all`.
+pub fn is_warning_flag(flag: &str) -> bool {
+    (flag.len() > 2 && flag.starts_with("-W")) || flag.starts_with("--warning=")
+}
+
+/// The options of a one line `{-# OPTIONS ... #-}` pragma.
+fn options(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if !line.starts_with("{-#") || !line.ends_with("#-}") {
+        return None;
+    }
+    let inner = line[3..line.len() - 3].trim();
+    let mut words = inner.splitn(2, char::is_whitespace);
+    match (words.next(), words.next()) {
+        (Some("OPTIONS"), rest) => Some(rest.unwrap_or("").trim()),
+        _ => None,
+    }
+}
+
+/// The warning flags of the `OPTIONS` pragmas, in order.
+pub fn warning_flags(lines: &[String]) -> Vec<&str> {
+    lines
+        .iter()
+        .filter_map(|line| options(line))
+        .flat_map(str::split_whitespace)
+        .filter(|flag| is_warning_flag(flag))
+        .collect()
+}
+
+/// Add `flag` to the first `OPTIONS` pragma: the line number and the new line.
+/// `None` if there's no pragma to add it to.
+pub fn add_flag(lines: &[String], flag: &str) -> Option<(usize, String)> {
+    let line_num = lines.iter().position(|line| options(line).is_some())?;
+    let line = &lines[line_num];
+    let end = line.rfind("#-}")?;
+    let before = line[..end].trim_end();
+    Some((line_num, format!("{} {} {}", before, flag, &line[end..])))
+}
+
+/// A new pragma with only `flag`.
+pub fn pragma(flag: &str) -> String {
+    format!("{{-# OPTIONS {} #-}}", flag)
+}
+
+/// Is the warning not covered by any of the `ignored` texts.
+pub fn is_shown(ignored: &[String], warning: &str) -> bool {
+    !ignored.iter().any(|text| warning.contains(text.as_str()))
+}
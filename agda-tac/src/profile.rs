@@ -0,0 +1,41 @@
+use agda_mode::resp::ProfileEntry;
+
+/// Agda's profile of the time spent on each definition, for `profile`.
+pub const FLAG: &str = "--profile=definitions";
+
+/// How many definitions are shown.
+pub const LIMIT: usize = 15;
+
+/// The running info of a load as a table, the slowest definitions first
+/// with their share of the total. Empty if Agda printed no profile.
+pub fn summary(infos: &[(i32, String)]) -> Vec<String> {
+    let mut entries: Vec<ProfileEntry> = infos
+        .iter()
+        .flat_map(|(_, message)| ProfileEntry::parse_all(message))
+        .collect();
+    let total = entries.iter().position(|e| e.account == "Total");
+    let total = total.map(|i| entries.remove(i));
+    // Stable, so equal times stay in Agda's order.
+    entries.sort_by_key(|e| std::cmp::Reverse(e.time.duration));
+    let width = entries.iter().map(|e| e.time.raw.len()).max().unwrap_or(0);
+    let total_millis = total
+        .as_ref()
+        .and_then(|t| t.time.duration)
+        .map(|d| d.as_millis());
+    let mut lines = Vec::new();
+    if let Some(total) = &total {
+        lines.push(format!("Total: {}", total.time));
+    }
+    for entry in entries.iter().take(LIMIT) {
+        let share = match (entry.time.duration, total_millis) {
+            (Some(d), Some(t)) if t > 0 => format!("{:>3}%  ", d.as_millis() * 100 / t),
+            _ => String::new(),
+        };
+        let time = format!("{:>width$}", entry.time.raw, width = width);
+        lines.push(format!("{}  {}{}", time, share, entry.account));
+    }
+    if entries.len() > LIMIT {
+        lines.push(format!("{} more.", entries.len() - LIMIT));
+    }
+    lines
+}
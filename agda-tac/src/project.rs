@@ -0,0 +1,105 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The module of an `import` or `open import` line, like `Data.Nat`.
+pub fn imported_module(line: &str) -> Option<&str> {
+    let mut words = line.split_whitespace();
+    let first = words.next()?;
+    let import = if first == "open" {
+        words.next()?
+    } else {
+        first
+    };
+    if import != "import" {
+        return None;
+    }
+    words.next()
+}
+
+/// Where the file of a module is, relative to the root of the project.
+pub fn module_path(root: &Path, module: &str) -> PathBuf {
+    let mut path = root.join(module.replace('.', "/"));
+    path.set_extension("agda");
+    path
+}
+
+/// The `include` directories of an `.agda-lib` file, relative to `dir`.
+/// Fields can go on over the lines that start with a space.
+fn library_includes(dir: &Path, text: &str) -> Vec<PathBuf> {
+    let mut includes = Vec::new();
+    let mut in_include = false;
+    for line in text.lines() {
+        let value = if line.starts_with(char::is_whitespace) {
+            line
+        } else {
+            let mut field = line.splitn(2, ':');
+            in_include = field.next().map(str::trim) == Some("include");
+            field.next().unwrap_or("")
+        };
+        if in_include {
+            includes.extend(value.split_whitespace().map(|d| dir.join(d)));
+        }
+    }
+    includes
+}
+
+/// The directories the modules of the project of `file` are in:
+/// the `include`s of the nearest `.agda-lib` file above it,
+/// or the directory of `file` when there's none.
+pub fn source_roots(file: &Path) -> Vec<PathBuf> {
+    let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    for dir in parent.ancestors() {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let library = entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .find(|p| p.extension() == Some(OsStr::new("agda-lib")));
+        if let Some(library) = library {
+            let text = fs::read_to_string(&library).unwrap_or_default();
+            let includes = library_includes(dir, &text);
+            return if includes.is_empty() {
+                vec![dir.to_path_buf()]
+            } else {
+                includes
+            };
+        }
+    }
+    vec![parent]
+}
+
+/// The file of the module under the first of the `roots` that has it.
+fn find_module(roots: &[PathBuf], module: &str) -> Option<PathBuf> {
+    roots
+        .iter()
+        .map(|root| module_path(root, module))
+        .find(|path| path.is_file())
+}
+
+/// The files of the project the `lines` import, directly or through other files.
+/// Modules that are not under the `roots`, like the ones of libraries, are left out.
+pub fn imported_files(roots: &[PathBuf], lines: &[String]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut todo: Vec<PathBuf> = lines
+        .iter()
+        .filter_map(|l| imported_module(l))
+        .filter_map(|m| find_module(roots, m))
+        .collect();
+    while let Some(file) = todo.pop() {
+        if files.contains(&file) {
+            continue;
+        }
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let imports = text.lines().filter_map(imported_module);
+        todo.extend(imports.filter_map(|m| find_module(roots, m)));
+        files.push(file);
+    }
+    files.sort();
+    files
+}
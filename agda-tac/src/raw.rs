@@ -0,0 +1,103 @@
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+use agda_mode::agda::{
+    deserialize_agda_bytes, deserialize_agda_with, init_agda_process_with, spawn_writer,
+    AgdaOptions, JustStdio, ProcessStdio,
+};
+use agda_mode::compat::Schema;
+use agda_mode::resp::Resp;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The prompt of the raw mode, where the lines are sent to Agda as they are.
+const RAW_PROMPT: &str = "IOTCM> ";
+
+/// `[   1.234s]`, the time since the session started.
+fn timestamp(start: Instant) -> String {
+    let elapsed = start.elapsed();
+    format!("[{:>4}.{:03}s]", elapsed.as_secs(), elapsed.subsec_millis())
+}
+
+/// A line of Agda's stdout, pretty-printed if it's JSON,
+/// and what agda-mode makes of it if that's nothing.
+pub fn pretty_line(line: &[u8], schema: Schema) -> Vec<String> {
+    let value: Value = match deserialize_agda_bytes(line) {
+        Ok(value) => value,
+        Err(_) => return vec![String::from_utf8_lossy(line).trim_end().to_owned()],
+    };
+    // Serializing a value read from JSON doesn't fail.
+    let mut lines: Vec<String> = serde_json::to_string_pretty(&value)
+        .unwrap()
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect();
+    match deserialize_agda_with::<Resp>(line, schema) {
+        Ok(Resp::Unknown) => lines.push("(not a response agda-mode knows)".to_owned()),
+        Ok(_) => {}
+        Err(e) => lines.push(format!("(agda-mode cannot read it: {})", e)),
+    }
+    lines
+}
+
+/// Print the lines of a pipe of Agda as they come, in a task of its own.
+fn print_lines<R: AsyncRead + Unpin + Send + 'static>(
+    pipe: R,
+    start: Instant,
+    format: impl Fn(&[u8]) -> Vec<String> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let mut pipe = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        while let Ok(n) = pipe.read_until(b'\n', &mut buf).await {
+            if n == 0 {
+                break;
+            }
+            let stamp = timestamp(start);
+            for line in format(&buf) {
+                println!("{} {}", stamp, line);
+            }
+            buf.clear();
+        }
+    });
+}
+
+/// Type `IOTCM` commands and see Agda's responses as they are,
+/// for working on the protocol. Nothing is loaded or interpreted.
+pub async fn run(agda_program: &str, options: &AgdaOptions) -> io::Result<()> {
+    let schema = match options.schema {
+        Some(schema) => schema,
+        None => Schema::detect(agda_program).await,
+    };
+    let ProcessStdio(process, JustStdio(stdin, stdout, stderr)) =
+        init_agda_process_with(agda_program, options)?;
+    tokio::spawn(async {
+        if let Ok(status) = process.await {
+            eprintln!("Agda exits with status {}.", status);
+        }
+    });
+    let start = Instant::now();
+    print_lines(stdout, start, move |line| pretty_line(line, schema));
+    print_lines(stderr, start, |line| {
+        vec![format!(
+            "stderr: {}",
+            String::from_utf8_lossy(line).trim_end()
+        )]
+    });
+    let mut sender = spawn_writer(stdin);
+    let input = io::stdin();
+    loop {
+        print!("{}", RAW_PROMPT);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        match line.trim() {
+            "" => {}
+            "exit" | "quit" => break,
+            command => sender.send_line(command).await?,
+        }
+    }
+    sender.close().await
+}
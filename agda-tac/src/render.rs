@@ -0,0 +1,895 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ansi_term::{Colour, Style};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use agda_mode::resp::{DuplicateBinding, Goal, LabeledRange};
+
+use crate::file_io::save_lines;
+
+/// A goal to display, with remarks like "blocked by ...".
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct GoalLine<'a> {
+    #[serde(flatten)]
+    pub goal: &'a Goal,
+    pub notes: &'a [String],
+}
+
+/// Where the output of agda-tac goes.
+/// Embedders can reuse the command handling with their own display.
+pub trait Renderer {
+    /// Goals to display, `hidden` is the number of goals left out.
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize);
+    /// Errors reported by Agda (or by the terminal).
+    fn error(&mut self, message: &str);
+    /// Complaints about the user input.
+    fn complain(&mut self, message: &str);
+    /// Problems that don't stop us, like suspicious whitespace.
+    fn warning(&mut self, message: &str);
+    /// Everything else.
+    fn info(&mut self, message: &str);
+    /// A load starts, what comes until it's [over](Renderer::loaded) is about it.
+    fn loading(&mut self) {}
+    /// The load is over, its goals or its error were shown.
+    fn loaded(&mut self) {}
+}
+
+/// Pick a renderer according to the command line flags.
+pub fn from_flags(plain: bool, json: bool, a11y: bool) -> Box<dyn Renderer> {
+    if json {
+        Box::new(Json)
+    } else if a11y {
+        Box::new(Accessible)
+    } else if plain {
+        Box::new(Plain)
+    } else {
+        Box::new(Ansi)
+    }
+}
+
+fn goal_line(line: &GoalLine) -> String {
+    let mut s = format!("?{}: {}", line.goal.interaction_point, line.goal.goal_type);
+    for note in line.notes {
+        s.push_str(&format!(" ({})", note));
+    }
+    s
+}
+
+fn hidden_line(hidden: usize) -> String {
+    format!("\u{2026} {} more (use `goals --all`)", hidden)
+}
+
+/// The text of a goal list, without any decoration.
+pub fn goal_lines(goals: &[GoalLine], hidden: usize) -> Vec<String> {
+    if goals.is_empty() && hidden == 0 {
+        return vec!["No goals.".to_owned()];
+    }
+    let mut lines = Vec::with_capacity(goals.len() + 2);
+    lines.push("Goals:".to_owned());
+    lines.extend(goals.iter().map(|g| goal_line(g)));
+    if hidden > 0 {
+        lines.push(hidden_line(hidden));
+    }
+    lines
+}
+
+/// One call of a [`Renderer`](self::Renderer), for the renderers that print text.
+#[derive(Debug, Clone, Copy)]
+pub enum Output<'a> {
+    Goals(&'a [GoalLine<'a>], usize),
+    Error(&'a str),
+    Complaint(&'a str),
+    Warning(&'a str),
+    Info(&'a str),
+}
+
+impl<'a> Output<'a> {
+    /// Print the text, errors go to stderr.
+    fn print(self, text: String) {
+        match self {
+            Output::Error(..) => eprint!("{}", text),
+            _ => print!("{}", text),
+        }
+    }
+}
+
+/// Each line of `lines` with a line break, as one text.
+fn text(lines: impl IntoIterator<Item = String>) -> String {
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// What [`Plain`](self::Plain) prints.
+pub fn plain_text(output: Output) -> String {
+    match output {
+        Output::Goals(goals, hidden) => text(goal_lines(goals, hidden)),
+        Output::Error(message) => format!("Errors:\n{}\n", message),
+        Output::Complaint(message) | Output::Info(message) => format!("{}\n", message),
+        Output::Warning(message) => format!("Warning: {}\n", message),
+    }
+}
+
+/// No colors, used with `--plain`.
+pub struct Plain;
+
+impl Renderer for Plain {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        let output = Output::Goals(goals, hidden);
+        output.print(plain_text(output))
+    }
+
+    fn error(&mut self, message: &str) {
+        let output = Output::Error(message);
+        output.print(plain_text(output))
+    }
+
+    fn complain(&mut self, message: &str) {
+        let output = Output::Complaint(message);
+        output.print(plain_text(output))
+    }
+
+    fn warning(&mut self, message: &str) {
+        let output = Output::Warning(message);
+        output.print(plain_text(output))
+    }
+
+    fn info(&mut self, message: &str) {
+        let output = Output::Info(message);
+        output.print(plain_text(output))
+    }
+}
+
+/// Lines of the [accessible](self::Accessible) output are at most this long.
+pub const A11Y_WIDTH: usize = 72;
+
+/// Break the lines of `text` at spaces, so they are at most `width` long.
+/// Words longer than that are kept in one piece.
+/// Blank lines and indented ones, like the code in Agda's errors, are kept as they are.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with(char::is_whitespace) {
+            lines.push(line.trim_end().to_owned());
+            continue;
+        }
+        let mut current = String::new();
+        for word in line.split_whitespace() {
+            let len = current.chars().count();
+            if len > 0 && len + 1 + word.chars().count() > width {
+                lines.push(current.clone());
+                current.clear();
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+    }
+    lines
+}
+
+/// A block of the accessible output, the first line starts with the category.
+pub fn a11y_block(category: &str, message: &str) -> Vec<String> {
+    wrap(&format!("{}: {}", category, message), A11Y_WIDTH)
+}
+
+/// The goal list in words, one block per goal.
+pub fn a11y_goal_lines(goals: &[GoalLine], hidden: usize) -> Vec<String> {
+    if goals.is_empty() && hidden == 0 {
+        return vec!["Goals: none.".to_owned()];
+    }
+    let total = goals.len() + hidden;
+    let mut lines = vec![format!("Goals: {}.", total)];
+    for line in goals {
+        let ii = line.goal.interaction_point;
+        let category = format!("Goal {} type", ii);
+        lines.extend(a11y_block(&category, &line.goal.goal_type));
+        for note in line.notes {
+            lines.extend(a11y_block(&format!("Goal {} note", ii), note));
+        }
+    }
+    if hidden > 0 {
+        let message = format!("{} more not shown, use goals --all to see them.", hidden);
+        lines.extend(a11y_block("Goals", &message));
+    }
+    lines
+}
+
+/// For screen readers, used with `--a11y`: no colors or symbols,
+/// every block says what it is in words, and the lines are short.
+pub struct Accessible;
+
+impl Renderer for Accessible {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        for line in a11y_goal_lines(goals, hidden) {
+            println!("{}", line);
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        for line in a11y_block("Error", message) {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn complain(&mut self, message: &str) {
+        for line in a11y_block("Input problem", message) {
+            println!("{}", line);
+        }
+    }
+
+    fn warning(&mut self, message: &str) {
+        for line in a11y_block("Warning", message) {
+            println!("{}", line);
+        }
+    }
+
+    fn info(&mut self, message: &str) {
+        for line in a11y_block("Info", message) {
+            println!("{}", line);
+        }
+    }
+}
+
+fn ansi_goal_lines(goals: &[GoalLine], hidden: usize) -> Vec<String> {
+    if goals.is_empty() && hidden == 0 {
+        return vec![Colour::Green.paint("No goals.").to_string()];
+    }
+    let mut lines = vec![Style::new().bold().paint("Goals:").to_string()];
+    for line in goals {
+        let ii = format!("?{}", line.goal.interaction_point);
+        let mut s = format!("{}: {}", Colour::Cyan.paint(ii), line.goal.goal_type);
+        for note in line.notes {
+            s.push_str(&format!(" {}", Colour::Yellow.paint(format!("({})", note))));
+        }
+        lines.push(s);
+    }
+    if hidden > 0 {
+        lines.push(Style::new().dimmed().paint(hidden_line(hidden)).to_string());
+    }
+    lines
+}
+
+fn count(n: usize, what: &str) -> String {
+    if n == 1 {
+        format!("1 {}", what)
+    } else {
+        format!("{} {}s", n, what)
+    }
+}
+
+/// The state of the proof before the prompt, like `[3 goals, 1 error]`.
+/// The errors are left out when there are none, and the goals when they're not known.
+pub fn prompt_badge(goals: Option<usize>, errors: usize, color: bool) -> String {
+    let paint = |colour: Colour, text: String| {
+        if color {
+            colour.paint(text).to_string()
+        } else {
+            text
+        }
+    };
+    let mut parts = Vec::with_capacity(2);
+    if let Some(goals) = goals {
+        let goal_colour = if goals == 0 {
+            Colour::Green
+        } else {
+            Colour::Yellow
+        };
+        parts.push(paint(goal_colour, count(goals, "goal")));
+    }
+    if errors > 0 {
+        parts.push(paint(Colour::Red, count(errors, "error")));
+    }
+    format!("[{}]", parts.join(", "))
+}
+
+/// What [`Ansi`](self::Ansi) prints, with the escape codes.
+pub fn ansi_text(output: Output) -> String {
+    match output {
+        Output::Goals(goals, hidden) => text(ansi_goal_lines(goals, hidden)),
+        Output::Error(message) => format!("{}\n{}\n", Colour::Red.bold().paint("Errors:"), message),
+        Output::Complaint(message) => format!("{}\n", Colour::Yellow.paint(message)),
+        Output::Warning(message) => {
+            format!("{} {}\n", Colour::Yellow.bold().paint("Warning:"), message)
+        }
+        Output::Info(message) => format!("{}\n", message),
+    }
+}
+
+/// Colored output, the default.
+pub struct Ansi;
+
+impl Renderer for Ansi {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        let output = Output::Goals(goals, hidden);
+        output.print(ansi_text(output))
+    }
+
+    fn error(&mut self, message: &str) {
+        let output = Output::Error(message);
+        output.print(ansi_text(output))
+    }
+
+    fn complain(&mut self, message: &str) {
+        let output = Output::Complaint(message);
+        output.print(ansi_text(output))
+    }
+
+    fn warning(&mut self, message: &str) {
+        let output = Output::Warning(message);
+        output.print(ansi_text(output))
+    }
+
+    fn info(&mut self, message: &str) {
+        let output = Output::Info(message);
+        output.print(ansi_text(output))
+    }
+}
+
+/// The JSON object for a goal list.
+pub fn json_goals(goals: &[GoalLine], hidden: usize) -> Value {
+    json!({ "kind": "Goals", "goals": goals, "hidden": hidden })
+}
+
+/// The JSON object for a message of `kind`.
+pub fn json_message(kind: &str, message: &str) -> Value {
+    json!({ "kind": kind, "message": message })
+}
+
+/// One JSON object per line, used with `--json`.
+pub struct Json;
+
+impl Renderer for Json {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        println!("{}", json_goals(goals, hidden));
+    }
+
+    fn error(&mut self, message: &str) {
+        println!("{}", json_message("Error", message));
+    }
+
+    fn complain(&mut self, message: &str) {
+        println!("{}", json_message("Complaint", message));
+    }
+
+    fn warning(&mut self, message: &str) {
+        println!("{}", json_message("Warning", message));
+    }
+
+    fn info(&mut self, message: &str) {
+        println!("{}", json_message("Info", message));
+    }
+}
+
+/// Collects the same objects as [`Json`](self::Json), to be sent in one go.
+#[derive(Clone, Default)]
+pub struct JsonSink(pub Rc<RefCell<Vec<Value>>>);
+
+impl JsonSink {
+    /// Take everything collected so far.
+    pub fn take(&self) -> Vec<Value> {
+        self.0.replace(Vec::new())
+    }
+}
+
+impl Renderer for JsonSink {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        self.0.borrow_mut().push(json_goals(goals, hidden))
+    }
+
+    fn error(&mut self, message: &str) {
+        self.0.borrow_mut().push(json_message("Error", message))
+    }
+
+    fn complain(&mut self, message: &str) {
+        self.0.borrow_mut().push(json_message("Complaint", message))
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.0.borrow_mut().push(json_message("Warning", message))
+    }
+
+    fn info(&mut self, message: &str) {
+        self.0.borrow_mut().push(json_message("Info", message))
+    }
+}
+
+/// Mirrors the output into a file, like the information buffer of Emacs.
+/// The file is rewritten on every output, with a section for each kind.
+/// The messages add up until the next load, the goals are the last ones.
+pub struct InfoFile {
+    inner: Box<dyn Renderer>,
+    path: PathBuf,
+    sections: Vec<(&'static str, Vec<String>)>,
+}
+
+impl InfoFile {
+    pub fn new(inner: Box<dyn Renderer>, path: PathBuf) -> Self {
+        Self {
+            inner,
+            path,
+            sections: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, section: &'static str, mut lines: Vec<String>) {
+        match self.sections.iter_mut().find(|(name, _)| *name == section) {
+            Some((_, old)) if section == "Goals" => *old = lines,
+            Some((_, old)) => old.append(&mut lines),
+            None => self.sections.push((section, lines)),
+        }
+        let mut text = Vec::new();
+        for (name, lines) in &self.sections {
+            text.push(format!("---- {} ----", name));
+            text.extend(lines.iter().cloned());
+            text.push(String::new());
+        }
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = save_lines(&self.path, &text) {
+            let message = format!("cannot write {}: {}", self.path.display(), e);
+            self.inner.warning(&message);
+        }
+    }
+}
+
+impl Renderer for InfoFile {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        self.inner.goals(goals, hidden);
+        self.update("Goals", goal_lines(goals, hidden));
+    }
+
+    fn error(&mut self, message: &str) {
+        self.inner.error(message);
+        self.update("Errors", vec![message.to_owned()]);
+    }
+
+    fn complain(&mut self, message: &str) {
+        self.inner.complain(message)
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.inner.warning(message);
+        self.update("Warnings", vec![message.to_owned()]);
+    }
+
+    fn info(&mut self, message: &str) {
+        self.inner.info(message);
+        self.update("Info", vec![message.to_owned()]);
+    }
+
+    fn loading(&mut self) {
+        self.inner.loading();
+        self.sections.clear();
+    }
+
+    fn loaded(&mut self) {
+        self.inner.loaded()
+    }
+}
+
+/// Parse Agda's `/path/A.agda:5,7-8` into the file, line and column.
+fn agda_location(line: &str) -> Option<(&str, u32, u32)> {
+    let line = line.trim();
+    let colon = line.rfind(':')?;
+    let (file, range) = (&line[..colon], &line[colon + 1..]);
+    let start = range.split('-').next()?;
+    let mut parts = start.splitn(2, ',');
+    let line_num = parts.next()?.parse().ok()?;
+    let column = parts.next()?.parse().ok()?;
+    if file.is_empty() {
+        None
+    } else {
+        Some((file, line_num, column))
+    }
+}
+
+/// At most this many lines of a range are shown.
+const SNIPPET_LINES: usize = 4;
+
+/// The lines of `buffer` in the range, with `^` under the range,
+/// like `rustc` does it.
+pub fn snippet(buffer: &[String], range: &LabeledRange) -> Vec<String> {
+    let ((l1, c1), (l2, c2)) = (range.start, range.end);
+    let mut lines = vec![format!("{}:{},{}: {}", range.file, l1, c1, range.label)];
+    let first = (l1 as usize).saturating_sub(1);
+    let last = (l2 as usize).min(buffer.len()).min(first + SNIPPET_LINES);
+    let width = last.to_string().len();
+    for (i, line) in buffer.iter().enumerate().take(last).skip(first) {
+        let from = if i == first {
+            (c1 as usize).saturating_sub(1)
+        } else {
+            0
+        };
+        let len = line.chars().count();
+        let to = if i + 1 == l2 as usize {
+            (c2 as usize).saturating_sub(1).min(len)
+        } else {
+            len
+        };
+        lines.push(format!("{:>w$} | {}", i + 1, line, w = width));
+        let carets = "^".repeat(to.saturating_sub(from).max(1));
+        lines.push(format!(
+            "{:>w$} | {}{}",
+            "",
+            " ".repeat(from),
+            carets,
+            w = width
+        ));
+    }
+    lines
+}
+
+/// An error or warning at a place in a file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+/// The diagnostics in an error message.
+/// Messages without a location are reported at the start of `file`.
+/// The original bindings of duplicates get a "first defined here" diagnostic.
+pub fn diagnostics(file: &Path, message: &str) -> Vec<Diagnostic> {
+    let originals: Vec<String> = DuplicateBinding::parse(message)
+        .into_iter()
+        .filter_map(|b| b.original)
+        .collect();
+    let mut entries: Vec<(String, u32, u32, Vec<&str>)> = Vec::new();
+    for line in message.lines() {
+        if let Some((f, l, c)) = agda_location(line) {
+            let text = if originals.iter().any(|o| o == line.trim()) {
+                vec!["first defined here"]
+            } else {
+                Vec::new()
+            };
+            entries.push((f.to_owned(), l, c, text));
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('\u{2014}') {
+            continue;
+        }
+        match entries.last_mut() {
+            Some((_, _, _, text)) => text.push(line.trim()),
+            None => entries.push((file.display().to_string(), 1, 1, vec![line.trim()])),
+        }
+    }
+    entries
+        .into_iter()
+        .map(|(file, line, column, text)| Diagnostic {
+            file,
+            line,
+            column,
+            message: text.join(" "),
+        })
+        .collect()
+}
+
+/// Whitespace lints come as `line:column: message`.
+fn lint_diagnostic(file: &Path, message: &str) -> Option<Diagnostic> {
+    let mut parts = message.splitn(3, ':');
+    let line = parts.next()?.parse().ok()?;
+    let column = parts.next()?.parse().ok()?;
+    let message = parts.next()?.trim().to_owned();
+    let file = file.display().to_string();
+    Some(Diagnostic {
+        file,
+        line,
+        column,
+        message,
+    })
+}
+
+/// A warning, a whitespace lint or one of Agda's, which come as `file:line,column: message`.
+fn warning_diagnostic(file: &Path, message: &str) -> Option<Diagnostic> {
+    if let Some(d) = lint_diagnostic(file, message) {
+        return Some(d);
+    }
+    let (place, text) = message.split_once(": ")?;
+    let (file, line, column) = agda_location(place)?;
+    let text: Vec<&str> = text.lines().map(str::trim).collect();
+    Some(Diagnostic {
+        file: file.to_owned(),
+        line,
+        column,
+        message: text.join(" "),
+    })
+}
+
+/// Quickfix entries (`%f:%l:%c: %m`) out of an error message.
+pub fn quickfix_lines(file: &Path, message: &str) -> Vec<String> {
+    diagnostics(file, message)
+        .into_iter()
+        .map(|d| format!("{}:{}:{}: {}", d.file, d.line, d.column, d.message))
+        .collect()
+}
+
+/// Writes the diagnostics of each load in Vim's errorformat,
+/// so `:cfile` can jump to them. Used with `--vim-quickfix`.
+pub struct Quickfix {
+    inner: Box<dyn Renderer>,
+    file: PathBuf,
+    path: PathBuf,
+    /// Warnings since the last load.
+    warnings: Vec<String>,
+}
+
+impl Quickfix {
+    /// `file` is the Agda module, `path` is where the quickfix list goes.
+    pub fn new(inner: Box<dyn Renderer>, file: PathBuf, path: PathBuf) -> Self {
+        Self {
+            inner,
+            file,
+            path,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, mut entries: Vec<String>) {
+        entries.append(&mut self.warnings);
+        if let Err(e) = save_lines(&self.path, &entries) {
+            let message = format!("cannot write {}: {}", self.path.display(), e);
+            self.inner.warning(&message);
+        }
+    }
+}
+
+impl Renderer for Quickfix {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        self.inner.goals(goals, hidden);
+        self.write(Vec::new());
+    }
+
+    fn error(&mut self, message: &str) {
+        self.inner.error(message);
+        let entries = quickfix_lines(&self.file, message);
+        self.write(entries);
+    }
+
+    fn complain(&mut self, message: &str) {
+        self.inner.complain(message)
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.inner.warning(message);
+        if let Some(d) = warning_diagnostic(&self.file, message) {
+            let entry = format!("{}:{}:{}: warning: {}", d.file, d.line, d.column, d.message);
+            self.warnings.push(entry);
+        }
+    }
+
+    fn info(&mut self, message: &str) {
+        self.inner.info(message)
+    }
+
+    fn loading(&mut self) {
+        self.inner.loading()
+    }
+
+    fn loaded(&mut self) {
+        self.inner.loaded()
+    }
+}
+
+/// A SARIF result, `level` is one of `error`, `warning` and `note`.
+fn sarif_result(rule: &str, level: &str, d: &Diagnostic) -> Value {
+    json!({
+        "ruleId": rule,
+        "level": level,
+        "message": { "text": d.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": d.file },
+                "region": { "startLine": d.line, "startColumn": d.column },
+            },
+        }],
+    })
+}
+
+/// The SARIF log of one load: the errors, the warnings and the remaining holes.
+/// Holes are reported where their goals start, or at the start of `file`
+/// when Agda doesn't tell (before 2.6.1).
+pub fn sarif_log(
+    file: &Path,
+    errors: &[Diagnostic],
+    warnings: &[Diagnostic],
+    goals: &[GoalLine],
+) -> Value {
+    let mut results = Vec::with_capacity(errors.len() + warnings.len() + goals.len());
+    results.extend(
+        errors
+            .iter()
+            .map(|d| sarif_result("agda.error", "error", d)),
+    );
+    results.extend(
+        warnings
+            .iter()
+            .map(|d| sarif_result("agda.warning", "warning", d)),
+    );
+    results.extend(goals.iter().map(|g| {
+        let start = g.goal.range.first().and_then(|i| i.start.line_col());
+        let (line, column) = start.unwrap_or((1, 1));
+        let hole = Diagnostic {
+            file: file.display().to_string(),
+            line,
+            column,
+            message: format!("Unsolved goal {}", goal_line(g)),
+        };
+        sarif_result("agda.hole", "note", &hole)
+    }));
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": { "driver": {
+                "name": "agda-tac",
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": "https://github.com/ice1000/agda-mode",
+            } },
+            "results": results,
+        }],
+    })
+}
+
+/// Writes the diagnostics and holes of each load as SARIF, once the load is over,
+/// for code-review tools in CI. Used with `--sarif`.
+pub struct Sarif {
+    inner: Box<dyn Renderer>,
+    file: PathBuf,
+    path: PathBuf,
+    /// If a load is going on, what's rendered outside of one isn't logged.
+    loading: bool,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    /// The goals with their notes.
+    goals: Vec<(Goal, Vec<String>)>,
+}
+
+impl Sarif {
+    /// `file` is the Agda module, `path` is where the log goes.
+    pub fn new(inner: Box<dyn Renderer>, file: PathBuf, path: PathBuf) -> Self {
+        Self {
+            inner,
+            file,
+            path,
+            loading: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            goals: Vec::new(),
+        }
+    }
+
+    fn write(&mut self) {
+        let goals: Vec<GoalLine> = self
+            .goals
+            .iter()
+            .map(|(goal, notes)| GoalLine { goal, notes })
+            .collect();
+        let log = sarif_log(&self.file, &self.errors, &self.warnings, &goals);
+        if let Err(e) = fs::write(&self.path, format!("{:#}\n", log)) {
+            let message = format!("cannot write {}: {}", self.path.display(), e);
+            self.inner.warning(&message);
+        }
+    }
+}
+
+impl Renderer for Sarif {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        self.inner.goals(goals, hidden);
+        if self.loading {
+            let goals = goals.iter().map(|g| (g.goal.clone(), g.notes.to_vec()));
+            self.goals = goals.collect();
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        self.inner.error(message);
+        if self.loading {
+            self.errors.extend(diagnostics(&self.file, message));
+        }
+    }
+
+    fn complain(&mut self, message: &str) {
+        self.inner.complain(message)
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.inner.warning(message);
+        if self.loading {
+            self.warnings
+                .extend(warning_diagnostic(&self.file, message));
+        }
+    }
+
+    fn info(&mut self, message: &str) {
+        self.inner.info(message)
+    }
+
+    fn loading(&mut self) {
+        self.loading = true;
+        self.errors.clear();
+        self.warnings.clear();
+        self.goals.clear();
+        self.inner.loading()
+    }
+
+    fn loaded(&mut self) {
+        if self.loading {
+            self.loading = false;
+            self.write();
+        }
+        self.inner.loaded()
+    }
+}
+
+/// Keeps the plain text of everything, for testing.
+/// [`with`](Self::with) keeps the text of a renderer instead, one entry per call.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Capture {
+    pub lines: Vec<String>,
+    text: Option<fn(Output) -> String>,
+}
+
+#[cfg(test)]
+impl Capture {
+    /// Keep what `text` makes of each call, like [`plain_text`](self::plain_text).
+    pub fn with(text: fn(Output) -> String) -> Self {
+        Capture {
+            lines: Vec::new(),
+            text: Some(text),
+        }
+    }
+
+    /// Keep the text of `output` if there's a renderer, returns if there's one.
+    fn text_of(&mut self, output: Output) -> bool {
+        match self.text {
+            Some(text) => self.lines.push(text(output)),
+            None => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+impl Renderer for Capture {
+    fn goals(&mut self, goals: &[GoalLine], hidden: usize) {
+        if !self.text_of(Output::Goals(goals, hidden)) {
+            self.lines.extend(goal_lines(goals, hidden))
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        if !self.text_of(Output::Error(message)) {
+            self.lines.push("Errors:".to_owned());
+            self.lines.push(message.to_owned());
+        }
+    }
+
+    fn complain(&mut self, message: &str) {
+        if !self.text_of(Output::Complaint(message)) {
+            self.lines.push(message.to_owned())
+        }
+    }
+
+    fn warning(&mut self, message: &str) {
+        if !self.text_of(Output::Warning(message)) {
+            self.lines.push(format!("Warning: {}", message))
+        }
+    }
+
+    fn info(&mut self, message: &str) {
+        if !self.text_of(Output::Info(message)) {
+            self.lines.push(message.to_owned())
+        }
+    }
+}
@@ -1,37 +1,152 @@
-use agda_mode::agda::ReplState;
-use agda_mode::base::InteractionPoint;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use agda_mode::agda::{NextResult, ReplState};
+use agda_mode::base::{InteractionPoint, Rewrite, UseForce};
 use agda_mode::cmd::{Cmd, GoalInput};
-use agda_mode::resp::{DisplayInfo, GoalInfo};
+use agda_mode::resp::{
+    AllGoalsWarnings, DisplayInfo, Goal, Intro, LabeledRange, MakeCase, ResponseContextEntry,
+    TcWarning,
+};
 
-use crate::file_io::{Monad, Repl};
-use crate::input::UserInput;
-use crate::interact::help;
+use crate::annotate::{annotated, annotation_line};
+use crate::background::Finding;
+use crate::diff::{diff_words, word_diff_line};
+use crate::effects::Effect;
+use crate::file_io::{write_atomically, Monad, Repl};
+use crate::holes::{hole_at, hole_column, replace_hole};
+use crate::imports::{format_imports, import_block};
+use crate::input::{GoalRange, UserInput, GOAL_LIST_LIMIT};
+use crate::lint::{fix, lint};
+use crate::occurrences::occurrences;
+use crate::pragmas::is_shown;
+use crate::profile;
+use crate::project;
+use crate::render::{diagnostics, snippet, GoalLine, Renderer};
+use crate::scope::{self, mentions, module_params};
+use crate::shell;
+use crate::solutions;
+use crate::spinner::Spinner;
+use crate::split::{self, PendingSplit};
+use crate::stdlib::{self, NameIndex};
+use crate::trace;
 
 pub async fn line(agda: &mut Repl, line: &str) -> Monad<bool> {
-    line_impl(agda, UserInput::from(line)).await
+    let commands = match agda.config.expand(line) {
+        Ok(commands) => commands,
+        Err(message) => {
+            agda.render.complain(&message);
+            return Ok(false);
+        }
+    };
+    for command in commands {
+        // Bookmarked commands are run like they were typed.
+        let bookmarked = match UserInput::from(command.as_str()) {
+            UserInput::BookmarkRun(name) => agda.bookmarks.command(name).map(ToOwned::to_owned),
+            _ => None,
+        };
+        let commands = match bookmarked {
+            Some(bookmarked) => match agda.config.expand(&bookmarked) {
+                Ok(commands) => commands,
+                Err(message) => {
+                    agda.render.complain(&message);
+                    return Ok(false);
+                }
+            },
+            None => vec![command],
+        };
+        for command in commands {
+            if line_impl(agda, UserInput::from(command.as_str())).await? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
 }
 
 async fn line_impl<'a>(agda: &mut Repl, line: UserInput<'a>) -> Monad<bool> {
-    use UserInput::*;
-    match line {
-        Define(function_name) => {
-            agda.append_line(format!("{} : ?", function_name))?;
-            agda.append_line(format!("{} = ?", function_name))?;
-            reload(agda).await?;
-        }
-        Give(i, new) => {
-            let command = Cmd::give(GoalInput::no_range(i, new.to_owned()));
-            agda.agda.command(command).await?;
-            // TODO: write to buffer
-            // TODO: check for error message & successful give result
+    for effect in agda.core().effects(line) {
+        if run(agda, effect).await? {
+            return Ok(true);
         }
+    }
+    Ok(false)
+}
+
+/// Carry out an effect, returns `true` if we should stop.
+async fn run<'a>(agda: &mut Repl, effect: Effect<'a>) -> Monad<bool> {
+    use Effect::*;
+    // Everything from now on is about the checked file.
+    settle(agda).await?;
+    show_findings(agda);
+    match effect {
+        AppendLine(line) => agda.append_line_buffer(line),
+        SetLine(line_num, line) => agda.set_line_buffer(line_num, line),
+        RemoveLine(line_num) => drop(agda.remove_line_buffer(line_num)),
+        InsertLine(line_num, line) => agda.insert_line_buffer(line_num, line),
+        Sync => agda.sync_buffer()?,
+        Write => agda.write_buffer()?,
         Reload => reload(agda).await?,
-        Help => {
-            println!("{}", help(agda.is_plain));
-            // TODO: info for commands.
+        ForceReload => {
+            agda.set_loaded(false);
+            reload(agda).await?
+        }
+        Query(input) => query(agda, input).await?,
+        ShowGoals(range) => list_goals(&mut *agda.render, &agda.goals.goals(), range),
+        Info(message) => agda.render.info(&message),
+        Warning(message) => agda.render.warning(&message),
+        Complain(message) => agda.render.complain(&message),
+        Shell(command) => {
+            let dir = agda.path.parent().unwrap_or_else(|| Path::new("."));
+            match shell::run(command, dir, &mut *agda.render) {
+                Ok(status) if status.success() => {}
+                Ok(status) => agda
+                    .render
+                    .warning(&format!("`{}` failed ({}).", command, status)),
+                Err(e) => agda
+                    .render
+                    .error(&format!("Cannot run `{}`: {}", command, e)),
+            }
+        }
+        Recreate => match agda.write_buffer() {
+            Ok(()) => {
+                agda.render.info("Recreated the file from the buffer.");
+                reload(agda).await?
+            }
+            Err(e) => agda
+                .render
+                .error(&format!("Cannot write {}: {}", agda.path.display(), e)),
+        },
+        Rebind(file) => {
+            let dir = agda.path.parent().unwrap_or_else(|| Path::new("."));
+            match dir.join(file).canonicalize() {
+                Ok(path) if path.is_file() => match agda.rebind(path) {
+                    Ok(Ok(())) => {
+                        agda.render.info(&format!("Working on `{}` now.", file));
+                        reload(agda).await?
+                    }
+                    Ok(Err(pid)) => {
+                        let message = format!(
+                            "Wait, another agda-tac (process {}) has `{}` open.",
+                            pid, file
+                        );
+                        agda.render.complain(&message)
+                    }
+                    Err(e) => agda
+                        .render
+                        .error(&format!("Cannot lock or read `{}`: {}", file, e)),
+                },
+                _ => {
+                    let message = format!(
+                        "Wait, there's no `{}`, `recreate` writes the buffer back instead.",
+                        file
+                    );
+                    agda.render.complain(&message)
+                }
+            }
         }
-        Unknown(Some(err)) => println!("Wait, {}", err),
-        Unknown(None) => println!("Sorry, I don't understand."),
         Exit => {
             finish(&mut agda.agda).await?;
             return Ok(true);
@@ -40,48 +155,1444 @@ async fn line_impl<'a>(agda: &mut Repl, line: UserInput<'a>) -> Monad<bool> {
     Ok(false)
 }
 
+/// The commands that need Agda's answers.
+async fn query<'a>(agda: &mut Repl, input: UserInput<'a>) -> Monad {
+    use UserInput::*;
+    match input {
+        Give(i, expr, force) => {
+            let given = give(agda, i, expr, force).await?;
+            if given {
+                list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+            }
+        }
+        Intro(i) => {
+            let given = intro(agda, i).await?;
+            if given {
+                list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+            }
+        }
+        Trace(i, expr) => trace(agda, i, expr).await?,
+        Profile => profile(agda).await?,
+        Accept(i) => match agda.goals.suggestion(i).map(ToOwned::to_owned) {
+            Some(term) => {
+                let given = give(agda, i, &term, UseForce::WithoutForce).await?;
+                if given {
+                    list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+                }
+            }
+            None => {
+                let message = format!("Wait, Auto has nothing for ?{}.", i);
+                agda.render.complain(&message);
+            }
+        },
+        Solve => solve(agda).await?,
+        Apply(path) => apply(agda, path).await?,
+        Pick(n) => pick(agda, n).await?,
+        ProjectGoals => project_goals(agda).await?,
+        FmtImports => fmt_imports(agda).await?,
+        Split(i, variable, auto) => split(agda, i, variable, auto).await?,
+        ApplySplit => match agda.split.take() {
+            Some(pending) => apply_split(agda, pending).await?,
+            None => agda
+                .render
+                .complain("Wait, there's no split to apply, try `split 0 x`."),
+        },
+        Occurrences(name) => {
+            let ending = agda.line_ending.as_str().len();
+            let highlighting = agda.agda.highlighting();
+            let found = occurrences(agda.buffer(), ending, highlighting, name);
+            if found.is_empty() {
+                let message = format!("No occurrences of {}, maybe try `reload`.", name);
+                agda.render.info(&message);
+                return Ok(());
+            }
+            let lines: Vec<String> = found
+                .iter()
+                .map(|o| {
+                    let line = agda.buffer()[o.line - 1].trim();
+                    format!("{}:{}: {}", o.line, o.column, line)
+                })
+                .collect();
+            agda.render.info(&lines.join("\n"));
+        }
+        ExportGoals(path) => export_goals(agda, path).await?,
+        Preview(code) => preview(agda, code).await?,
+        Alias(definition) => match agda.config.define(definition) {
+            Ok(()) => agda.render.info("Defined for this session."),
+            Err(message) => agda.render.complain(&message),
+        },
+        BookmarkAdd(name, text) => match agda.bookmarks.add(name, text) {
+            Ok(()) => agda.render.info(&format!("Bookmarked `{}`.", name)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                agda.render.complain(&e.to_string())
+            }
+            Err(e) => agda.render.error(&bookmarks_unsaved(e)),
+        },
+        BookmarkRemove(name) => match agda.bookmarks.remove(name) {
+            Ok(true) => agda.render.info(&format!("Removed `{}`.", name)),
+            Ok(false) => agda
+                .render
+                .complain(&format!("There's no bookmark `{}`.", name)),
+            Err(e) => agda.render.error(&bookmarks_unsaved(e)),
+        },
+        BookmarkRun(name) => match agda.bookmarks.get(name).map(ToOwned::to_owned) {
+            Some(expr) => match normalise(&mut agda.agda, &expr).await? {
+                Ok(normal) => agda.render.info(&format!("{} = {}", expr, normal)),
+                Err(err_msg) => agda.render.error(&err_msg),
+            },
+            None => agda
+                .render
+                .complain(&format!("There's no bookmark `{}`.", name)),
+        },
+        Bookmarks => {
+            let lines: Vec<String> = agda
+                .bookmarks
+                .iter()
+                .map(|(name, text)| format!("{} = {}", name, text))
+                .collect();
+            if lines.is_empty() {
+                agda.render
+                    .info("No bookmarks, add one with `bookmark add`.");
+            } else {
+                agda.render.info(&lines.join("\n"));
+            }
+        }
+        Set(key, value) => match agda.config.set(key, value) {
+            Ok(()) => {
+                apply_config(agda)?;
+                agda.render.info("Set for this session.");
+            }
+            Err(message) => agda.render.complain(&message),
+        },
+        Type(i, false) => match goal_type(&mut agda.agda, i, agda.config.rewrite.goals).await? {
+            Ok(ty) => agda.render.info(&ty),
+            Err(err_msg) => agda.render.error(&err_msg),
+        },
+        Type(i, true) => {
+            let as_is = match goal_type(&mut agda.agda, i, Rewrite::AsIs).await? {
+                Ok(ty) => ty,
+                Err(err_msg) => {
+                    agda.render.error(&err_msg);
+                    return Ok(());
+                }
+            };
+            match goal_type(&mut agda.agda, i, Rewrite::Normalised).await? {
+                Ok(normal) => {
+                    let diff = word_diff_line(&diff_words(&as_is, &normal));
+                    agda.render.info(&format!("As is:      {}", as_is));
+                    agda.render.info(&format!("Normalised: {}", normal));
+                    if as_is == normal {
+                        agda.render.info("Normalisation doesn't change anything.");
+                    } else {
+                        agda.render.info(&format!("Changes:    {}", diff));
+                    }
+                }
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Context(i, relevant) => {
+            let input = GoalInput::simple(i);
+            let rewrite = agda.config.rewrite.context;
+            agda.agda.command(Cmd::Context { rewrite, input }).await?;
+            match agda.agda.next_context().await? {
+                Ok(context) => show_context(agda, i, &context, relevant),
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Infer(expr) => {
+            let rewrite = agda.config.rewrite.infer;
+            match agda.agda.infer_with(expr, rewrite).await? {
+                Ok(inferred) => agda.render.info(&format!("{} : {}", expr, inferred.expr)),
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Contents(module) => {
+            let rewrite = agda.config.rewrite.search;
+            match agda.agda.module_contents(module, rewrite).await? {
+                Ok(contents) => {
+                    let mut lines = Vec::new();
+                    let params = contents.parameters();
+                    if !params.is_empty() {
+                        lines.push("Parameters:".to_owned());
+                        lines.extend(params.iter().map(|p| format!("  {}", p)));
+                    } else if !contents.telescope.is_empty() {
+                        lines.push(format!("Parameters: {}", contents.telescope));
+                    }
+                    if !contents.names.is_empty() {
+                        lines.push(format!("Modules: {}", contents.names.join(", ")));
+                    }
+                    agda.remember_names(contents.contents.iter().map(|r| r.name.clone()));
+                    let names = contents.contents.iter();
+                    lines.extend(names.map(|r| format!("{} : {}", r.name, r.term)));
+                    if lines.is_empty() {
+                        lines.push(format!("{} is empty.", module));
+                    }
+                    agda.render.info(&lines.join("\n"))
+                }
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Search(names) => {
+            let rewrite = agda.config.rewrite.search;
+            match agda.agda.search_about(names, rewrite).await? {
+                Ok(results) if results.is_empty() => agda.render.info("Nothing found."),
+                Ok(results) => {
+                    agda.remember_names(results.iter().map(|r| r.name.clone()));
+                    let lines: Vec<String> = results
+                        .iter()
+                        .map(|r| format!("{} : {}", r.name, r.term))
+                        .collect();
+                    agda.render.info(&lines.join("\n"))
+                }
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        DiffExpr(e1, e2) => {
+            let n1 = match normalise(&mut agda.agda, e1).await? {
+                Ok(n) => n,
+                Err(err_msg) => {
+                    agda.render.error(&err_msg);
+                    return Ok(());
+                }
+            };
+            match normalise(&mut agda.agda, e2).await? {
+                Ok(n2) if n1 == n2 => {
+                    let message = format!("Both are {}", n1);
+                    agda.render.info(&message)
+                }
+                Ok(n2) => {
+                    agda.render.info(&format!("First:   {}", n1));
+                    agda.render.info(&format!("Second:  {}", n2));
+                    let diff = word_diff_line(&diff_words(&n1, &n2));
+                    agda.render.info(&format!("Changes: {}", diff));
+                }
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Meta(name) => meta(agda, name).await?,
+        Annotate(expr) => match normalise(&mut agda.agda, expr).await? {
+            Ok(value) => {
+                agda.append_line_buffer(annotation_line(expr, &value));
+                agda.sync_buffer()?;
+                reload(agda).await?;
+            }
+            Err(err_msg) => agda.render.error(&err_msg),
+        },
+        AnnotateGoal(i) => {
+            let line_num = match agda.goals.line_of(i) {
+                Some(line_num) => line_num,
+                None => {
+                    let message = format!("Wait, I don't know where ?{} is, try `reload`.", i);
+                    agda.render.complain(&message);
+                    return Ok(());
+                }
+            };
+            match goal_type(&mut agda.agda, i, agda.config.rewrite.goals).await? {
+                Ok(ty) => {
+                    let line = annotated(agda.get_line_buffer(line_num), &ty);
+                    agda.set_line_buffer(line_num, line);
+                    agda.sync_buffer()?;
+                    reload(agda).await?;
+                }
+                Err(err_msg) => agda.render.error(&err_msg),
+            }
+        }
+        Last(kind) => last(agda, kind),
+        Stderr => {
+            let lines = agda.agda.stderr_lines();
+            if lines.is_empty() {
+                agda.render.info("Agda didn't print anything to stderr.");
+            }
+            for line in lines {
+                agda.render.info(&line);
+            }
+            for line in agda.agda.protocol_log() {
+                agda.render.warning(line);
+            }
+        }
+        // Done by `ReplCore` alone, they never come as a query.
+        Define(..) | Reload(..) | Write | Warnings(..) | ListGoals(..) | StripAnnotations
+        | ConvertHoles(..) | Lint(..) | Help | Shell(..) | Recreate | Rebind(..) | Exit(..)
+        | Unknown(..) => {}
+    }
+    Ok(())
+}
+
+fn bookmarks_unsaved(e: io::Error) -> String {
+    format!("Cannot write the bookmarks: {}", e)
+}
+
 pub async fn reload(agda: &mut Repl) -> Monad {
-    let da = &mut agda.agda;
-    da.reload_file().await?;
-    poll_goals(da).await
+    agda.render.loading();
+    check_whitespace(agda)?;
+    if agda.is_loaded() {
+        list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+        agda.render
+            .info("Unchanged since the last load, `reload!` checks it again.");
+        agda.render.loaded();
+        return Ok(());
+    }
+    if agda.config.scope_first {
+        if !scope_check(agda).await? {
+            return Ok(());
+        }
+        load(agda).await?;
+        agda.checking = true;
+        agda.render
+            .info("The scope is fine, type-checking in the background.");
+        return Ok(());
+    }
+    load(agda).await?;
+    poll_goals(agda).await
 }
 
-pub async fn poll_goals(agda: &mut ReplState) -> Monad {
-    match agda.next_goals().await? {
-        Ok(iis) => {
-            if iis.is_empty() {
-                println!("No goals.");
-            } else {
-                println!("Goals:");
+/// Wait for the type-check [`reload`](self::reload) left running, if there's one,
+/// and show its goals and errors.
+pub async fn settle(agda: &mut Repl) -> Monad {
+    if agda.checking {
+        agda.checking = false;
+        poll_goals(agda).await?;
+    }
+    Ok(())
+}
+
+/// Agda only checks the scope then, which is much faster than type-checking.
+const ONLY_SCOPE_CHECKING: &str = "--only-scope-checking";
+
+/// Load the buffer with only its scope checked, and show the errors.
+/// Returns if the scope is fine, and the file can be type-checked.
+async fn scope_check(agda: &mut Repl) -> Monad<bool> {
+    load_with_flags(agda, vec![ONLY_SCOPE_CHECKING.to_owned()]).await?;
+    match agda.agda.next_all_goals_warnings().await? {
+        Ok(_) => Ok(true),
+        Err(err_msg) => {
+            show_load_error(agda, err_msg)?;
+            agda.render.loaded();
+            Ok(false)
+        }
+    }
+}
+
+/// Tell the Agda state about the options of the config it cares about.
+pub fn apply_config(agda: &mut Repl) -> Monad {
+    agda.agda.set_verbosity(agda.config.verbosity);
+    let log = agda.config.running_log.as_deref();
+    agda.agda.log_running_info(log)
+}
+
+/// How often the running info is shown without a spinner.
+const RUNNING_INFO_EVERY: Duration = Duration::from_secs(1);
+
+/// Ask Agda to load the buffer: the file, or a shadow copy if the buffer
+/// has edits that are not saved yet.
+async fn load(agda: &mut Repl) -> Monad {
+    load_with_flags(agda, vec![]).await
+}
+
+/// Like [`load`](self::load), with Agda's command line `flags`.
+async fn load_with_flags(agda: &mut Repl, flags: Vec<String>) -> Monad {
+    agda.set_loaded(false);
+    if agda.is_unsaved() {
+        let contents = agda.contents();
+        agda.agda.load_shadow_with_flags(&contents, flags).await
+    } else {
+        agda.agda.reload_file_with_flags(flags).await
+    }
+}
+
+pub async fn poll_goals(agda: &mut Repl) -> Monad {
+    let spinner = if agda.spinner {
+        Some(Spinner::start(agda.agda.running_info()))
+    } else if agda.config.verbosity.is_some() {
+        Some(Spinner::lines(agda.agda.running_info(), RUNNING_INFO_EVERY))
+    } else {
+        None
+    };
+    let all = agda.agda.next_all_goals_warnings().await?;
+    drop(spinner);
+    agda.background.invalidate();
+    match all {
+        Ok(all) => {
+            agda.errors = 0;
+            agda.has_loaded = true;
+            show_warnings(agda, &all);
+            for goal in cache_goals(agda, all) {
+                let message = format!(
+                    "\u{2713} solved ?{} (was: {})",
+                    goal.interaction_point, goal.goal_type
+                );
+                agda.render.info(&message);
+            }
+            if agda.config.speculative_auto {
+                speculate(agda);
             }
-            list_goals(agda, &iis).await?;
+            agda.set_loaded(true);
+            list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+            if agda.bell_on_done {
+                bell()?;
+            }
+        }
+        Err(err_msg) => show_load_error(agda, err_msg)?,
+    }
+    agda.render.loaded();
+    Ok(())
+}
+
+/// Agda only knows the shadow copy when there are unsaved edits,
+/// the messages should name the file.
+fn unshadowed(agda: &Repl, message: String) -> String {
+    match agda.agda.shadow() {
+        Some(shadow) => message.replace(shadow, &agda.agda.file),
+        None => message,
+    }
+}
+
+fn show_load_error(agda: &mut Repl, err_msg: String) -> Monad {
+    let err_msg = unshadowed(agda, err_msg);
+    agda.errors = diagnostics(&agda.path, &err_msg).len().max(1);
+    agda.has_loaded = true;
+    agda.render.error(&err_msg);
+    show_snippets(agda, &err_msg);
+    if agda.bell_on_fail {
+        bell()?;
+    }
+    Ok(())
+}
+
+/// Try Auto on every goal in the background, the goals it solves are marked
+/// when they are [found](self::show_findings), so they can be `accept`ed.
+fn speculate(agda: &mut Repl) {
+    let goals: Vec<InteractionPoint> = agda
+        .goals
+        .goals()
+        .iter()
+        .map(|g| g.goal.interaction_point)
+        .collect();
+    if !goals.is_empty() {
+        let file = agda.agda.file.clone();
+        agda.background.speculate(file, agda.contents(), goals);
+    }
+}
+
+/// Mark the goals the background Agda found solvable since the last time.
+pub fn show_findings(agda: &mut Repl) {
+    for finding in agda.background.findings() {
+        match finding {
+            Finding::Solvable { goal, term } => {
+                let message = format!(
+                    "Auto solves ?{} with `{}`, `accept {}` gives it.",
+                    goal, term, goal
+                );
+                agda.render.info(&message);
+                agda.goals.suggest(goal, term);
+            }
+            Finding::Broken(message) => {
+                let message = format!("The background Agda stopped: {}", message);
+                agda.render.warning(&message);
+            }
+        }
+    }
+}
+
+/// The warnings of a load, except the ones the config ignores.
+/// Duplicate bindings are told with the place of the first one.
+fn show_warnings(agda: &mut Repl, all: &AllGoalsWarnings) {
+    let bindings = all.duplicate_bindings();
+    let mut messages: Vec<String> = bindings.iter().map(ToString::to_string).collect();
+    let places: Vec<&str> = bindings
+        .iter()
+        .flat_map(|b| b.location.iter().chain(&b.original))
+        .map(String::as_str)
+        .collect();
+    for d in diagnostics(&agda.path, &all.warnings_text()) {
+        let place = format!("{}:{},{}", d.file, d.line, d.column);
+        let at = |p: &&str| *p == place || p.starts_with(&format!("{}-", place));
+        if !places.iter().any(at) {
+            messages.push(format!("{}: {}", place, d.message));
+        }
+    }
+    for message in messages {
+        let message = unshadowed(agda, message);
+        if is_shown(&agda.config.ignored_warnings, &message) {
+            agda.render.warning(&message);
+        }
+    }
+}
+
+/// Give `expr` to a goal and write it into the hole, returns if it worked.
+/// The `?`s in it are the new goals Agda made.
+/// When a name is not in scope, an import of the standard library is suggested,
+/// or added with `auto-import`, and `expr` is given again.
+async fn give(agda: &mut Repl, i: InteractionPoint, expr: &str, force: UseForce) -> Monad<bool> {
+    let message = match give_once(agda, i, expr, force).await? {
+        Ok(given) => return Ok(given),
+        Err(message) => message,
+    };
+    agda.render.error(&message);
+    let name = match stdlib::not_in_scope(&message) {
+        Some(name) => name.to_owned(),
+        None => return Ok(false),
+    };
+    let modules = stdlib_modules(agda, &name);
+    let import = match modules.first() {
+        Some(module) => stdlib::import_line(module, &name),
+        None => return Ok(false),
+    };
+    if !agda.config.auto_import {
+        let message = format!(
+            "`{}` is in {}, `{}` brings it into scope (`set auto-import on` adds it).",
+            name,
+            modules.join(", "),
+            import
+        );
+        agda.render.info(&message);
+        return Ok(false);
+    }
+    // Agda numbers the goals again when the file is loaded with the import,
+    // which goes above the hole.
+    let (line, nth) = match agda.goals.hole_of(i) {
+        Some(hole) => hole,
+        None => return Ok(false),
+    };
+    if !add_import(agda, &import).await? {
+        return Ok(false);
+    }
+    let i = match agda.goals.goal_at(line + 1, nth) {
+        Some(i) => i,
+        None => return Ok(false),
+    };
+    match give_once(agda, i, expr, force).await? {
+        Ok(given) => Ok(given),
+        Err(message) => {
+            agda.render.error(&message);
+            Ok(false)
+        }
+    }
+}
+
+/// Give `expr` to a goal once, the error of Agda if it doesn't fit.
+async fn give_once(
+    agda: &mut Repl,
+    i: InteractionPoint,
+    expr: &str,
+    force: UseForce,
+) -> Monad<Result<bool, String>> {
+    let hole = match hole_of(agda, i) {
+        Some(hole) => hole,
+        None => return Ok(Ok(false)),
+    };
+    let input = GoalInput::no_range(i, expr.to_owned());
+    let text = match agda.agda.give_force(input, force).await? {
+        Ok(give_result) => give_result.text(expr),
+        Err(message) => return Ok(Err(message)),
+    };
+    write_given(agda, i, hole, &text).await.map(Ok)
+}
+
+/// The modules of the standard library defining `name`.
+/// The library is scanned the first time, if the config says where it is.
+fn stdlib_modules(agda: &mut Repl, name: &str) -> Vec<String> {
+    if agda.stdlib.is_none() {
+        let mut index = NameIndex::bundled();
+        if let Some(root) = &agda.config.stdlib {
+            index.scan(root);
+        }
+        agda.stdlib = Some(index);
+    }
+    agda.stdlib
+        .as_ref()
+        .map_or_else(Vec::new, |index| index.modules(name).to_vec())
+}
+
+/// Add an import line and load the file with it, returns if it loads.
+/// The line is taken out again if it doesn't.
+async fn add_import(agda: &mut Repl, import: &str) -> Monad<bool> {
+    let at = stdlib::import_position(agda.buffer());
+    agda.insert_line_buffer(at, import.to_owned());
+    agda.sync_buffer()?;
+    load(agda).await?;
+    match agda.agda.next_all_goals_warnings().await? {
+        Ok(all) => {
+            agda.render.info(&format!("Added `{}`.", import));
+            cache_goals(agda, all);
+            Ok(true)
         }
         Err(err_msg) => {
-            eprintln!("Errors:");
-            eprintln!("{}", err_msg);
+            agda.render.error(&err_msg);
+            agda.render
+                .warning("The file doesn't load with the import, it is taken out.");
+            agda.remove_line_buffer(at);
+            agda.sync_buffer()?;
+            reload(agda).await?;
+            Ok(false)
         }
     }
+}
+
+/// Check `expr` against the goal with the type checker's debug output on,
+/// and show it. The file is loaded as usual afterwards, as the output stays on until then.
+async fn trace(agda: &mut Repl, i: InteractionPoint, expr: &str) -> Monad {
+    if hole_of(agda, i).is_none() {
+        return Ok(());
+    }
+    load_with_flags(agda, vec![trace::FLAG.to_owned()]).await?;
+    let checked = match agda.agda.next_all_goals_warnings().await? {
+        Ok(_) => {
+            agda.agda.capture_running_info();
+            let input = GoalInput::no_range(i, expr.to_owned());
+            let rewrite = agda.config.rewrite.goals;
+            let cmd = Cmd::GoalTypeContextCheck { rewrite, input };
+            agda.agda.command(cmd).await?;
+            agda.agda.next_goal_info().await?
+        }
+        Err(err_msg) => Err(err_msg),
+    };
+    let lines = trace::lines(&agda.agda.captured_running_info());
+    load(agda).await?;
+    if let Err(err_msg) = agda.agda.next_all_goals_warnings().await? {
+        agda.render.error(&err_msg);
+    }
+    if lines.is_empty() {
+        agda.render.info("Agda printed nothing for the check.");
+    } else {
+        agda.render
+            .info(&lines[..lines.len().min(trace::LIMIT)].join("\n"));
+    }
+    if lines.len() > trace::LIMIT {
+        let message = format!(
+            "{} more lines, `set running-log agda.log` keeps all of them.",
+            lines.len() - trace::LIMIT
+        );
+        agda.render.info(&message);
+    }
+    match checked {
+        Ok(_) => agda.render.info(&format!("`{}` fits ?{}.", expr, i)),
+        Err(err_msg) => agda.render.error(&err_msg),
+    }
     Ok(())
 }
 
-async fn finish(agda: &mut ReplState) -> Monad {
-    agda.command(Cmd::Abort).await?;
-    agda.shutdown().await
+/// Give the solutions of the goals Agda can solve alone to their holes,
+/// like the goals' `give`s one after another.
+async fn solve(agda: &mut Repl) -> Monad {
+    let rewrite = agda.config.rewrite.goals;
+    agda.agda.command(Cmd::SolveAll(rewrite)).await?;
+    let solutions = match agda.agda.next_solve_all().await? {
+        Ok(solutions) => solutions,
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            return Ok(());
+        }
+    };
+    if solutions.is_empty() {
+        agda.render.info("Agda cannot solve any goal alone.");
+        return Ok(());
+    }
+    for solution in solutions {
+        let i = solution.interaction_point;
+        if give(agda, i, &solution.expression, UseForce::WithoutForce).await? {
+            let message = format!("Solved ?{} with `{}`.", i, solution.expression);
+            agda.render.info(&message);
+        }
+    }
+    list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+    Ok(())
+}
+
+/// Load the file with Agda's profile on, and show where the time went.
+/// It's loaded as usual afterwards, so the next loads are not slowed down.
+async fn profile(agda: &mut Repl) -> Monad {
+    agda.agda.capture_running_info();
+    load_with_flags(agda, vec![profile::FLAG.to_owned()]).await?;
+    let checked = agda.agda.next_all_goals_warnings().await?;
+    let lines = profile::summary(&agda.agda.captured_running_info());
+    load(agda).await?;
+    if let Err(err_msg) = agda.agda.next_all_goals_warnings().await? {
+        agda.render.error(&err_msg);
+    }
+    match checked {
+        Err(err_msg) => agda.render.error(&err_msg),
+        Ok(_) if lines.is_empty() => agda.render.info("Agda printed no profile."),
+        Ok(_) => agda.render.info(&lines.join("\n")),
+    }
+    Ok(())
+}
+
+/// Where the hole of the goal is, complains if we don't know.
+fn hole_of(agda: &mut Repl, i: InteractionPoint) -> Option<(usize, usize)> {
+    let hole = agda.goals.hole_of(i);
+    if hole.is_none() {
+        let message = format!("Wait, I don't know where ?{} is, try `reload`.", i);
+        agda.render.complain(&message);
+    }
+    hole
 }
 
-async fn list_goals(agda: &mut ReplState, iis: &[InteractionPoint]) -> Monad {
-    for &ii in iis {
-        agda.command(Cmd::goal_type(GoalInput::simple(ii))).await?;
-        let ty = loop {
-            if let DisplayInfo::GoalSpecific {
-                goal_info: GoalInfo::CurrentGoal { the_type, .. },
-                ..
-            } = agda.next_display_info().await?
-            {
-                break the_type;
+/// Let Agda introduce something in the goal, and write it into the hole.
+/// When it could be several constructors, they are listed instead.
+async fn intro(agda: &mut Repl, i: InteractionPoint) -> Monad<bool> {
+    let hole = match hole_of(agda, i) {
+        Some(hole) => hole,
+        None => return Ok(false),
+    };
+    let input = GoalInput::simple(i);
+    agda.agda
+        .command(Cmd::Intro {
+            dunno: false,
+            input,
+        })
+        .await?;
+    match agda.agda.next_intro().await? {
+        Ok(Intro::Given(result)) => write_given(agda, i, hole, &result.text("?")).await,
+        Ok(Intro::NotFound) => {
+            let message = format!("Wait, there's nothing to introduce in ?{}.", i);
+            agda.render.complain(&message);
+            Ok(false)
+        }
+        Ok(Intro::Constructors(constructors)) => {
+            let message = format!(
+                "?{} could be any of {}, `give` one of them.",
+                i,
+                constructors.join(", ")
+            );
+            agda.render.info(&message);
+            Ok(false)
+        }
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            Ok(false)
+        }
+    }
+}
+
+/// Agda took `text` for the goal, write it into the `hole` and remember the new goals.
+async fn write_given(
+    agda: &mut Repl,
+    i: InteractionPoint,
+    (line_num, nth): (usize, usize),
+    text: &str,
+) -> Monad<bool> {
+    let points = match agda.agda.next_goals().await? {
+        Ok(points) => points,
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            return Ok(false);
+        }
+    };
+    let known: Vec<InteractionPoint> = agda
+        .goals
+        .goals()
+        .iter()
+        .map(|g| g.goal.interaction_point)
+        .collect();
+    let new: Vec<InteractionPoint> = points.into_iter().filter(|p| !known.contains(p)).collect();
+    let rewrite = agda.config.rewrite.goals;
+    let mut new_goals = Vec::with_capacity(new.len());
+    for (interaction_point, goal_type) in new.iter().zip(agda.agda.goal_types(&new, rewrite).await?)
+    {
+        let goal_type = goal_type.unwrap_or_else(|e| e);
+        let interaction_point = *interaction_point;
+        new_goals.push(Goal {
+            interaction_point,
+            goal_type,
+            range: vec![],
+        });
+    }
+    let line = agda.get_line_buffer(line_num).clone();
+    match replace_hole(&line, nth, text) {
+        Some(line) => agda.set_line_buffer(line_num, line),
+        None => {
+            agda.render
+                .warning("I cannot find the hole in the file, try `reload`.");
+            return Ok(false);
+        }
+    }
+    agda.sync_buffer()?;
+    agda.cache_given(i, new_goals);
+    Ok(true)
+}
+
+/// Check the buffer with `code` as a new last line, from a shadow file,
+/// and go back to the file, so the goals are the same as before.
+async fn preview(agda: &mut Repl, code: &str) -> Monad {
+    let mut contents = agda.contents();
+    contents.push_str(code);
+    contents.push_str(agda.line_ending.as_str());
+    agda.agda.load_shadow(&contents).await?;
+    let checked = agda.agda.next_all_goals_warnings().await?;
+    let shadow = agda.agda.shadow().unwrap_or_default().to_owned();
+    match checked {
+        Ok(all) => {
+            let notes = Vec::new();
+            let goals: Vec<_> = all
+                .visible_goals
+                .iter()
+                .map(|goal| GoalLine {
+                    goal,
+                    notes: &notes,
+                })
+                .collect();
+            agda.render.info("It checks, the file is not changed.");
+            list_goals(&mut *agda.render, &goals, GoalRange::First);
+        }
+        Err(err_msg) => {
+            let err_msg = err_msg.replace(&shadow, &agda.agda.file);
+            agda.render.error(&err_msg);
+        }
+    }
+    load(agda).await?;
+    if let Err(err_msg) = agda.agda.next_all_goals_warnings().await? {
+        agda.render.error(&err_msg);
+    }
+    Ok(())
+}
+
+/// Replace the lines `start..start + len` of the buffer with `lines`.
+fn replace_lines(agda: &mut Repl, start: usize, len: usize, lines: &[String]) {
+    for _ in 0..len {
+        agda.remove_line_buffer(start);
+    }
+    for (k, line) in lines.iter().enumerate() {
+        agda.insert_line_buffer(start + k, line.clone());
+    }
+}
+
+/// Sort the import block, and put it back if the file doesn't load after that.
+async fn fmt_imports(agda: &mut Repl) -> Monad {
+    let (start, end) = match import_block(agda.buffer()) {
+        Some(block) => block,
+        None => {
+            let message = "I cannot find the imports, or they go over several lines.";
+            agda.render.warning(message);
+            return Ok(());
+        }
+    };
+    let old = agda.buffer()[start..end].to_vec();
+    let new = format_imports(&old, agda.config.separate_open);
+    if new == old {
+        agda.render.info("The imports are tidy already.");
+        return Ok(());
+    }
+    replace_lines(agda, start, old.len(), &new);
+    agda.sync_buffer()?;
+    load(agda).await?;
+    match agda.agda.next_all_goals_warnings().await? {
+        Ok(all) => {
+            let message = format!("{} import lines, {} before.", new.len(), old.len());
+            agda.render.info(&message);
+            show_warnings(agda, &all);
+            cache_goals(agda, all);
+            list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+        }
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            agda.render
+                .warning("The file doesn't load with the sorted imports, they are put back.");
+            replace_lines(agda, start, new.len(), &old);
+            agda.sync_buffer()?;
+            reload(agda).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The goals of the file, then of each file of the project it imports.
+/// Agda only tells the goals of the file it loaded, so the other files are
+/// loaded by the background Agda, and the session's Agda keeps the file.
+async fn project_goals(agda: &mut Repl) -> Monad {
+    let roots = project::source_roots(&agda.path);
+    let files = project::imported_files(&roots, agda.buffer());
+    let own = agda.goals.goals();
+    let mut total = own.len();
+    let name = |path: &Path| {
+        let root = roots.iter().find(|root| path.starts_with(root));
+        root.and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let message = format!("{} ({} goals):", name(&agda.path), own.len());
+    agda.render.info(&message);
+    agda.render.goals(&own, 0);
+    let first = match files.first() {
+        Some(first) => first.to_string_lossy().into_owned(),
+        None => return Ok(()),
+    };
+    let shared = match agda.background.agda(&first).await {
+        Ok(shared) => shared,
+        Err(e) => {
+            let message = format!("Cannot start another Agda for the project: {}", e);
+            agda.render.error(&message);
+            return Ok(());
+        }
+    };
+    for file in &files {
+        let session = shared.session(file.to_string_lossy().into_owned());
+        match session.load().await {
+            Ok(Ok(all)) => {
+                let notes = Vec::new();
+                let goals: Vec<_> = all
+                    .visible_goals
+                    .iter()
+                    .map(|goal| GoalLine {
+                        goal,
+                        notes: &notes,
+                    })
+                    .collect();
+                total += goals.len();
+                let message = format!("{} ({} goals):", name(file), goals.len());
+                agda.render.info(&message);
+                agda.render.goals(&goals, 0);
+            }
+            Ok(Err(err_msg)) => agda.render.error(&err_msg),
+            Err(e) => {
+                agda.background.reset().await;
+                let message = format!("The Agda for the project stopped: {}", e);
+                agda.render.error(&message);
+                return Ok(());
+            }
+        }
+    }
+    let message = format!("{} goals in {} files.", total, files.len() + 1);
+    agda.render.info(&message);
+    Ok(())
+}
+
+/// Ask Agda to split the clause of the goal, and show the clauses.
+/// Unless `auto`, they are only written on `split --apply`.
+async fn split(agda: &mut Repl, i: InteractionPoint, variable: &str, auto: bool) -> Monad {
+    let line_num = match agda.goals.line_of(i) {
+        Some(line_num) => line_num,
+        None => {
+            let message = format!("I cannot find the hole of ?{}, try `reload`.", i);
+            agda.render.warning(&message);
+            return Ok(());
+        }
+    };
+    let input = GoalInput::no_range(i, variable.to_owned());
+    agda.agda.command(Cmd::MakeCase(input)).await?;
+    let clauses = match agda.agda.next_make_case().await? {
+        Ok((MakeCase::Function, clauses)) => clauses,
+        Ok((MakeCase::ExtendedLambda, _)) => {
+            let message = "Sorry, I can only split clauses of functions, not of lambdas.";
+            agda.render.complain(message);
+            return Ok(());
+        }
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            return Ok(());
+        }
+    };
+    let line = agda.buffer()[line_num].clone();
+    let clauses = split::clause_lines(&line, &clauses);
+    agda.render
+        .info(&split::preview(&line, &clauses).join("\n"));
+    let pending = PendingSplit {
+        line_num,
+        line,
+        clauses,
+    };
+    if auto {
+        apply_split(agda, pending).await
+    } else {
+        agda.render
+            .info("Write them with `split --apply`, or edit the line first and `split` again.");
+        agda.split = Some(pending);
+        Ok(())
+    }
+}
+
+/// Replace the line of a split with its clauses, and load the file for the new goals.
+async fn apply_split(agda: &mut Repl, pending: PendingSplit) -> Monad {
+    if agda.buffer().get(pending.line_num) != Some(&pending.line) {
+        agda.render
+            .warning("The line is edited since the split, `split` again.");
+        return Ok(());
+    }
+    agda.remove_line_buffer(pending.line_num);
+    for (k, clause) in pending.clauses.into_iter().enumerate() {
+        agda.insert_line_buffer(pending.line_num + k, clause);
+    }
+    agda.sync_buffer()?;
+    reload(agda).await
+}
+
+/// Write the goals of the last load, where their holes are and their contexts to `path`.
+/// Goals whose holes were edited since are left out.
+async fn export_goals(agda: &mut Repl, path: &str) -> Monad {
+    let goals: Vec<_> = agda.goals.goals().iter().map(|g| g.goal.clone()).collect();
+    let mut exported = Vec::with_capacity(goals.len());
+    for goal in goals {
+        let i = goal.interaction_point;
+        let (line_num, nth) = match agda.goals.hole_of(i) {
+            Some(hole) => hole,
+            None => continue,
+        };
+        let line = agda.buffer().get(line_num);
+        let column = match line.and_then(|line| hole_column(line, nth)) {
+            Some(column) => column,
+            None => continue,
+        };
+        let input = GoalInput::simple(i);
+        let rewrite = agda.config.rewrite.context;
+        agda.agda.command(Cmd::Context { rewrite, input }).await?;
+        let context = match agda.agda.next_context().await? {
+            Ok(context) => context.iter().map(From::from).collect(),
+            Err(err_msg) => {
+                agda.render.error(&err_msg);
+                return Ok(());
             }
         };
-        println!("?{:?}: {}", ii, ty);
+        exported.push(solutions::ExportedGoal {
+            goal: i.id,
+            line: line_num + 1,
+            column,
+            goal_type: goal.goal_type,
+            context,
+        });
     }
+    let text = solutions::export(&agda.path.to_string_lossy(), &exported);
+    if let Err(e) = write_atomically(path.as_ref(), |f| f.write_all(text.as_bytes())) {
+        agda.render
+            .complain(&format!("cannot write {}: {}", path, e));
+        return Ok(());
+    }
+    let stale = agda.goals.stale_count();
+    if stale > 0 {
+        let message = format!("{} edited goals are left out, try `reload`.", stale);
+        agda.render.warning(&message);
+    }
+    let message = format!("Exported {} goals to {}.", exported.len(), path);
+    agda.render.info(&message);
+    Ok(())
+}
+
+/// Give the terms of a solutions file to the holes at their positions.
+/// The ones that fail are tried again after a reload, as they may need the others.
+async fn apply(agda: &mut Repl, path: &str) -> Monad {
+    let all = match fs::read_to_string(path) {
+        Ok(text) => match solutions::parse(&text) {
+            Ok(solutions) => solutions,
+            Err(e) => {
+                agda.render.complain(&format!("{}: {}", path, e));
+                return Ok(());
+            }
+        },
+        Err(e) => {
+            agda.render
+                .complain(&format!("cannot read {}: {}", path, e));
+            return Ok(());
+        }
+    };
+    let (mut pending, mut conflicts) = solutions::conflicts(all, |s| {
+        let line_num = s.line.saturating_sub(1);
+        let line = agda.buffer().get(line_num)?;
+        agda.goals.goal_at(line_num, hole_at(line, s.column)?)
+    });
+    let total = pending.len();
+    for pass in 0..2 {
+        if pass > 0 {
+            if pending.is_empty() {
+                break;
+            }
+            // The goals are numbered anew by the load.
+            let holes: Vec<_> = conflicts
+                .iter()
+                .map(|c| agda.goals.hole_of(c.hole))
+                .collect();
+            reload(agda).await?;
+            let mut found = Vec::with_capacity(conflicts.len());
+            for (mut conflict, hole) in conflicts.into_iter().zip(holes) {
+                match hole.and_then(|(line, nth)| agda.goals.goal_at(line, nth)) {
+                    Some(i) => {
+                        conflict.hole = i;
+                        found.push(conflict);
+                    }
+                    None => {
+                        let message = format!(
+                            "{}:{}: the hole is edited since, skipping it.",
+                            conflict.line, conflict.column
+                        );
+                        agda.render.warning(&message);
+                    }
+                }
+            }
+            conflicts = found;
+        }
+        solutions::bottom_up(&mut pending);
+        let mut failed = Vec::new();
+        for solution in pending {
+            let line_num = solution.line.saturating_sub(1);
+            let goal = agda
+                .buffer()
+                .get(line_num)
+                .and_then(|line| hole_at(line, solution.column))
+                .and_then(|nth| agda.goals.goal_at(line_num, nth));
+            let given = match goal {
+                Some(i) => give(agda, i, &solution.term, UseForce::WithoutForce).await?,
+                None => false,
+            };
+            if !given {
+                failed.push(solution);
+            }
+        }
+        pending = failed;
+    }
+    for s in &pending {
+        let message = format!("{}:{}: cannot give `{}`", s.line, s.column, s.term);
+        agda.render.warning(&message);
+    }
+    let message = format!("Applied {} of {} solutions.", total - pending.len(), total);
+    agda.render.info(&message);
+    list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+    agda.conflicts = conflicts;
+    show_conflict(agda);
     Ok(())
 }
+
+/// The next conflict of `apply`, with how to resolve it.
+fn show_conflict(agda: &mut Repl) {
+    if let Some(conflict) = agda.conflicts.first() {
+        let left = agda.conflicts.len();
+        agda.render.warning(&conflict.choices().join("\n"));
+        let message = format!(
+            "Conflicts left: {}, pick a term with `apply --pick 1`.",
+            left
+        );
+        agda.render.info(&message);
+    }
+}
+
+/// Give the `n`th term of the next conflict of `apply`, and show the one after it.
+async fn pick(agda: &mut Repl, n: usize) -> Monad {
+    let conflict = match agda.conflicts.first() {
+        Some(conflict) => conflict.clone(),
+        None => {
+            let message = "Wait, there's no conflict to resolve, try `apply solutions.json`.";
+            agda.render.complain(message);
+            return Ok(());
+        }
+    };
+    let term = match n.checked_sub(1).and_then(|k| conflict.terms.get(k)) {
+        Some(term) => term.clone(),
+        None => {
+            let message = format!("Wait, pick one of 1 to {}.", conflict.terms.len());
+            agda.render.complain(&message);
+            return Ok(());
+        }
+    };
+    agda.conflicts.remove(0);
+    match agda.goals.hole_of(conflict.hole) {
+        Some(_) => {
+            if give(agda, conflict.hole, &term, UseForce::WithoutForce).await? {
+                list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::First);
+            }
+        }
+        None => {
+            let message = format!(
+                "{}:{}: the hole is edited since, skipping it.",
+                conflict.line, conflict.column
+            );
+            agda.render.warning(&message);
+        }
+    }
+    show_conflict(agda);
+    Ok(())
+}
+
+/// The type of a hidden meta and the constraints mentioning it,
+/// out of the metas and constraints queries.
+pub async fn meta(agda: &mut Repl, name: &str) -> Monad {
+    agda.agda.command(Cmd::Metas).await?;
+    let metas = match agda.agda.next_all_goals_warnings().await? {
+        Ok(all) => all.invisible_goals,
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            return Ok(());
+        }
+    };
+    let constraints = match agda.agda.constraints().await? {
+        Ok(constraints) => constraints,
+        Err(err_msg) => {
+            agda.render.error(&err_msg);
+            return Ok(());
+        }
+    };
+    match metas.iter().find(|m| m.name() == name) {
+        Some(m) => agda.render.info(&format!("{} : {}", name, m.meta_type)),
+        None => {
+            let message = format!("{} is not an unsolved meta (solved, or never there).", name);
+            agda.render.complain(&message)
+        }
+    }
+    let mentions: Vec<&str> = constraints
+        .iter()
+        .filter(|c| c.mentions_meta(name))
+        .map(|c| c.constraint.as_str())
+        .collect();
+    if mentions.is_empty() {
+        agda.render.info("No constraints on it.");
+    } else {
+        agda.render.info("Constraints:");
+        for constraint in mentions {
+            agda.render.info(&format!("  {}", constraint));
+        }
+    }
+    Ok(())
+}
+
+/// The parts of our file an error is about, when there are several.
+fn show_snippets(agda: &mut Repl, err_msg: &str) {
+    let ranges = LabeledRange::parse(err_msg);
+    if ranges.len() < 2 {
+        return;
+    }
+    let snippets: Vec<String> = ranges
+        .iter()
+        .filter(|r| r.file == agda.agda.file)
+        .map(|r| snippet(agda.buffer(), r).join("\n"))
+        .collect();
+    for snippet in snippets {
+        agda.render.info(&snippet);
+    }
+}
+
+/// Remember the goals, and tell the user which ones are stuck on constraints.
+/// Returns the goals solved since the previous load.
+fn cache_goals(agda: &mut Repl, all: AllGoalsWarnings) -> Vec<Goal> {
+    let constraints = all.unsolved_constraints();
+    let blocked: Vec<_> = all
+        .visible_goals
+        .iter()
+        .filter_map(|goal| {
+            let c = constraints.iter().find(|c| c.mentions_goal(goal))?;
+            Some((goal.interaction_point, c.constraint.clone()))
+        })
+        .collect();
+    let previous = std::mem::take(&mut agda.goals);
+    let ranges = all.goal_ranges();
+    agda.cache_goals(all.visible_goals, &ranges);
+    for (ii, constraint) in blocked {
+        let note = format!("blocked by unsolved constraint {}", constraint);
+        agda.goals.annotate(ii, note);
+    }
+    agda.goals.solved_since(&previous)
+}
+
+/// Show the last display info of some kind again,
+/// `kind` can be a short name like `norm`.
+fn last(agda: &mut Repl, kind: &str) {
+    let kind = match kind {
+        "error" => "Error",
+        "norm" | "normal" => "NormalForm",
+        "goals" => "AllGoalsWarnings",
+        "type" | "infer" => "InferredType",
+        "context" => "Context",
+        "time" => "Time",
+        "version" => "Version",
+        kind => kind,
+    };
+    match agda.agda.last_display_info(kind) {
+        None => agda
+            .render
+            .complain(&format!("Wait, there's no `{}` yet.", kind)),
+        Some(info) => show_display_info(&mut *agda.render, info),
+    }
+}
+
+/// Show a display info of Agda, goal lists and errors as such.
+pub fn show_display_info(render: &mut dyn Renderer, info: &DisplayInfo) {
+    match info {
+        DisplayInfo::AllGoalsWarnings(all) => {
+            let goals: Vec<GoalLine> = all
+                .visible_goals
+                .iter()
+                .map(|goal| GoalLine { goal, notes: &[] })
+                .collect();
+            list_goals(render, &goals, GoalRange::All);
+        }
+        DisplayInfo::Error { message, .. } => {
+            let message = message.as_deref().unwrap_or("Unknown error");
+            render.error(message)
+        }
+        info => render.info(&display_info_text(info)),
+    }
+}
+
+/// Plain text of the display infos we know how to show.
+pub fn display_info_text(info: &DisplayInfo) -> String {
+    use DisplayInfo::*;
+    match info {
+        NormalForm { expr, .. } => expr.clone(),
+        InferredType(inferred) => inferred.expr.clone(),
+        Time { time } => time.to_string(),
+        Version { version } => version.clone(),
+        Auto { info } => info.clone(),
+        CompilationOk { warnings, errors } => {
+            format!("{}{}", TcWarning::text(warnings), TcWarning::text(errors))
+        }
+        Error { message, .. } => message.clone().unwrap_or_default(),
+        info => format!("{:?}", info),
+    }
+}
+
+/// Look for whitespace that breaks Agda's layout before loading,
+/// and fix it if the user asked us to.
+fn check_whitespace(agda: &mut Repl) -> Monad {
+    if agda.config.fix_whitespace {
+        if agda.rewrite_lines(fix) {
+            agda.sync_buffer()?;
+        }
+        return Ok(());
+    }
+    for lint in lint(agda.buffer()) {
+        agda.render.warning(&lint.to_string());
+    }
+    Ok(())
+}
+
+/// Ring the terminal bell, so users can do something else during long checks.
+fn bell() -> Monad {
+    // Not on stdout, which is JSON with `--json` and `--json-rpc`.
+    eprint!("\x07");
+    io::stderr().flush()
+}
+
+/// The context of a goal of the file.
+fn show_context(
+    agda: &mut Repl,
+    i: InteractionPoint,
+    context: &[ResponseContextEntry],
+    relevant: bool,
+) {
+    let params = module_params(agda.buffer());
+    let goals = agda.goals.goals();
+    let goal_type = goals
+        .iter()
+        .find(|g| g.goal.interaction_point == i)
+        .map_or("", |g| g.goal.goal_type.as_str());
+    render_context(&mut *agda.render, &params, goal_type, context, relevant);
+}
+
+/// Show the entries that are module `params` apart from the local context,
+/// and point out the ones the goal type talks about.
+/// With `relevant`, only the entries the goal type needs are shown.
+pub fn render_context(
+    render: &mut dyn Renderer,
+    params: &[String],
+    goal_type: &str,
+    context: &[ResponseContextEntry],
+    relevant: bool,
+) {
+    let shown = if relevant {
+        scope::relevant(goal_type, context)
+    } else {
+        context.iter().collect()
+    };
+    let hidden = context.len() - shown.len();
+    let (module, local): (Vec<_>, Vec<_>) = shown
+        .into_iter()
+        .partition(|entry| params.contains(&entry.original_name));
+    // The names are padded, so the types line up like a table.
+    let width = |entries: &[&ResponseContextEntry]| {
+        let names = entries.iter().map(|e| e.reified_name.chars().count());
+        names.max().unwrap_or(0)
+    };
+    let (module_width, local_width) = (width(&module), width(&local));
+    let line = |width: usize| {
+        move |entry: &ResponseContextEntry| {
+            let padding = width - entry.reified_name.chars().count();
+            let mut line = format!(
+                "  {}{} : {}",
+                entry.reified_name,
+                " ".repeat(padding),
+                entry.binding
+            );
+            if !entry.in_scope {
+                line.push_str("  (not in scope)");
+            } else if mentions(goal_type, &entry.reified_name) {
+                line.push_str("  (in the goal type)");
+            }
+            line
+        }
+    };
+    let mut lines = Vec::new();
+    if !module.is_empty() {
+        lines.push("Module parameters:".to_owned());
+        lines.extend(module.into_iter().map(line(module_width)));
+    }
+    if local.is_empty() {
+        lines.push("Context: empty".to_owned());
+    } else {
+        lines.push("Context:".to_owned());
+        lines.extend(local.into_iter().map(line(local_width)));
+    }
+    if hidden > 0 {
+        lines.push(format!("({} more not needed by the goal type)", hidden));
+    }
+    render.info(&lines.join("\n"));
+}
+
+/// Ask Agda for the type of a goal.
+async fn goal_type(
+    agda: &mut ReplState,
+    i: InteractionPoint,
+    rewrite: Rewrite,
+) -> NextResult<String> {
+    let input = GoalInput::simple(i);
+    agda.command(Cmd::GoalType { rewrite, input }).await?;
+    let info = agda.next_goal_info().await?;
+    Ok(info.map(|info| match info.the_type() {
+        Some(the_type) => the_type.to_owned(),
+        None => format!("{:?}", info),
+    }))
+}
+
+/// Ask Agda for the normal form of an expression, in the top level scope.
+async fn normalise(agda: &mut ReplState, code: &str) -> NextResult<String> {
+    let compute_mode = Default::default();
+    let code = code.to_owned();
+    agda.command(Cmd::ComputeToplevel { compute_mode, code })
+        .await?;
+    agda.next_normal_form().await
+}
+
+/// agda-tac exits with this when goals are left, and `0` when the file is done.
+pub const EXIT_GOALS_LEFT: i32 = 2;
+
+/// agda-tac exits with this when the last load failed, whatever the goals are.
+pub const EXIT_LOAD_FAILED: i32 = 3;
+
+/// The exit code of a session, from the last load.
+pub fn exit_code(agda: &Repl) -> i32 {
+    exit_code_of(agda.goals.goals().len(), agda.errors)
+}
+
+/// The exit code after a load with `goals` goals and `errors` errors.
+pub fn exit_code_of(goals: usize, errors: usize) -> i32 {
+    if errors > 0 {
+        EXIT_LOAD_FAILED
+    } else if goals == 0 {
+        0
+    } else {
+        EXIT_GOALS_LEFT
+    }
+}
+
+async fn finish(agda: &mut ReplState) -> Monad {
+    agda.command(Cmd::Abort).await?;
+    agda.shutdown().await
+}
+
+pub fn list_goals(render: &mut dyn Renderer, goals: &[GoalLine], range: GoalRange) {
+    let shown: Vec<GoalLine> = match range {
+        GoalRange::First => goals.iter().take(GOAL_LIST_LIMIT).copied().collect(),
+        GoalRange::All => goals.to_vec(),
+        GoalRange::Between(start, end) => goals
+            .iter()
+            .filter(|g| start <= g.goal.interaction_point && g.goal.interaction_point < end)
+            .copied()
+            .collect(),
+    };
+    render.goals(&shown, goals.len() - shown.len());
+}
@@ -0,0 +1,307 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::future::FutureExt;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::timer::delay_for;
+
+use agda_mode::agda::{AgdaInternalError, AgdaOptions, ReplState};
+use agda_mode::base::InteractionPoint;
+use agda_mode::cmd::{Cmd, GoalInput};
+use agda_mode::resp::{DisplayInfo, Resp};
+
+use crate::file_io::{Monad, Repl};
+use crate::input::GoalRange;
+use crate::listen::{forward, Incoming};
+use crate::render::JsonSink;
+use crate::repl::{line, list_goals, poll_goals, reload, settle};
+
+/// Bumped on every incompatible change of the methods.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Agda rejected the request, like a `give` that doesn't type-check.
+pub const AGDA_ERROR: i64 = 1;
+/// Agda crashed, it is restarted with `--heartbeat`.
+pub const AGDA_INTERNAL_ERROR: i64 = 2;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self { code, message }
+    }
+}
+
+pub type RpcResult = Result<Value, RpcError>;
+
+#[derive(Deserialize)]
+struct GiveParams {
+    goal: InteractionPoint,
+    expr: String,
+}
+
+#[derive(Deserialize)]
+struct CaseParams {
+    goal: InteractionPoint,
+    variable: String,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// The response object of a request, `output` is what agda-tac
+/// would have printed in the meantime.
+pub fn response(id: Value, result: RpcResult, output: Vec<Value>) -> Value {
+    match result {
+        Ok(result) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": { "value": result, "output": output },
+        }),
+        Err(RpcError { code, message }) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message, "data": { "output": output } },
+        }),
+    }
+}
+
+fn agda_error(message: Option<String>) -> RpcError {
+    RpcError::new(
+        AGDA_ERROR,
+        message.unwrap_or_else(|| "Unknown error".to_owned()),
+    )
+}
+
+async fn give(agda: &mut Repl, params: GiveParams) -> Monad<RpcResult> {
+    let input = GoalInput::no_range(params.goal, params.expr.clone());
+    agda.agda.command(Cmd::give(input)).await?;
+    loop {
+        match agda.agda.response().await? {
+            Resp::GiveAction { give_result, .. } => {
+                let text = give_result.text(&params.expr);
+                poll_goals(agda).await?;
+                break Ok(Ok(json!({ "text": text })));
+            }
+            Resp::DisplayInfo {
+                info: Some(DisplayInfo::Error { message, .. }),
+            } => break Ok(Err(agda_error(message))),
+            _ => {}
+        }
+    }
+}
+
+async fn case(agda: &mut Repl, params: CaseParams) -> Monad<RpcResult> {
+    let input = GoalInput::no_range(params.goal, params.variable);
+    agda.agda.command(Cmd::MakeCase(input)).await?;
+    loop {
+        match agda.agda.response().await? {
+            Resp::MakeCase { clauses, .. } => break Ok(Ok(json!({ "clauses": clauses }))),
+            Resp::DisplayInfo {
+                info: Some(DisplayInfo::Error { message, .. }),
+            } => break Ok(Err(agda_error(message))),
+            _ => {}
+        }
+    }
+}
+
+/// How to bring Agda back when it stops answering.
+pub struct Restart {
+    pub program: String,
+    pub options: AgdaOptions,
+    /// How long we stay quiet before checking on Agda.
+    pub every: Duration,
+}
+
+/// How long Agda may take to tell its version.
+const PATIENCE: Duration = Duration::from_secs(10);
+
+/// Exponential backoff between restarts.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    first: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(first: Duration, max: Duration) -> Self {
+        let next = first;
+        Self { first, max, next }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.first
+    }
+}
+
+fn notify(method: &str, params: Value) {
+    let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+    println!("{}", notification);
+}
+
+/// Restart Agda until it answers again, telling the client about it.
+async fn keep_alive(
+    agda: &mut Repl,
+    restart: &Restart,
+    backoff: &mut Backoff,
+    sink: &JsonSink,
+) -> Monad {
+    if agda.agda.ping(PATIENCE).await.unwrap_or(false) {
+        backoff.reset();
+        return Ok(());
+    }
+    notify("agdaUnresponsive", Value::Null);
+    // The hung one would run on forever.
+    agda.agda.kill();
+    loop {
+        delay_for(backoff.next_delay()).await;
+        let file = agda.agda.file.clone();
+        match ReplState::start_with(&restart.program, file, &restart.options).await {
+            Ok(state) => agda.agda = state,
+            Err(e) => {
+                notify("restartFailed", json!({ "message": e.to_string() }));
+                continue;
+            }
+        }
+        if let Err(e) = poll_goals(agda).await {
+            let params = json!({ "message": e.to_string(), "output": sink.take() });
+            notify("restartFailed", params);
+            continue;
+        }
+        notify("restarted", json!({ "output": sink.take() }));
+        if agda.agda.ping(PATIENCE).await.unwrap_or(false) {
+            backoff.reset();
+            break Ok(());
+        }
+    }
+}
+
+/// Handle one request, the `bool` tells if we should stop.
+pub async fn dispatch(agda: &mut Repl, request: Request) -> Monad<(RpcResult, bool)> {
+    let result = match request.method.as_str() {
+        "version" => Ok(json!({
+            "protocol": PROTOCOL_VERSION,
+            "agda-tac": env!("CARGO_PKG_VERSION"),
+        })),
+        "load" => {
+            agda.read_buffer()?;
+            reload(agda).await?;
+            // The client waits for all of the load.
+            settle(agda).await?;
+            Ok(Value::Null)
+        }
+        "goals" => {
+            list_goals(&mut *agda.render, &agda.goals.goals(), GoalRange::All);
+            Ok(Value::Null)
+        }
+        "give" => match params(request.params) {
+            Ok(params) => give(agda, params).await?,
+            Err(e) => Err(e),
+        },
+        "case" => match params(request.params) {
+            Ok(params) => case(agda, params).await?,
+            Err(e) => Err(e),
+        },
+        "exit" => return Ok((Ok(Value::Null), line(agda, "exit").await?)),
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method `{}`", method),
+        )),
+    };
+    Ok((result, false))
+}
+
+/// JSON-RPC over stdio, one request per line, for simple editor plugins.
+/// The protocol is documented in the README.
+/// With `restart`, Agda is checked on when there are no requests.
+pub async fn serve(mut agda: Repl, restart: Option<Restart>) -> Monad {
+    let sink = JsonSink::default();
+    agda.render = Box::new(sink.clone());
+    poll_goals(&mut agda).await?;
+    let loaded = json!({
+        "jsonrpc": "2.0",
+        "method": "loaded",
+        "params": { "protocol": PROTOCOL_VERSION, "output": sink.take() },
+    });
+    println!("{}", loaded);
+    let (mut tx, mut requests) = unbounded_channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        forward(stdin.lock(), &mut tx, Incoming::Terminal);
+    });
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    loop {
+        let next = match &restart {
+            None => requests.recv().await,
+            Some(restart) => match requests.recv().timeout(restart.every).await {
+                Ok(next) => next,
+                Err(_) => {
+                    keep_alive(&mut agda, restart, &mut backoff, &sink).await?;
+                    continue;
+                }
+            },
+        };
+        let buf = match next {
+            Some(Incoming::Terminal(buf)) | Some(Incoming::Pipe(buf)) => buf,
+            None => break Ok(()),
+        };
+        if buf.trim().is_empty() {
+            continue;
+        }
+        let (id, result, stop) = match serde_json::from_str::<Request>(&buf) {
+            Ok(request) => {
+                let id = request.id.clone();
+                match dispatch(&mut agda, request).await {
+                    Ok((result, stop)) => (id, result, stop),
+                    Err(e) => match (AgdaInternalError::of(&e), &restart) {
+                        (Some(fatal), Some(restart)) => {
+                            let error = RpcError::new(AGDA_INTERNAL_ERROR, fatal.to_string());
+                            println!("{}", response(id, Err(error), sink.take()));
+                            keep_alive(&mut agda, restart, &mut backoff, &sink).await?;
+                            continue;
+                        }
+                        _ => break Err(e),
+                    },
+                }
+            }
+            Err(e) => (
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, e.to_string())),
+                false,
+            ),
+        };
+        println!("{}", response(id, result, sink.take()));
+        if stop {
+            break Ok(());
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use agda_mode::resp::ResponseContextEntry;
+
+/// Names bound by the telescope of the first `module ... where` line,
+/// like `A` and `n` in `module M (A : Set) {n : Nat} where`.
+pub fn module_params(lines: &[String]) -> Vec<String> {
+    let header = match lines.iter().find(|l| l.trim_start().starts_with("module ")) {
+        Some(header) => header,
+        None => return Vec::new(),
+    };
+    let header = header.trim_start().trim_start_matches("module ");
+    let header = header.split(" where").next().unwrap_or(header);
+    let mut params = Vec::new();
+    let mut depth = 0;
+    let mut binder = String::new();
+    for c in header.chars() {
+        match c {
+            '(' | '{' => {
+                depth += 1;
+                if depth == 1 {
+                    binder.clear();
+                    continue;
+                }
+            }
+            ')' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(colon) = binder.find(':') {
+                        let names = binder[..colon].split_whitespace();
+                        params.extend(names.map(|n| n.trim_matches('{').to_owned()));
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        if depth > 0 {
+            binder.push(c);
+        }
+    }
+    params
+}
+
+/// Does `name` occur as a word in `expr`.
+pub fn mentions(expr: &str, name: &str) -> bool {
+    expr.split(|c: char| c.is_whitespace() || "(){}".contains(c))
+        .any(|word| word == name)
+}
+
+/// The entries of the context that occur in the goal type,
+/// or in the types of the entries that do, and so on.
+pub fn relevant<'a>(
+    goal_type: &str,
+    context: &'a [ResponseContextEntry],
+) -> Vec<&'a ResponseContextEntry> {
+    let mut keep: Vec<bool> = context
+        .iter()
+        .map(|entry| mentions(goal_type, &entry.reified_name))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..context.len() {
+            if keep[i] {
+                continue;
+            }
+            let name = &context[i].reified_name;
+            let needed = context
+                .iter()
+                .zip(&keep)
+                .any(|(entry, &kept)| kept && mentions(&entry.binding, name));
+            if needed {
+                keep[i] = true;
+                changed = true;
+            }
+        }
+    }
+    context
+        .iter()
+        .zip(keep)
+        .filter(|(_, kept)| *kept)
+        .map(|(entry, _)| entry)
+        .collect()
+}
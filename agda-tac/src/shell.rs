@@ -0,0 +1,50 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use crate::render::Renderer;
+
+/// The shell running `line`, with the errors in the output so they stay in order.
+#[cfg(not(windows))]
+fn shell(line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!("exec 2>&1\n{}", line));
+    command
+}
+
+#[cfg(windows)]
+fn shell(line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(format!("{} 2>&1", line));
+    command
+}
+
+/// Run a shell command in `dir`, showing its output line by line as it comes.
+/// It can't read input, the REPL has the terminal.
+pub fn run(line: &str, dir: &Path, render: &mut dyn Renderer) -> io::Result<ExitStatus> {
+    let mut child = shell(line)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let read = match child.stdout.take() {
+        Some(stdout) => show_output(stdout, render),
+        None => Ok(()),
+    };
+    // Even if the output can't be read, the child is waited for, not left a zombie.
+    let status = child.wait()?;
+    read.map(|()| status)
+}
+
+/// The output line by line, until the end or an error.
+fn show_output(stdout: impl Read, render: &mut dyn Renderer) -> io::Result<()> {
+    // Not `lines`, the output may not be UTF-8.
+    let mut reader = BufReader::new(stdout);
+    let mut buf = Vec::new();
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        let text = String::from_utf8_lossy(&buf);
+        render.info(text.trim_end_matches(&['\r', '\n'][..]));
+        buf.clear();
+    }
+    Ok(())
+}
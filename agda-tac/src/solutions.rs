@@ -0,0 +1,152 @@
+use agda_mode::resp::ResponseContextEntry;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A term for the hole at a position, positions are 1-based.
+/// Goal numbers change on every load, positions don't (as long as the file doesn't).
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct Solution {
+    pub line: usize,
+    pub column: usize,
+    pub term: String,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    line: usize,
+    column: usize,
+    #[serde(default)]
+    term: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SolutionsFile {
+    goals: Vec<Entry>,
+}
+
+/// Read a solutions file, like `{"goals": [{"line": 3, "column": 5, "term": "zero"}]}`.
+/// Other fields are ignored and goals without a `term` are skipped,
+/// so a file from `export-goals` can be filled in and given back.
+pub fn parse(text: &str) -> Result<Vec<Solution>, String> {
+    let file: SolutionsFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let solutions = file.goals.into_iter().filter_map(|e| {
+        let term = e.term?;
+        Some(Solution {
+            line: e.line,
+            column: e.column,
+            term,
+        })
+    });
+    Ok(solutions.collect())
+}
+
+/// The order to give them in: from the bottom of the file,
+/// so the positions of the remaining holes stay the same.
+pub fn bottom_up(solutions: &mut [Solution]) {
+    solutions.sort_by_key(|s| Reverse((s.line, s.column)))
+}
+
+/// Different terms for the same hole, waiting for `apply --pick`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict<K> {
+    /// The position of the first of them, as written in the solutions file.
+    pub line: usize,
+    pub column: usize,
+    /// The hole they are for, which outlives the positions once other holes are filled.
+    pub hole: K,
+    pub terms: Vec<String>,
+}
+
+impl<K> Conflict<K> {
+    /// The terms to pick from, numbered from 1.
+    pub fn choices(&self) -> Vec<String> {
+        let header = format!("{}:{}: conflicting solutions:", self.line, self.column);
+        let choices = self.terms.iter().enumerate();
+        let choices = choices.map(|(k, term)| format!("  {}. {}", k + 1, term));
+        std::iter::once(header).chain(choices).collect()
+    }
+}
+
+/// Take out the solutions that are for the same hole (the `hole` of a solution)
+/// but have different terms, giving them twice would write over each other.
+/// The same term written twice is given once. Solutions without a hole are kept.
+pub fn conflicts<K: Eq + Hash + Clone>(
+    solutions: Vec<Solution>,
+    hole: impl Fn(&Solution) -> Option<K>,
+) -> (Vec<Solution>, Vec<Conflict<K>>) {
+    let mut groups: Vec<(Option<K>, Vec<Solution>)> = Vec::with_capacity(solutions.len());
+    let mut index: HashMap<K, usize> = HashMap::new();
+    for solution in solutions {
+        match hole(&solution) {
+            Some(key) => match index.get(&key) {
+                Some(&k) => groups[k].1.push(solution),
+                None => {
+                    index.insert(key.clone(), groups.len());
+                    groups.push((Some(key), vec![solution]));
+                }
+            },
+            None => groups.push((None, vec![solution])),
+        }
+    }
+    let (mut kept, mut conflicts) = (Vec::new(), Vec::new());
+    for (key, group) in groups {
+        let mut terms: Vec<String> = Vec::with_capacity(group.len());
+        for solution in &group {
+            if !terms.contains(&solution.term) {
+                terms.push(solution.term.clone());
+            }
+        }
+        let mut group = group.into_iter();
+        // Groups are never empty.
+        let first = group.next().unwrap();
+        match key {
+            Some(hole) if terms.len() > 1 => conflicts.push(Conflict {
+                line: first.line,
+                column: first.column,
+                hole,
+                terms,
+            }),
+            _ => kept.push(first),
+        }
+    }
+    (kept, conflicts)
+}
+
+/// A variable in the context of an exported goal.
+#[derive(Debug, Clone, Serialize)]
+pub struct Binding {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub binding: String,
+}
+
+impl From<&ResponseContextEntry> for Binding {
+    fn from(entry: &ResponseContextEntry) -> Self {
+        Self {
+            name: entry.reified_name.clone(),
+            binding: entry.binding.clone(),
+        }
+    }
+}
+
+/// A goal as written by `export-goals`, with the position of its hole.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedGoal {
+    /// The id of the goal, without the range Agda may know.
+    pub goal: u32,
+    pub line: usize,
+    pub column: usize,
+    #[serde(rename = "type")]
+    pub goal_type: String,
+    pub context: Vec<Binding>,
+}
+
+/// The goals file, which [`parse`](self::parse) reads back once terms are added.
+pub fn export(file: &str, goals: &[ExportedGoal]) -> String {
+    let value = json!({ "file": file, "goals": goals });
+    // Serializing derived structs and strings doesn't fail.
+    serde_json::to_string_pretty(&value).unwrap() + "\n"
+}
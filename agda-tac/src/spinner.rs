@@ -0,0 +1,94 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FRAMES: &[char] = &['|', '/', '-', '\\'];
+const TICK: Duration = Duration::from_millis(100);
+
+/// One frame of the spinner, like `/ Checking A (/path/A.agda). 1.2s`.
+pub fn frame(tick: usize, label: &str, elapsed: Duration) -> String {
+    let spinner = FRAMES[tick % FRAMES.len()];
+    let seconds = elapsed.as_millis() as f64 / 1000.0;
+    if label.is_empty() {
+        format!("{} {:.1}s", spinner, seconds)
+    } else {
+        format!("{} {} {:.1}s", spinner, label.trim_end(), seconds)
+    }
+}
+
+/// An animated spinner on stderr while Agda is thinking.
+/// It runs on its own thread, labeled with Agda's running info,
+/// so it never gets in the way of reading the responses.
+pub struct Spinner {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub fn start(label: Arc<Mutex<String>>) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = done.clone();
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut width = 0usize;
+            for tick in 0.. {
+                thread::sleep(TICK);
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let line = frame(tick, &label.lock().unwrap(), start.elapsed());
+                let padding = width.saturating_sub(line.chars().count());
+                width = line.chars().count();
+                eprint!("\r{}{}", line, " ".repeat(padding));
+                let _ = io::stderr().flush();
+            }
+            if width > 0 {
+                eprint!("\r{}\r", " ".repeat(width));
+            }
+        });
+        Self {
+            done,
+            thread: Some(thread),
+        }
+    }
+
+    /// Print the label as lines instead, when it changes,
+    /// at most one line `every` so a verbose Agda doesn't flood the terminal.
+    pub fn lines(label: Arc<Mutex<String>>, every: Duration) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = done.clone();
+        let thread = thread::spawn(move || {
+            let mut last = String::new();
+            let mut printed: Option<Instant> = None;
+            while !stop.load(Ordering::SeqCst) {
+                thread::sleep(TICK);
+                let waiting = match printed {
+                    Some(t) => t.elapsed() < every,
+                    None => false,
+                };
+                let line = label.lock().unwrap().trim_end().to_owned();
+                if !waiting && !line.is_empty() && line != last {
+                    eprintln!("{}", line);
+                    printed = Some(Instant::now());
+                    last = line;
+                }
+            }
+        });
+        Self {
+            done,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Spinner {
+    /// Stop the spinner and clear its line.
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
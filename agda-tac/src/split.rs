@@ -0,0 +1,31 @@
+use crate::diff::{diff, Change};
+
+/// The clauses of a case split, waiting for `split --apply`.
+#[derive(Debug, Clone)]
+pub struct PendingSplit {
+    pub line_num: usize,
+    /// The line as it was when Agda split it, so we know if it's edited since.
+    pub line: String,
+    /// Already indented, see [`clause_lines`](self::clause_lines).
+    pub clauses: Vec<String>,
+}
+
+/// The clauses, indented like the line they replace.
+pub fn clause_lines(line: &str, clauses: &[String]) -> Vec<String> {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    clauses.iter().map(|c| format!("{}{}", indent, c)).collect()
+}
+
+/// The split as a diff of lines, like `- f x = ?` and `+ f zero = ?`.
+pub fn preview(line: &str, clauses: &[String]) -> Vec<String> {
+    let new: Vec<&str> = clauses.iter().map(String::as_str).collect();
+    let changes = diff(&[line], &new);
+    changes
+        .iter()
+        .map(|change| match change {
+            Change::Same(l) => format!("  {}", l),
+            Change::Removed(l) => format!("- {}", l),
+            Change::Added(l) => format!("+ {}", l),
+        })
+        .collect()
+}
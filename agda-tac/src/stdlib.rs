@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::imports::import_block;
+
+/// Often used names of the standard library, and the module to open for them.
+const BUNDLED: &[(&str, &str)] = &[
+    ("ℕ", "Data.Nat"),
+    ("zero", "Data.Nat"),
+    ("suc", "Data.Nat"),
+    ("_+_", "Data.Nat"),
+    ("_*_", "Data.Nat"),
+    ("_≤_", "Data.Nat"),
+    ("Bool", "Data.Bool"),
+    ("true", "Data.Bool"),
+    ("false", "Data.Bool"),
+    ("if_then_else_", "Data.Bool"),
+    ("List", "Data.List"),
+    ("[]", "Data.List"),
+    ("_∷_", "Data.List"),
+    ("_++_", "Data.List"),
+    ("Maybe", "Data.Maybe"),
+    ("just", "Data.Maybe"),
+    ("nothing", "Data.Maybe"),
+    ("Fin", "Data.Fin"),
+    ("Vec", "Data.Vec"),
+    ("String", "Data.String"),
+    ("Char", "Data.Char"),
+    ("⊥", "Data.Empty"),
+    ("⊥-elim", "Data.Empty"),
+    ("⊤", "Data.Unit"),
+    ("tt", "Data.Unit"),
+    ("Σ", "Data.Product"),
+    ("∃", "Data.Product"),
+    ("_×_", "Data.Product"),
+    ("_,_", "Data.Product"),
+    ("proj₁", "Data.Product"),
+    ("proj₂", "Data.Product"),
+    ("_⊎_", "Data.Sum"),
+    ("inj₁", "Data.Sum"),
+    ("inj₂", "Data.Sum"),
+    ("_≡_", "Relation.Binary.PropositionalEquality"),
+    ("refl", "Relation.Binary.PropositionalEquality"),
+    ("sym", "Relation.Binary.PropositionalEquality"),
+    ("trans", "Relation.Binary.PropositionalEquality"),
+    ("cong", "Relation.Binary.PropositionalEquality"),
+    ("subst", "Relation.Binary.PropositionalEquality"),
+    ("¬_", "Relation.Nullary"),
+    ("Dec", "Relation.Nullary"),
+    ("yes", "Relation.Nullary"),
+    ("no", "Relation.Nullary"),
+    ("id", "Function"),
+    ("_∘_", "Function"),
+    ("Level", "Level"),
+];
+
+/// The modules defining each name, the bundled ones first.
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    modules: BTreeMap<String, Vec<String>>,
+}
+
+impl NameIndex {
+    pub fn bundled() -> Self {
+        let mut index = NameIndex::default();
+        for (name, module) in BUNDLED {
+            index.add(name, module);
+        }
+        index
+    }
+
+    fn add(&mut self, name: &str, module: &str) {
+        let modules = self.modules.entry(name.to_owned()).or_default();
+        if !modules.iter().any(|m| m == module) {
+            modules.push(module.to_owned());
+        }
+    }
+
+    /// Add the names defined in the `.agda` files under `root`,
+    /// the `src` directory of the library.
+    /// Only definitions at the start of a line are found, so not the ones in
+    /// modules inside the file, and files that cannot be read are skipped.
+    pub fn scan(&mut self, root: &Path) {
+        let mut todo = vec![root.to_path_buf()];
+        while let Some(dir) = todo.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut paths: Vec<_> = entries.filter_map(|e| Some(e.ok()?.path())).collect();
+            paths.sort();
+            for path in paths {
+                if path.is_dir() {
+                    todo.push(path);
+                    continue;
+                }
+                let module = match module_of(root, &path) {
+                    Some(module) => module,
+                    None => continue,
+                };
+                if let Ok(text) = fs::read_to_string(&path) {
+                    for name in text.lines().filter_map(defined_name) {
+                        self.add(name, &module);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn modules(&self, name: &str) -> &[String] {
+        self.modules.get(name).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The module of an `.agda` file under `root`, like `Data.Nat.Base`.
+fn module_of(root: &Path, path: &Path) -> Option<String> {
+    if path.extension()? != "agda" {
+        return None;
+    }
+    let relative = path.strip_prefix(root).ok()?.with_extension("");
+    let parts: Option<Vec<&str>> = relative.iter().map(|p| p.to_str()).collect();
+    Some(parts?.join("."))
+}
+
+/// The name a line defines, like `f` in `f : A` or `D` in `data D : Set where`.
+fn defined_name(line: &str) -> Option<&str> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut words = line.split_whitespace();
+    match (words.next()?, words.next()?) {
+        ("data", name) | ("record", name) => Some(name),
+        (name, ":") if !name.starts_with('-') => Some(name),
+        _ => None,
+    }
+}
+
+/// The name of a scope error of Agda, like `ℕ` in
+/// `Not in scope:\n  ℕ at A.agda:3,5-6`.
+pub fn not_in_scope(message: &str) -> Option<&str> {
+    let idx = message.find("Not in scope:")?;
+    message[idx + "Not in scope:".len()..]
+        .split_whitespace()
+        .next()
+}
+
+/// The line importing just `name` of `module`.
+pub fn import_line(module: &str, name: &str) -> String {
+    format!("open import {} using ({})", module, name)
+}
+
+/// Where a new import goes: after the imports or, without them,
+/// before the module header, so after the `OPTIONS` pragmas.
+pub fn import_position(lines: &[String]) -> usize {
+    match import_block(lines) {
+        Some((_, end)) => end,
+        None => lines
+            .iter()
+            .position(|l| l.starts_with("module "))
+            .unwrap_or(0),
+    }
+}
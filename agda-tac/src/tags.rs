@@ -0,0 +1,146 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use agda_mode::agda::{AgdaOptions, ReplState};
+use agda_mode::resp::AspectHighlight;
+
+use crate::file_io::{split_lines, write_atomically, Monad};
+use crate::occurrences::position;
+
+/// A name defined in a file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub file: String,
+    /// 1-based.
+    pub line: usize,
+    /// The line of the definition, for etags.
+    pub text: String,
+    /// In bytes, where the line starts in the file, for etags.
+    pub offset: usize,
+    /// What Agda thinks it is, like `function` or `datatype`.
+    pub kind: Option<String>,
+}
+
+/// The kinds of names worth a tag, out of Agda's aspects.
+const KINDS: &[&str] = &[
+    "function",
+    "datatype",
+    "record",
+    "constructor",
+    "field",
+    "postulate",
+    "primitive",
+    "module",
+    "macro",
+    "argument",
+];
+
+/// The definitions in `text`, which Agda loaded as `file`.
+/// A token defines a name if its definition site is at the token itself.
+pub fn definitions(file: &str, text: &str, highlighting: &[AspectHighlight]) -> Vec<Tag> {
+    let (buffer, ending) = split_lines(text);
+    let ending = ending.as_str();
+    let mut tags: Vec<Tag> = highlighting
+        .iter()
+        .filter(|h| match &h.definition_site {
+            Some(site) => {
+                site.position == h.range.start.offset
+                    && Path::new(&site.filepath) == Path::new(file)
+            }
+            None => false,
+        })
+        .filter_map(|h| {
+            let (line_num, column) =
+                position(&buffer, ending.len(), h.range.start.offset as usize)?;
+            let width = (h.range.end.offset - h.range.start.offset) as usize;
+            let line = &buffer[line_num];
+            let name: String = line.chars().skip(column).take(width).collect();
+            let offset = buffer[..line_num]
+                .iter()
+                .map(|l| l.len() + ending.len())
+                .sum();
+            let kind = h.atoms.iter().find(|a| KINDS.contains(&a.as_str()));
+            Some(Tag {
+                name,
+                file: file.to_owned(),
+                line: line_num + 1,
+                text: line.clone(),
+                offset,
+                kind: kind.cloned(),
+            })
+        })
+        .collect();
+    tags.sort_by_key(|t| t.line);
+    // Agda may highlight a token more than once.
+    tags.dedup_by(|a, b| a.line == b.line && a.name == b.name);
+    tags
+}
+
+/// A ctags file, sorted by name as the editors expect.
+pub fn ctags(tags: &[Tag]) -> String {
+    let mut tags: Vec<&Tag> = tags.iter().collect();
+    tags.sort_by(|a, b| (&a.name, &a.file, a.line).cmp(&(&b.name, &b.file, b.line)));
+    let mut out = String::from("!_TAG_FILE_FORMAT\t2\t/extended format/\n");
+    out.push_str("!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/\n");
+    for tag in tags {
+        out.push_str(&format!("{}\t{}\t{};\"", tag.name, tag.file, tag.line));
+        if let Some(kind) = &tag.kind {
+            out.push_str(&format!("\tkind:{}", kind));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// An Emacs TAGS file, with a section for each file in order.
+pub fn etags(tags: &[Tag]) -> String {
+    let mut out = String::new();
+    let mut files: Vec<&str> = tags.iter().map(|t| t.file.as_str()).collect();
+    files.dedup();
+    for file in files {
+        let section: String = tags
+            .iter()
+            .filter(|t| t.file == file)
+            .map(|t| format!("{}\x7f{}\x01{},{}\n", t.text, t.name, t.line, t.offset))
+            .collect();
+        out.push_str(&format!("\x0c\n{},{}\n{}", file, section.len(), section));
+    }
+    out
+}
+
+/// Load every file and write the tags of their definitions into `output`.
+/// The files are not changed.
+pub async fn write_tags(
+    agda_program: &str,
+    options: &AgdaOptions,
+    files: &[String],
+    output: &Path,
+    emacs: bool,
+) -> Monad {
+    let mut tags = Vec::new();
+    let mut agda: Option<ReplState> = None;
+    for file in files {
+        let file = fs::canonicalize(file)?.to_string_lossy().into_owned();
+        let state = match &mut agda {
+            Some(state) => {
+                state.set_file(file.clone());
+                state.reload_file().await?;
+                state
+            }
+            None => agda
+                .get_or_insert(ReplState::start_with(agda_program, file.clone(), options).await?),
+        };
+        if let Err(err_msg) = state.next_all_goals_warnings().await? {
+            eprintln!("{}", err_msg);
+        }
+        let text = fs::read_to_string(&file)?;
+        tags.append(&mut definitions(&file, &text, state.highlighting()));
+    }
+    if let Some(state) = &mut agda {
+        state.shutdown().await?;
+    }
+    let out = if emacs { etags(&tags) } else { ctags(&tags) };
+    write_atomically(output, |f| f.write_all(out.as_bytes()))
+}
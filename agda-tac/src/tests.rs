@@ -0,0 +1,1399 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use agda_mode::base::{Interval, Position, UseForce};
+use agda_mode::resp::{DisplayInfo, Goal, Resp, ResponseContextEntry};
+
+use crate::annotate::{annotated, annotation_line, strip};
+use crate::args::{merge_config, merge_env, CliOptions};
+use crate::bookmarks::Bookmarks;
+use crate::cache::GoalCache;
+use crate::config::Config;
+use crate::diff::{diff_words, word_diff_line, words};
+use crate::editor::CliEditor;
+use crate::effects::ReplCore;
+use crate::file_io::{
+    save_lines, save_lines_with, split_lines, temp_path, write_atomically, LineEnding, SavePolicy,
+};
+use crate::holes::{
+    collapse_holes, count_holes, expand_holes, hole_at, hole_column, replace_hole, HoleStyle,
+};
+use crate::input::{split_expr, GoalRange, UserInput, GOAL_LIST_LIMIT};
+use crate::lint::{fix, lint, LintKind};
+use crate::listen::{forward, Incoming};
+use crate::occurrences::occurrences;
+use crate::pragmas::{is_shown, warning_flags};
+use crate::profile::summary;
+use crate::render::{
+    a11y_block, a11y_goal_lines, ansi_text, diagnostics, plain_text, prompt_badge, quickfix_lines,
+    sarif_log, snippet, Capture, GoalLine, InfoFile, Output, Renderer, Sarif,
+};
+use crate::repl::{
+    exit_code_of, list_goals, render_context, show_display_info, EXIT_GOALS_LEFT, EXIT_LOAD_FAILED,
+};
+use crate::rpc::{response, Backoff, Request, RpcError, METHOD_NOT_FOUND};
+use crate::scope::{mentions, module_params, relevant};
+use crate::spinner::frame;
+use crate::split::{clause_lines, preview};
+use crate::tags::{ctags, definitions, etags};
+
+fn goals(n: u32) -> Vec<Goal> {
+    (0..n)
+        .map(|i| Goal {
+            interaction_point: i.into(),
+            goal_type: "Nat".to_owned(),
+            range: vec![],
+        })
+        .collect()
+}
+
+#[test]
+fn goal_list_is_cut() {
+    let goals = goals(25);
+    let goals: Vec<GoalLine> = goals
+        .iter()
+        .map(|goal| GoalLine { goal, notes: &[] })
+        .collect();
+    let mut render = Capture::default();
+    list_goals(&mut render, &goals, GoalRange::First);
+    assert_eq!(render.lines.len(), GOAL_LIST_LIMIT + 2);
+    assert_eq!(render.lines[1], "?0: Nat");
+    assert_eq!(
+        render.lines.last().unwrap(),
+        "\u{2026} 5 more (use `goals --all`)"
+    );
+}
+
+#[test]
+fn goal_list_range() {
+    let goals = goals(25);
+    let goals: Vec<GoalLine> = goals
+        .iter()
+        .map(|goal| GoalLine { goal, notes: &[] })
+        .collect();
+    let mut render = Capture::default();
+    list_goals(
+        &mut render,
+        &goals,
+        GoalRange::Between(10.into(), 12.into()),
+    );
+    assert_eq!(render.lines[1..3], ["?10: Nat", "?11: Nat"]);
+    render.lines.clear();
+    list_goals(&mut render, &[], GoalRange::All);
+    assert_eq!(render.lines, ["No goals."]);
+}
+
+#[test]
+fn interrupted_write_keeps_file() {
+    let path = std::env::temp_dir().join("agda-tac-atomic-test.agda");
+    let lines = vec!["module A where".to_owned(), "a = ?".to_owned()];
+    save_lines(&path, &lines).unwrap();
+    let written = write_atomically(&path, |f| {
+        f.write_all(b"module B wh")?;
+        Err(io::Error::new(io::ErrorKind::Interrupted, "Ctrl-C"))
+    });
+    assert!(written.is_err());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "module A where\na = ?\n"
+    );
+    assert!(!temp_path(&path).exists());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn crlf_round_trip() {
+    let path = std::env::temp_dir().join("agda-tac-crlf-test.agda");
+    let (mut lines, ending) = split_lines("module A where\r\nf = ?\r\n");
+    assert_eq!(ending, LineEnding::CrLf);
+    assert_eq!(lines[1], "f = ?");
+    assert_eq!(count_holes(&lines[1]), 1);
+    lines[1] = replace_hole(&lines[1], 0, "zero").unwrap();
+    save_lines_with(&path, &lines, ending).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "module A where\r\nf = zero\r\n"
+    );
+    assert_eq!(split_lines("a\nb\r\n").1, LineEnding::Lf);
+    assert_eq!(split_lines("").1, LineEnding::Lf);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn hole_styles() {
+    let style = HoleStyle::parse("{!  !}").unwrap();
+    assert_eq!(style, HoleStyle::Braces { padding: 2 });
+    assert_eq!(expand_holes("f ?a = ? -- ?", style), "f ?a = {!  !} -- ?");
+    assert_eq!(collapse_holes("f = {!  !} {! x !}"), "f = ? {! x !}");
+    assert_eq!(count_holes("f = {! x !} (? ?)"), 3);
+    let config = Config::parse("# comment\nhole = {!!}\n").unwrap();
+    assert_eq!(config.hole_style, HoleStyle::Braces { padding: 0 });
+    assert!(Config::parse("hole = !").is_err());
+}
+
+#[test]
+fn rewrite_config() {
+    use agda_mode::base::Rewrite;
+    let config = Config::parse("rewrite-infer = normalised\nrewrite-context = As-Is\n").unwrap();
+    assert_eq!(config.rewrite.infer, Rewrite::Normalised);
+    assert_eq!(config.rewrite.context, Rewrite::AsIs);
+    assert_eq!(config.rewrite.goals, Rewrite::Simplified);
+    assert!(Config::parse("rewrite-goals = a lot").is_err());
+}
+
+#[test]
+fn aliases_and_macros() {
+    let text = "alias g = give\nalias goals = goals --all\nmacro qed = g 0 refl; reload; goals\n";
+    let mut config = Config::parse(text).unwrap();
+    assert_eq!(config.expand(":g 1 x").unwrap(), ["give 1 x"]);
+    assert_eq!(
+        config.expand("qed").unwrap(),
+        ["give 0 refl", "reload", "goals --all"]
+    );
+    // Recursion stops at the name being expanded.
+    config.define("loop = reload; loop").unwrap();
+    assert_eq!(config.expand("loop").unwrap(), ["reload", "loop"]);
+    config.define("a = b").unwrap();
+    config.define("b = a").unwrap();
+    assert_eq!(config.expand("a").unwrap(), ["a"]);
+    config
+        .define("many = qed; qed; qed; qed; qed; qed; qed; qed")
+        .unwrap();
+    config
+        .define("more = many; many; many; many; many; many; many; many")
+        .unwrap();
+    assert!(config.expand("more").is_err());
+    assert!(config.define("two words = give").is_err());
+    let effects = effects_of(&[], &GoalCache::default(), "alias");
+    assert_eq!(effects, [r#"Info("No aliases.")"#]);
+}
+
+#[test]
+fn whitespace_lints() {
+    let lines = vec!["\u{feff}module A where".to_owned(), "a\t= ? ".to_owned()];
+    let kinds: Vec<_> = lint(&lines).into_iter().map(|l| (l.line, l.kind)).collect();
+    assert_eq!(
+        kinds,
+        [
+            (0, LintKind::Bom),
+            (1, LintKind::Tab),
+            (1, LintKind::TrailingWhitespace)
+        ]
+    );
+    assert_eq!(fix(&lines[1]), "a       = ?");
+    assert_eq!(fix(&lines[0]), "module A where");
+}
+
+#[test]
+fn info_file_sections() {
+    let path = std::env::temp_dir().join("agda-tac-info-test/info.txt");
+    let mut render = InfoFile::new(Box::new(Capture::default()), path.clone());
+    render.error("Oops");
+    render.info("Hi");
+    render.error("Oops again");
+    let text = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        text,
+        "---- Errors ----\nOops\nOops again\n\n---- Info ----\nHi\n\n"
+    );
+    render.loading();
+    render.warning("Hmm");
+    let text = fs::read_to_string(&path).unwrap();
+    assert_eq!(text, "---- Warnings ----\nHmm\n\n");
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn sarif_once_per_load() {
+    let path = std::env::temp_dir().join("agda-tac-sarif-test.json");
+    let _ = fs::remove_file(&path);
+    let file = PathBuf::from("/tmp/A.agda");
+    let mut render = Sarif::new(Box::new(Capture::default()), file, path.clone());
+    render.error("/tmp/A.agda:1,1-2\nNot a load\n");
+    render.loading();
+    render.warning("3:5: trailing whitespace");
+    render.warning("/tmp/A.agda:2,1-9: Unreachable clause");
+    render.error("/tmp/A.agda:5,7-8\nNat !=< Bool\n");
+    assert!(!path.exists());
+    render.loaded();
+    let log: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let results = log["runs"][0]["results"].as_array().unwrap();
+    let texts: Vec<_> = results.iter().map(|r| &r["message"]["text"]).collect();
+    assert_eq!(
+        texts,
+        ["Nat !=< Bool", "trailing whitespace", "Unreachable clause"]
+    );
+    assert_eq!(
+        results[2]["locations"][0]["physicalLocation"]["region"]["startLine"],
+        2
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn forward_lines() {
+    let (mut tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let input = io::Cursor::new("give 0 x\nreload\n");
+    assert!(forward(input, &mut tx, Incoming::Pipe));
+    drop(tx);
+    assert_eq!(rx.recv().await, Some(Incoming::Pipe("give 0 x".to_owned())));
+    assert_eq!(rx.recv().await, Some(Incoming::Pipe("reload".to_owned())));
+    assert_eq!(rx.recv().await, None);
+}
+
+#[test]
+fn quickfix_entries() {
+    let message = "\u{2014}\u{2014}\u{2014}\u{2014} Errors\n\
+                   /tmp/A.agda:5,7-8\n\
+                   Nat !=< Bool\n\
+                   when checking that the expression zero has type Bool\n\
+                   /tmp/A.agda:9,1-10,3\n\
+                   Unreachable clause\n";
+    let lines = quickfix_lines(std::path::Path::new("/tmp/A.agda"), message);
+    assert_eq!(
+        lines,
+        [
+            "/tmp/A.agda:5:7: Nat !=< Bool when checking that the expression zero has type Bool",
+            "/tmp/A.agda:9:1: Unreachable clause",
+        ]
+    );
+    let message = "/tmp/A.agda:5,1-2\n\
+                   Multiple definitions of f. Previous definition at\n\
+                   /tmp/A.agda:3,1-2\n";
+    let lines = quickfix_lines(std::path::Path::new("/tmp/A.agda"), message);
+    assert_eq!(
+        lines,
+        [
+            "/tmp/A.agda:5:1: Multiple definitions of f. Previous definition at",
+            "/tmp/A.agda:3:1: first defined here",
+        ]
+    );
+    let lines = quickfix_lines(std::path::Path::new("/tmp/A.agda"), "Oops");
+    assert_eq!(lines, ["/tmp/A.agda:1:1: Oops"]);
+}
+
+#[test]
+fn rpc_messages() {
+    let request = r#"{"jsonrpc":"2.0","id":3,"method":"give","params":{"goal":0,"expr":"x"}}"#;
+    let request: Request = serde_json::from_str(request).unwrap();
+    assert_eq!(request.method, "give");
+    assert_eq!(request.params["expr"], "x");
+    let output = vec![serde_json::json!({ "kind": "Info", "message": "Hi" })];
+    let ok = response(
+        request.id.clone(),
+        Ok(serde_json::json!({ "text": "x" })),
+        output,
+    );
+    assert_eq!(ok["id"], 3);
+    assert_eq!(ok["result"]["value"]["text"], "x");
+    assert_eq!(ok["result"]["output"][0]["message"], "Hi");
+    let err = RpcError::new(METHOD_NOT_FOUND, "nope");
+    let err = response(request.id, Err(err), Vec::new());
+    assert_eq!(err["error"]["code"], METHOD_NOT_FOUND);
+}
+
+#[test]
+fn word_diff() {
+    assert_eq!(words("f (g x)"), ["f", "(", "g", "x", ")"]);
+    let diff = diff_words("Vec A (n + zero)", "Vec A n");
+    assert_eq!(word_diff_line(&diff), "Vec A [-(-] n [-+ zero )-]");
+    let diff = diff_words("plus 1 1", "suc (suc zero)");
+    assert_eq!(word_diff_line(&diff), "[-plus 1 1-] {+suc ( suc zero )+}");
+}
+
+#[test]
+fn split_exprs() {
+    assert_eq!(split_expr("x (suc y)"), Some(("x", "(suc y)")));
+    assert_eq!(split_expr("(f (g x)) y"), Some(("f (g x)", "y")));
+    assert_eq!(split_expr("(suc y)"), Some(("suc y", "")));
+    assert_eq!(split_expr("(f x"), None);
+}
+
+#[test]
+fn module_telescope() {
+    let lines = vec![
+        "-- A comment".to_owned(),
+        "module M (A B : Set) {n : Nat} {{eq : Eq A}} where".to_owned(),
+        "module N (x : A) where".to_owned(),
+    ];
+    assert_eq!(module_params(&lines), ["A", "B", "n", "eq"]);
+    assert!(module_params(&["module M where".to_owned()]).is_empty());
+    assert!(mentions("Vec A (suc n)", "n"));
+    assert!(!mentions("Vec A (suc n)", "su"));
+}
+
+#[test]
+fn relevant_context() {
+    let entry = |name: &str, binding: &str| ResponseContextEntry {
+        original_name: name.to_owned(),
+        reified_name: name.to_owned(),
+        binding: binding.to_owned(),
+        in_scope: true,
+    };
+    let context = vec![
+        entry("A", "Set"),
+        entry("n", "Nat"),
+        entry("m", "Nat"),
+        entry("xs", "Vec A n"),
+        entry("f", "A → A"),
+    ];
+    let names: Vec<_> = relevant("xs ≡ xs", &context)
+        .iter()
+        .map(|e| e.reified_name.as_str())
+        .collect();
+    assert_eq!(names, ["A", "n", "xs"]);
+    assert!(relevant("Nat", &context).is_empty());
+}
+
+#[test]
+fn restart_backoff() {
+    use std::time::Duration;
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+    let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, [1, 2, 4, 5, 5]);
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+}
+
+fn effects_of(buffer: &[String], goals: &GoalCache, input: &str) -> Vec<String> {
+    let config = Config::default();
+    let core = ReplCore {
+        buffer,
+        config: &config,
+        goals,
+        is_plain: true,
+        unsaved: false,
+        missing: false,
+        moved_to: None,
+    };
+    let effects = core.effects(input.into());
+    effects.iter().map(|e| format!("{:?}", e)).collect()
+}
+
+#[test]
+fn command_effects() {
+    let buffer = vec!["module A where".to_owned(), "f = {! !}".to_owned()];
+    let goals = GoalCache::new(&buffer, goals(1), &HashMap::new());
+    assert_eq!(
+        effects_of(&buffer, &goals, "define g"),
+        [
+            r#"AppendLine("g : ?")"#,
+            r#"AppendLine("g = ?")"#,
+            "Sync",
+            "Reload"
+        ]
+    );
+    assert_eq!(effects_of(&buffer, &goals, "reload"), ["Reload"]);
+    assert_eq!(effects_of(&buffer, &goals, ":reload!"), ["ForceReload"]);
+    assert_eq!(
+        effects_of(&buffer, &goals, "holes --collapse"),
+        [r#"SetLine(1, "f = ?")"#, "Sync", "Reload"]
+    );
+    assert_eq!(
+        effects_of(&buffer, &goals, "holes --expand"),
+        [r#"Info("No holes to convert.")"#]
+    );
+    assert_eq!(effects_of(&buffer, &goals, "goals"), ["ShowGoals(First)"]);
+    let edited = vec!["module B where".to_owned(), "f = {! !}".to_owned()];
+    let effects = effects_of(&edited, &goals, "goals --all");
+    assert_eq!(effects.len(), 2);
+    assert!(effects[1].contains("The file has changed"));
+    assert_eq!(
+        effects_of(&buffer, &goals, "type 0"),
+        ["Query(Type(InteractionPoint { id: 0, range: None }, false))"]
+    );
+    assert_eq!(
+        effects_of(&buffer, &goals, "meta _12"),
+        [r#"Query(Meta("_12"))"#]
+    );
+}
+
+#[test]
+fn annotations() {
+    let line = annotated("f = ?", "Nat\n  \u{2192} Nat");
+    assert_eq!(line, "f = ?  -- \u{21d2} Nat \u{2192} Nat");
+    assert_eq!(annotated(&line, "Bool"), "f = ?  -- \u{21d2} Bool");
+    assert_eq!(strip(&line).as_deref(), Some("f = ?"));
+    let line = annotation_line("1 + 1", "2");
+    assert_eq!(line, "-- \u{21d2} 1 + 1 = 2");
+    assert_eq!(strip(&line), None);
+    let buffer = vec![
+        "module A where".to_owned(),
+        line,
+        "f = ?  -- \u{21d2} Nat".to_owned(),
+    ];
+    assert_eq!(
+        effects_of(&buffer, &GoalCache::default(), "annotate --strip"),
+        [r#"SetLine(2, "f = ?")"#, "RemoveLine(1)", "Sync", "Reload"]
+    );
+}
+
+#[test]
+fn sarif_report() {
+    let file = std::path::Path::new("/tmp/A.agda");
+    let errors = diagnostics(file, "/tmp/A.agda:5,7-8\nNat !=< Bool\n");
+    let text = "module A where\nf : Nat\nf = {! !}\n";
+    let hole = text.find('{').unwrap();
+    let interval = Interval {
+        start: Position::at_byte(text, hole).unwrap(),
+        end: Position::at_byte(text, hole + 5).unwrap(),
+    };
+    let goal = |range| Goal {
+        interaction_point: 0.into(),
+        goal_type: "Nat".to_owned(),
+        range,
+    };
+    let (placed, unplaced) = (goal(vec![interval]), goal(vec![]));
+    let goals = [
+        GoalLine {
+            goal: &placed,
+            notes: &[],
+        },
+        GoalLine {
+            goal: &unplaced,
+            notes: &[],
+        },
+    ];
+    let log = sarif_log(file, &errors, &[], &goals);
+    assert_eq!(log["version"], "2.1.0");
+    let results = &log["runs"][0]["results"];
+    assert_eq!(results[0]["level"], "error");
+    assert_eq!(results[0]["message"]["text"], "Nat !=< Bool");
+    let region = &results[0]["locations"][0]["physicalLocation"]["region"];
+    assert_eq!(region["startLine"], 5);
+    assert_eq!(region["startColumn"], 7);
+    assert_eq!(results[1]["ruleId"], "agda.hole");
+    assert_eq!(results[1]["message"]["text"], "Unsolved goal ?0: Nat");
+    let region = |i: usize| &results[i]["locations"][0]["physicalLocation"]["region"];
+    assert_eq!(region(1)["startLine"], 3);
+    assert_eq!(region(1)["startColumn"], 5);
+    assert_eq!(region(2)["startLine"], 1);
+    assert_eq!(region(2)["startColumn"], 1);
+}
+
+#[test]
+fn solved_goals() {
+    let goal = |id: u32, goal_type: &str| Goal {
+        interaction_point: id.into(),
+        goal_type: goal_type.to_owned(),
+        range: vec![],
+    };
+    let before = vec!["f = ? ?".to_owned(), "g = ?".to_owned()];
+    let previous = GoalCache::new(
+        &before,
+        vec![goal(0, "Nat"), goal(1, "Bool"), goal(2, "Nat")],
+        &HashMap::new(),
+    );
+    // `?1` is solved, and the goals are renumbered.
+    let after = vec!["f = ? true".to_owned(), "g = ?".to_owned()];
+    let current = GoalCache::new(
+        &after,
+        vec![goal(0, "Nat"), goal(1, "Nat")],
+        &HashMap::new(),
+    );
+    assert_eq!(current.solved_since(&previous), [goal(1, "Bool")]);
+    assert!(current.solved_since(&current).is_empty());
+}
+
+#[test]
+fn error_snippets() {
+    let buffer = vec!["f : Nat".to_owned(), "f = zero".to_owned()];
+    let range = agda_mode::resp::LabeledRange {
+        label: "Unreachable clause".to_owned(),
+        file: "/tmp/A.agda".to_owned(),
+        start: (2, 5),
+        end: (2, 9),
+    };
+    assert_eq!(
+        snippet(&buffer, &range),
+        [
+            "/tmp/A.agda:2,5: Unreachable clause",
+            "2 | f = zero",
+            "  |     ^^^^",
+        ]
+    );
+}
+
+#[test]
+fn give_nested_holes() {
+    // The `?` in the braces is not a hole of its own, until the braces are given.
+    assert_eq!(count_holes("f = {! suc ? !} ?"), 2);
+    assert_eq!(count_holes("f = {! {! x !} !}"), 1);
+    let line = "f = {! suc ? !} ?";
+    assert_eq!(replace_hole(line, 0, "suc ?").unwrap(), "f = suc ? ?");
+    assert_eq!(
+        replace_hole(line, 1, "zero").unwrap(),
+        "f = {! suc ? !} zero"
+    );
+    assert_eq!(replace_hole(line, 2, "zero"), None);
+
+    let goal = |id: u32| Goal {
+        interaction_point: id.into(),
+        goal_type: "Nat".to_owned(),
+        range: vec![],
+    };
+    let mut buffer = vec!["f : Nat".to_owned(), line.to_owned()];
+    let mut cache = GoalCache::new(&buffer, vec![goal(0), goal(1)], &HashMap::new());
+    assert_eq!(cache.hole_of(1.into()), Some((1, 1)));
+    buffer[1] = "f = suc ? ?".to_owned();
+    cache.invalidate_line(1);
+    cache.given(0.into(), vec![goal(2)], &buffer);
+    assert!(cache.is_fresh(&buffer));
+    assert_eq!(cache.hole_of(2.into()), Some((1, 0)));
+    assert_eq!(cache.hole_of(1.into()), Some((1, 1)));
+    assert_eq!(cache.hole_of(0.into()), None);
+}
+
+#[test]
+fn spinner_frames() {
+    let elapsed = std::time::Duration::from_millis(1250);
+    assert_eq!(frame(0, "", elapsed), "| 1.2s");
+    assert_eq!(
+        frame(5, "Checking A (/tmp/A.agda).\n", elapsed),
+        "/ Checking A (/tmp/A.agda). 1.2s"
+    );
+}
+
+#[test]
+fn solutions_file() {
+    let text = r#"{"goals": [
+        {"goal": 0, "line": 2, "column": 5, "type": "Nat", "term": "zero"},
+        {"goal": 1, "line": 2, "column": 7, "type": "Nat"},
+        {"goal": 2, "line": 3, "column": 5, "term": "suc ?"}
+    ]}"#;
+    let mut solutions = crate::solutions::parse(text).unwrap();
+    assert_eq!(solutions.len(), 2);
+    crate::solutions::bottom_up(&mut solutions);
+    assert_eq!((solutions[0].line, solutions[0].column), (3, 5));
+    assert_eq!(solutions[1].term, "zero");
+    assert!(crate::solutions::parse("[]").is_err());
+
+    let buffer = vec!["f : Nat".to_owned(), "f = ? {! !}".to_owned()];
+    assert_eq!(hole_at(&buffer[1], 5), Some(0));
+    assert_eq!(hole_at(&buffer[1], 8), Some(1));
+    assert_eq!(hole_at(&buffer[1], 1), None);
+    let cache = GoalCache::new(&buffer, goals(2), &HashMap::new());
+    assert_eq!(cache.goal_at(1, 1), Some(1.into()));
+    assert_eq!(cache.goal_at(0, 0), None);
+    // Agda knows where the holes are, a `?` in a string is not one.
+    let buffer = vec!["s = \"a ? b\"".to_owned(), "f = ?".to_owned()];
+    let counted = GoalCache::new(&buffer, goals(1), &HashMap::new());
+    assert_eq!(counted.hole_of(0.into()), Some((0, 0)));
+    let start = Position {
+        offset: 17,
+        line: 2,
+        col: 5,
+    };
+    let ranges = vec![(0.into(), vec![Interval { start, end: start }])];
+    let cache = GoalCache::new(&buffer, goals(1), &ranges.into_iter().collect());
+    assert_eq!(cache.hole_of(0.into()), Some((1, 0)));
+}
+
+#[test]
+fn conflicting_solutions() {
+    let text = r#"{"goals": [
+        {"line": 2, "column": 5, "term": "zero"},
+        {"line": 2, "column": 9, "term": "suc zero"},
+        {"line": 2, "column": 7, "term": "zero"},
+        {"line": 2, "column": 13, "term": "one"},
+        {"line": 2, "column": 14, "term": "one"}
+    ]}"#;
+    let buffer = "f = {! !} ? ?";
+    let solutions = crate::solutions::parse(text).unwrap();
+    let (kept, conflicts) = crate::solutions::conflicts(solutions, |s| hole_at(buffer, s.column));
+    let kept: Vec<_> = kept.iter().map(|s| (s.column, s.term.as_str())).collect();
+    assert_eq!(kept, vec![(13, "one"), (14, "one")]);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].hole, 0);
+    assert_eq!(
+        conflicts[0].choices(),
+        vec!["2:5: conflicting solutions:", "  1. zero", "  2. suc zero"]
+    );
+    match UserInput::from("apply --pick 2") {
+        UserInput::Pick(2) => {}
+        input => panic!("{:?}", input),
+    }
+}
+
+#[test]
+fn trace_lines() {
+    let infos = [
+        (1, "Checking A (A.agda).".to_owned()),
+        (10, "checking expression suc n".to_owned()),
+        (20, "  against Nat\n\n".to_owned()),
+        (20, "  against Nat\n\n".to_owned()),
+        (30, "".to_owned()),
+        (10, "done".to_owned()),
+    ];
+    let lines = crate::trace::lines(&infos);
+    assert_eq!(
+        lines,
+        vec!["checking expression suc n", "    against Nat", "done"]
+    );
+    match UserInput::from("trace 0 suc n") {
+        UserInput::Trace(i, "suc n") if i.id == 0 => {}
+        input => panic!("{:?}", input),
+    }
+}
+
+#[test]
+fn export_goals_file() {
+    let line = "f = λ x → {! !} ?";
+    let column = hole_column(line, 1).unwrap();
+    assert_eq!(column, 17);
+    assert_eq!(hole_at(line, column), Some(1));
+    assert_eq!(hole_column(line, 2), None);
+    let goal = crate::solutions::ExportedGoal {
+        goal: 1,
+        line: 3,
+        column,
+        goal_type: "Nat".to_owned(),
+        context: vec![crate::solutions::Binding {
+            name: "x".to_owned(),
+            binding: "Nat".to_owned(),
+        }],
+    };
+    let text = crate::solutions::export("A.agda", &[goal]);
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["file"], "A.agda");
+    assert_eq!(value["goals"][0]["type"], "Nat");
+    assert_eq!(value["goals"][0]["context"][0]["name"], "x");
+    // Nothing to give yet.
+    assert_eq!(crate::solutions::parse(&text).unwrap(), vec![]);
+    let filled = text.replace("\"context\"", "\"term\": \"x\", \"context\"");
+    let solutions = crate::solutions::parse(&filled).unwrap();
+    assert_eq!((solutions[0].line, solutions[0].column), (3, 17));
+}
+
+#[test]
+fn warning_pragmas() {
+    let buffer = vec![
+        "{-# OPTIONS --safe -Wno-UnreachableClauses #-}".to_owned(),
+        "module A where".to_owned(),
+    ];
+    let goals = GoalCache::default();
+    assert_eq!(warning_flags(&buffer), ["-Wno-UnreachableClauses"]);
+    assert_eq!(
+        effects_of(&buffer, &goals, "warnings -Wall"),
+        [
+            r#"SetLine(0, "{-# OPTIONS --safe -Wno-UnreachableClauses -Wall #-}")"#,
+            "Sync",
+            "Reload"
+        ]
+    );
+    let effects = effects_of(&buffer[1..], &goals, "warnings --warning=all");
+    assert_eq!(
+        effects[0],
+        r#"InsertLine(0, "{-# OPTIONS --warning=all #-}")"#
+    );
+    assert!(
+        effects_of(&buffer, &goals, "warnings -Wno-UnreachableClauses")[0]
+            .contains("already there")
+    );
+    assert!(effects_of(&buffer, &goals, "warnings --safe")[0].starts_with("Complain"));
+    let config = Config::parse("ignore-warning = shadows\nignore-warning = Unreachable\n").unwrap();
+    assert!(!is_shown(&config.ignored_warnings, "x shadows a binding"));
+    assert!(is_shown(&config.ignored_warnings, "Unused variable"));
+}
+
+#[test]
+fn auto_suggestions() {
+    let buffer = vec!["f = ? ?".to_owned()];
+    let mut cache = GoalCache::new(&buffer, goals(2), &HashMap::new());
+    cache.suggest(1.into(), "zero".to_owned());
+    assert_eq!(cache.suggestion(1.into()), Some("zero"));
+    assert_eq!(cache.suggestion(0.into()), None);
+    assert_eq!(cache.goals()[1].notes, ["auto \u{2713}"]);
+    let config = Config::parse("speculative-auto = on\nscope-first = yes").unwrap();
+    assert!(config.speculative_auto && config.scope_first);
+    cache.invalidate_line(0);
+    assert_eq!(cache.suggestion(1.into()), None);
+}
+
+#[test]
+fn name_occurrences() {
+    use agda_mode::resp::AspectHighlight;
+    let buffer = vec![
+        "foo : Nat".to_owned(),
+        "foo = suc zero".to_owned(),
+        "bar = foo".to_owned(),
+    ];
+    let aspect = |from, to, atom: &str| AspectHighlight {
+        range: Interval::offsets(from, to),
+        atoms: vec![atom.to_owned()],
+        ..Default::default()
+    };
+    // With `\r\n`, every line is one character longer.
+    let highlighting = vec![
+        aspect(22, 25, "function"),
+        aspect(1, 4, "function"),
+        aspect(12, 15, "function"),
+        aspect(12, 15, "operator"),
+        aspect(16, 19, "inductiveconstructor"),
+    ];
+    let found = occurrences(&buffer, 2, &highlighting, "foo");
+    let places: Vec<_> = found.iter().map(|o| (o.line, o.column)).collect();
+    assert_eq!(places, [(1, 1), (2, 1)]);
+    assert_eq!(found[1].atoms, ["function", "operator"]);
+    assert_eq!(occurrences(&buffer, 1, &highlighting, "foo").len(), 1);
+    assert!(occurrences(&buffer, 2, &highlighting, "suc").is_empty());
+}
+
+#[test]
+fn quit_confirmation() {
+    let buffer = vec!["module A where".to_owned(), "f = ?".to_owned()];
+    let mut goals = GoalCache::new(&buffer, goals(1), &HashMap::new());
+    assert_eq!(effects_of(&buffer, &goals, "quit"), ["Exit"]);
+    goals.invalidate_line(1);
+    assert!(effects_of(&buffer, &goals, ":quit")[0].contains("not checked yet"));
+    assert_eq!(effects_of(&buffer, &goals, ":quit!"), ["Exit"]);
+    let quiet = Config::parse("no-confirm-exit = true").unwrap();
+    for (config, expected) in &[(Config::default(), false), (quiet, true)] {
+        let core = ReplCore {
+            buffer: &buffer,
+            config,
+            goals: &goals,
+            is_plain: true,
+            unsaved: true,
+            missing: false,
+            moved_to: None,
+        };
+        let effects = format!("{:?}", core.effects("exit".into()));
+        assert_eq!(effects == "[Exit]", *expected, "{}", effects);
+    }
+}
+
+#[test]
+fn save_policy() {
+    assert_eq!(Config::default().save, SavePolicy::Always);
+    let config = Config::parse("save = exit").unwrap();
+    assert_eq!(config.save, SavePolicy::OnExit);
+    assert!(Config::parse("save = never").is_err());
+    let buffer = vec!["module A where".to_owned()];
+    let goals = GoalCache::new(&buffer, vec![], &HashMap::new());
+    assert_eq!(
+        effects_of(&buffer, &goals, "write"),
+        ["Info(\"Nothing to write.\")"]
+    );
+    for input in &["write", "quit"] {
+        let core = ReplCore {
+            buffer: &buffer,
+            config: &config,
+            goals: &goals,
+            is_plain: true,
+            unsaved: true,
+            missing: false,
+            moved_to: None,
+        };
+        let effects = format!("{:?}", core.effects((*input).into()));
+        assert!(effects.starts_with("[Write, "), "{}", effects);
+    }
+}
+
+#[test]
+fn case_split_preview() {
+    let line = "  f x = ?";
+    let clauses = clause_lines(line, &["f zero = ?".to_owned(), "f (suc x) = ?".to_owned()]);
+    assert_eq!(clauses, ["  f zero = ?", "  f (suc x) = ?"]);
+    assert_eq!(
+        preview(line, &clauses),
+        ["-   f x = ?", "+   f zero = ?", "+   f (suc x) = ?"]
+    );
+    match UserInput::from("split 0 x --auto") {
+        UserInput::Split(i, "x", true) if i.id == 0 => {}
+        input => panic!("{:?}", input),
+    }
+    match UserInput::from(":split 1") {
+        UserInput::Split(i, "", false) if i.id == 1 => {}
+        input => panic!("{:?}", input),
+    }
+}
+
+#[test]
+fn tags_of_definitions() {
+    use agda_mode::resp::{AspectHighlight, DefinitionSite};
+
+    let text = "module A where\nf : Nat\nf = zero\n";
+    let aspect = |(from, to), position, filepath: &str| AspectHighlight {
+        range: Interval::offsets(from, to),
+        atoms: vec!["function".to_owned()],
+        definition_site: Some(DefinitionSite {
+            filepath: filepath.to_owned(),
+            position,
+        }),
+        ..Default::default()
+    };
+    let highlighting = [
+        aspect((16, 17), 16, "/tmp/A.agda"),
+        aspect((24, 25), 16, "/tmp/A.agda"),
+        aspect((20, 23), 3, "/lib/Nat.agda"),
+    ];
+    let tags = definitions("/tmp/A.agda", text, &highlighting);
+    assert_eq!(tags.len(), 1);
+    assert_eq!(
+        (tags[0].name.as_str(), tags[0].line, tags[0].offset),
+        ("f", 2, 15)
+    );
+    assert!(ctags(&tags).ends_with("f\t/tmp/A.agda\t2;\"\tkind:function\n"));
+    assert_eq!(etags(&tags), "\x0c\n/tmp/A.agda,15\nf : Nat\x7ff\x012,15\n");
+}
+
+#[test]
+fn project_imports() {
+    use crate::project::{imported_files, imported_module, source_roots};
+
+    assert_eq!(
+        imported_module("open import Data.Nat using (ℕ)"),
+        Some("Data.Nat")
+    );
+    assert_eq!(imported_module("import Lemmas as L"), Some("Lemmas"));
+    assert_eq!(imported_module("open Lemmas"), None);
+    let root = std::env::temp_dir().join(format!("agda-tac-project-{}", std::process::id()));
+    fs::create_dir_all(root.join("Utils")).unwrap();
+    fs::write(
+        root.join("Lemmas.agda"),
+        "module Lemmas where\nimport Utils.Eq\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("Utils/Eq.agda"),
+        "module Utils.Eq where\nimport Lemmas\n",
+    )
+    .unwrap();
+    let lines = vec![
+        "open import Lemmas".to_owned(),
+        "import Data.Nat".to_owned(),
+    ];
+    let files = imported_files(&source_roots(&root.join("A.agda")), &lines);
+    assert_eq!(
+        files,
+        [root.join("Lemmas.agda"), root.join("Utils/Eq.agda")]
+    );
+    // With a library, the modules are under its `include`s.
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("p.agda-lib"), "name: p\ninclude:\n  src\n").unwrap();
+    fs::write(root.join("src/B.agda"), "module B where\n").unwrap();
+    let roots = source_roots(&root.join("src/A.agda"));
+    assert_eq!(roots, [root.join("src")]);
+    let lines = vec!["open import B".to_owned(), "open import Lemmas".to_owned()];
+    assert_eq!(imported_files(&roots, &lines), [root.join("src/B.agda")]);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sorted_imports() {
+    use crate::imports::{format_imports, import_block};
+
+    let lines: Vec<String> = vec![
+        "module A where",
+        "import Relation.Binary.PropositionalEquality as Eq",
+        "open import Data.Nat  using (ℕ)",
+        "import Data.Bool",
+        "open Eq using (_≡_)",
+        "open import Data.Nat using (ℕ)",
+        "open Data.Bool",
+        "",
+        "f : ℕ",
+    ]
+    .into_iter()
+    .map(ToOwned::to_owned)
+    .collect();
+    assert_eq!(import_block(&lines), Some((1, 7)));
+    let block = &lines[1..7];
+    assert_eq!(
+        format_imports(block, false),
+        [
+            "open import Data.Bool",
+            "open import Data.Nat using (ℕ)",
+            "import Relation.Binary.PropositionalEquality as Eq",
+            "open Eq using (_≡_)",
+        ]
+    );
+    assert_eq!(format_imports(block, true)[0], "import Data.Bool");
+    let continued = ["open import Data.Nat".to_owned(), "  using (ℕ)".to_owned()];
+    assert_eq!(import_block(&continued), None);
+}
+
+#[test]
+fn stdlib_imports() {
+    use crate::stdlib::{import_line, import_position, not_in_scope, NameIndex};
+
+    let message =
+        "/tmp/A.agda:3,5-6\nNot in scope:\n  ℕ at /tmp/A.agda:3,5-6\nwhen scope checking ℕ";
+    assert_eq!(not_in_scope(message), Some("ℕ"));
+    assert_eq!(not_in_scope("ℕ !=< Bool"), None);
+    let root = std::env::temp_dir().join(format!("agda-tac-stdlib-{}", std::process::id()));
+    fs::create_dir_all(root.join("Data/Nat")).unwrap();
+    let text = "module Data.Nat.Base where\n\ndata ℕ : Set where\n  zero : ℕ\n\npred : ℕ → ℕ\n";
+    fs::write(root.join("Data/Nat/Base.agda"), text).unwrap();
+    let mut index = NameIndex::bundled();
+    index.scan(&root);
+    fs::remove_dir_all(&root).unwrap();
+    assert_eq!(index.modules("ℕ"), ["Data.Nat", "Data.Nat.Base"]);
+    assert_eq!(index.modules("pred"), ["Data.Nat.Base"]);
+    assert!(index.modules("zero").iter().all(|m| m == "Data.Nat"));
+    assert_eq!(
+        import_line("Data.Nat", "ℕ"),
+        "open import Data.Nat using (ℕ)"
+    );
+    let lines: Vec<String> = vec!["{-# OPTIONS --safe #-}", "module A where", "f = ?"]
+        .into_iter()
+        .map(ToOwned::to_owned)
+        .collect();
+    assert_eq!(import_position(&lines), 1);
+    let mut imported = lines.clone();
+    imported.insert(2, "open import Data.Bool".to_owned());
+    assert_eq!(import_position(&imported), 3);
+    let config = Config::parse("auto-import = on\nstdlib = /opt/agda-stdlib/src").unwrap();
+    assert!(config.auto_import);
+    assert_eq!(
+        config.stdlib.unwrap().to_str(),
+        Some("/opt/agda-stdlib/src")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn shell_escape() {
+    let goals = GoalCache::default();
+    let buffer = vec!["module A where".to_owned()];
+    assert_eq!(
+        effects_of(&buffer, &goals, ":! git diff"),
+        [r#"Shell("git diff")"#]
+    );
+    assert_eq!(
+        effects_of(&buffer, &goals, "!"),
+        [r#"Complain("Wait, run which command?")"#]
+    );
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    let mut render = Capture::default();
+    let line = "echo one; echo two >&2; pwd -P; exit 3";
+    let status = crate::shell::run(line, &dir, &mut render).unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(render.lines, ["one", "two", dir.to_str().unwrap()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn session_lock() {
+    use crate::lock::{lock_path, Lock};
+
+    let file = std::env::temp_dir().join(format!("agda-tac-lock-{}.agda", std::process::id()));
+    let lock = Lock::acquire(&file, false).unwrap().unwrap();
+    let pid = std::process::id();
+    assert_eq!(
+        fs::read_to_string(lock_path(&file)).unwrap(),
+        pid.to_string()
+    );
+    assert_eq!(Lock::acquire(&file, false).unwrap().unwrap_err(), pid);
+    let forced = Lock::acquire(&file, true).unwrap().unwrap();
+    // It's still the first session's.
+    drop(forced);
+    assert_eq!(Lock::acquire(&file, false).unwrap().unwrap_err(), pid);
+    drop(lock);
+    assert!(!lock_path(&file).exists());
+    // Left by a process that is gone.
+    fs::write(lock_path(&file), "4194304").unwrap();
+    let lock = Lock::acquire(&file, false).unwrap();
+    assert!(lock.is_ok());
+    drop(lock);
+    assert!(!lock_path(&file).exists());
+}
+
+#[test]
+fn open_existing_module() {
+    use crate::file_io::open_module;
+    use crate::lock::lock_path;
+
+    let dir = std::env::temp_dir().join(format!("agda-tac-open-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("Open").to_str().unwrap().to_owned();
+    let (path, lock) = open_module(file.clone(), false).unwrap().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "module Open where\n");
+    // A second session is told about the first one, even though the file exists now.
+    let pid = open_module(file.clone(), false).unwrap().unwrap_err();
+    assert_eq!(pid, std::process::id());
+    fs::write(&path, "module Open where\nx = 1\n").unwrap();
+    drop(lock);
+    let (_, lock) = open_module(file.clone() + ".agda", false).unwrap().unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "module Open where\nx = 1\n"
+    );
+    assert!(open_module(file.clone(), true).unwrap().is_ok());
+    drop(lock);
+    assert!(!lock_path(&path).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn raw_responses() {
+    use crate::raw::pretty_line;
+    use agda_mode::compat::Schema;
+
+    let line =
+        br#"JSON> {"kind":"Status","status":{"checked":true,"showImplicitArguments":false}}"#;
+    let lines = pretty_line(line, Schema::V2_6_0);
+    assert_eq!(lines[0], "{");
+    assert!(lines.contains(&r#"  "kind": "Status","#.to_owned()));
+    assert_eq!(lines.last().unwrap(), "}");
+    let unknown = pretty_line(br#"{"kind":"Fancy"}"#, Schema::V2_6_0);
+    assert_eq!(unknown.last().unwrap(), "(not a response agda-mode knows)");
+    assert_eq!(
+        pretty_line(b"Agda is starting\n", Schema::V2_6_0),
+        ["Agda is starting"]
+    );
+}
+
+#[test]
+fn session_options() {
+    for line in &["set verbosity 2", ":set verbosity = 2"] {
+        match UserInput::from(*line) {
+            UserInput::Set("verbosity", "2") => {}
+            input => panic!("{:?}", input),
+        }
+    }
+    let mut config = Config::parse("running-log = agda.log").unwrap();
+    assert!(config.set("verbosity", "loud").is_err());
+    config.set("verbosity", "2").unwrap();
+    assert_eq!(config.verbosity, Some(2));
+    assert_eq!(config.running_log.unwrap().to_str(), Some("agda.log"));
+}
+
+#[test]
+fn accessible_output() {
+    let goals = goals(2);
+    let notes = ["blocked by ?0".to_owned()];
+    let lines = [
+        GoalLine {
+            goal: &goals[0],
+            notes: &[],
+        },
+        GoalLine {
+            goal: &goals[1],
+            notes: &notes,
+        },
+    ];
+    assert_eq!(
+        a11y_goal_lines(&lines, 3),
+        [
+            "Goals: 5.",
+            "Goal 0 type: Nat",
+            "Goal 1 type: Nat",
+            "Goal 1 note: blocked by ?0",
+            "Goals: 3 more not shown, use goals --all to see them.",
+        ]
+    );
+    assert_eq!(a11y_goal_lines(&[], 0), ["Goals: none."]);
+    let message = "x ".repeat(50);
+    let block = a11y_block("Error", &message);
+    assert_eq!(block.len(), 2);
+    assert!(block[0].starts_with("Error: x x"));
+    assert!(block.iter().all(|l| l.chars().count() <= 72));
+    let message = "Nat !=< Bool\n\n  f  x =   y\nwhen checking";
+    assert_eq!(
+        a11y_block("Error", message),
+        ["Error: Nat !=< Bool", "", "  f  x =   y", "when checking"]
+    );
+}
+
+#[test]
+fn bookmarks_persist() {
+    match UserInput::from(":bookmark add two suc (suc zero)") {
+        UserInput::BookmarkAdd("two", "suc (suc zero)") => {}
+        input => panic!("{:?}", input),
+    }
+    match UserInput::from("bookmark run two") {
+        UserInput::BookmarkRun("two") => {}
+        input => panic!("{:?}", input),
+    }
+    let path = std::env::temp_dir()
+        .join(format!("agda-tac-bookmarks-{}", std::process::id()))
+        .join("bookmarks");
+    let mut bookmarks = Bookmarks::load(Some(path.clone()));
+    assert_eq!(bookmarks.iter().count(), 0);
+    bookmarks.add("two", "suc (suc zero)").unwrap();
+    bookmarks.add("ty", ":infer two").unwrap();
+    assert!(bookmarks.add("not a name", "x").is_err());
+    let bookmarks = Bookmarks::load(Some(path.clone()));
+    assert_eq!(bookmarks.get("two"), Some("suc (suc zero)"));
+    assert_eq!(bookmarks.command("two"), None);
+    assert_eq!(bookmarks.command("ty"), Some("infer two"));
+    let mut bookmarks = bookmarks;
+    assert!(bookmarks.remove("ty").unwrap());
+    assert!(!bookmarks.remove("ty").unwrap());
+    assert_eq!(Bookmarks::load(Some(path.clone())).iter().count(), 1);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn options_from_env() {
+    use std::collections::HashMap;
+    use structopt::StructOpt;
+
+    let vars: HashMap<&str, &str> = [
+        (
+            "AGDA_TAC_OPTS",
+            "--no-libraries --agda /env/agda -i env --bell-on-done",
+        ),
+        ("AGDA_TAC_INCLUDE", "a:b"),
+        ("AGDA_TAC_PLAIN", "1"),
+        ("NO_COLOR", ""),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    let var = |key: &str| vars.get(key).map(|v| (*v).to_owned());
+    let mut args = CliOptions::from_iter(&["agda-tac", "--agda", "/cli/agda", "-i", "cli", "A"]);
+    merge_env(&mut args, var).unwrap();
+    assert_eq!(args.agda.as_deref(), Some("/cli/agda"));
+    assert_eq!(args.include, ["env", "a", "b", "cli"]);
+    assert!(args.no_libraries && args.bell_on_done && args.plain && args.no_color);
+    assert!(!args.json);
+    let mut args = CliOptions::from_iter(&["agda-tac"]);
+    let file = |key: &str| match key {
+        "AGDA_TAC_OPTS" => Some("A.agda".to_owned()),
+        _ => None,
+    };
+    assert!(merge_env(&mut args, file).is_err());
+    assert!(!args.plain);
+}
+
+#[test]
+fn options_from_env_all_flags() {
+    use structopt::StructOpt;
+
+    // Every flag of `--help`, those with a value get `1`.
+    let mut help = Vec::new();
+    CliOptions::clap().write_help(&mut help).unwrap();
+    let mut words = Vec::new();
+    for line in String::from_utf8(help).unwrap().lines() {
+        let line = line.trim_start();
+        let start = match line.find("--") {
+            Some(start) if line.starts_with('-') => start,
+            _ => continue,
+        };
+        let mut parts = line[start..].split_whitespace();
+        let flag = parts.next().unwrap().trim_end_matches(',');
+        if ["--help", "--version", "--no-caching"].contains(&flag) {
+            continue;
+        }
+        words.push(flag.to_owned());
+        if parts.next().map_or(false, |value| value.starts_with('<')) {
+            words.push("1".to_owned());
+        }
+    }
+    assert!(words.len() > 20);
+    let direct = CliOptions::from_iter(std::iter::once("agda-tac".to_owned()).chain(words.clone()));
+    let opts = words.join(" ");
+    let mut merged = CliOptions::from_iter(&["agda-tac"]);
+    let var = |key: &str| match key {
+        "AGDA_TAC_OPTS" => Some(opts.clone()),
+        _ => None,
+    };
+    merge_env(&mut merged, var).unwrap();
+    assert_eq!(format!("{:?}", merged), format!("{:?}", direct));
+}
+
+#[test]
+fn options_from_config() {
+    use structopt::StructOpt;
+
+    let config = Config::parse("agda = /config/agda\ninclude = config\nplain = true\n").unwrap();
+    let mut args = CliOptions::from_iter(&["agda-tac", "-i", "cli", "A"]);
+    merge_config(&mut args, &config);
+    assert_eq!(args.agda.as_deref(), Some("/config/agda"));
+    assert_eq!(args.include, ["config", "cli"]);
+    assert!(args.plain && !args.no_color);
+    let mut args = CliOptions::from_iter(&["agda-tac", "--agda", "/cli/agda"]);
+    merge_config(&mut args, &config);
+    assert_eq!(args.agda.as_deref(), Some("/cli/agda"));
+}
+
+/// Compare `actual` with `src/snapshots/<name>`.
+/// Run with `AGDA_TAC_UPDATE_SNAPSHOTS=1` to write it instead, and review the diff.
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src")
+        .join("snapshots")
+        .join(name);
+    if std::env::var_os("AGDA_TAC_UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        expected == actual,
+        "{} changed, run with AGDA_TAC_UPDATE_SNAPSHOTS=1 to update it:\n{}",
+        name,
+        actual
+    );
+}
+
+/// Add what `render` captured since the last case to the snapshot.
+fn snapshot_case(snapshot: &mut String, case: &str, render: &mut Capture) {
+    snapshot.push_str(&format!("---- {} ----\n", case));
+    snapshot.push_str(&render.lines.concat().replace('\u{1b}', "\\e"));
+    render.lines.clear();
+}
+
+#[test]
+fn renderer_snapshots() {
+    let goals = goals(3);
+    let notes = ["blocked by unsolved constraint _12 := suc ?0".to_owned()];
+    let lines = [
+        GoalLine {
+            goal: &goals[0],
+            notes: &[],
+        },
+        GoalLine {
+            goal: &goals[1],
+            notes: &notes,
+        },
+    ];
+    let display_info = |json: &str| match serde_json::from_str(json).unwrap() {
+        Resp::DisplayInfo { info: Some(info) } => info,
+        resp => panic!("{:?}", resp),
+    };
+    let error = display_info(
+        r#"{"kind":"DisplayInfo","info":{"kind":"Error","message":"/tmp/A.agda:3,5-9\nNat !=< Bool\nwhen checking that the expression zero has type Bool"}}"#,
+    );
+    let context = match display_info(
+        r#"{"kind":"DisplayInfo","info":{"kind":"Context","interactionPoint":0,"context":[
+            {"originalName":"A","reifiedName":"A","binding":"Set","inScope":true},
+            {"originalName":"x","reifiedName":"x","binding":"A","inScope":true},
+            {"originalName":"xs","reifiedName":"xs","binding":"List A","inScope":true}]}}"#,
+    ) {
+        DisplayInfo::Context { context, .. } => context,
+        info => panic!("{:?}", info),
+    };
+    let params = module_params(&["module A (A : Set) where".to_owned()]);
+    for (name, text) in &[
+        ("plain.snap", plain_text as fn(Output) -> String),
+        ("ansi.snap", ansi_text),
+    ] {
+        let mut render = Capture::with(*text);
+        let mut snapshot = String::new();
+        render.goals(&lines, 1);
+        snapshot_case(&mut snapshot, "goals", &mut render);
+        render.goals(&[], 0);
+        snapshot_case(&mut snapshot, "no goals", &mut render);
+        show_display_info(&mut render, &error);
+        snapshot_case(&mut snapshot, "error", &mut render);
+        render.complain("Wait, I cannot parse the goal number.");
+        snapshot_case(&mut snapshot, "complaint", &mut render);
+        render.warning("3:12: trailing whitespace");
+        snapshot_case(&mut snapshot, "warning", &mut render);
+        render_context(
+            &mut render,
+            &params,
+            "length xs \u{2261} 1",
+            &context,
+            false,
+        );
+        snapshot_case(&mut snapshot, "context", &mut render);
+        assert_snapshot(name, &snapshot);
+    }
+}
+
+#[test]
+fn complete_names() {
+    let editor = CliEditor {
+        names: Default::default(),
+    };
+    editor.names.borrow_mut().extend(vec![
+        "+-comm".to_owned(),
+        "+-assoc".to_owned(),
+        "*-comm".to_owned(),
+    ]);
+    assert_eq!(editor.candidates("sea", 3), (0, vec!["search".to_owned()]));
+    let line = "give 0 +-";
+    assert_eq!(
+        editor.candidates(line, line.len()),
+        (7, vec!["+-assoc".to_owned(), "+-comm".to_owned()])
+    );
+}
+
+#[test]
+fn prompt_counts() {
+    assert_eq!(prompt_badge(Some(1), 0, false), "[1 goal]");
+    assert_eq!(prompt_badge(Some(3), 2, false), "[3 goals, 2 errors]");
+    assert_eq!(prompt_badge(None, 2, false), "[2 errors]");
+    assert_eq!(
+        prompt_badge(Some(0), 0, true),
+        "[\u{1b}[32m0 goals\u{1b}[0m]"
+    );
+    let colored = prompt_badge(None, 1, true);
+    assert!(colored.contains("\u{1b}[31m1 error\u{1b}[0m"));
+}
+
+#[test]
+fn missing_file() {
+    let buffer = vec!["module A where".to_owned()];
+    let goals = GoalCache::new(&buffer, vec![], &HashMap::new());
+    let config = Config::default();
+    let core = |moved_to: Option<&str>| ReplCore {
+        buffer: &buffer,
+        config: &config,
+        goals: &goals,
+        is_plain: true,
+        unsaved: false,
+        missing: true,
+        moved_to: moved_to.map(ToOwned::to_owned),
+    };
+    let effects = |input: &str, moved_to| format!("{:?}", core(moved_to).effects(input.into()));
+    for input in &["reload", "define f", "quit"] {
+        let complaint = effects(input, None);
+        assert!(complaint.contains("deleted or moved"), "{}", complaint);
+        assert!(complaint.contains("`rebind <file>`"), "{}", complaint);
+    }
+    assert!(effects("write", Some("B.agda")).contains("`rebind B.agda` follows it"));
+    assert_eq!(effects("recreate", None), "[Recreate]");
+    assert_eq!(effects("rebind B.agda", None), "[Rebind(\"B.agda\")]");
+    assert_eq!(effects("quit!", None), "[Exit]");
+    assert_eq!(
+        effects_of(&buffer, &goals, "recreate"),
+        ["Info(\"The file is there, nothing to recreate.\")"]
+    );
+}
+
+#[test]
+fn profile_summary() {
+    let table = "Total      1,000ms\nA.fast        10ms\nA.slow       500ms\nMiscellaneous 0ms\n";
+    let infos = vec![(1, "Checking A".to_owned()), (1, table.to_owned())];
+    assert_eq!(
+        summary(&infos),
+        [
+            "Total: 1,000ms",
+            "500ms   50%  A.slow",
+            " 10ms    1%  A.fast",
+            "  0ms    0%  Miscellaneous"
+        ]
+    );
+    assert!(summary(&[(1, "Checking A".to_owned())]).is_empty());
+}
+
+#[test]
+fn forced_give() {
+    for (line, expected) in &[
+        ("give! 1 f x", UseForce::WithForce),
+        (":fill 1 f x", UseForce::WithoutForce),
+    ] {
+        match UserInput::from(*line) {
+            UserInput::Give(i, "f x", force) if i.id == 1 => assert_eq!(force, *expected),
+            input => panic!("{:?}", input),
+        }
+    }
+}
+
+#[test]
+fn session_exit_codes() {
+    assert_eq!(exit_code_of(0, 0), 0);
+    assert_eq!(exit_code_of(2, 0), EXIT_GOALS_LEFT);
+    // Goals are not cached when the load fails, the stale ones don't count.
+    assert_eq!(exit_code_of(0, 1), EXIT_LOAD_FAILED);
+    assert_eq!(exit_code_of(2, 1), EXIT_LOAD_FAILED);
+}
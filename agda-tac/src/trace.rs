@@ -0,0 +1,31 @@
+/// Agda's debug output of all the type checker's topics (`tc.*`), for `trace`.
+pub const FLAG: &str = "--verbose=tc:20";
+
+/// How many lines of a trace are shown.
+pub const LIMIT: usize = 200;
+
+/// Running info below this debug level is Agda's progress, like `Checking A`.
+const MIN_LEVEL: i32 = 10;
+
+/// The debug output of a check, one line per message line,
+/// indented by how much deeper its debug level is than the shallowest one.
+/// Progress, blank lines and repeated messages are left out.
+pub fn lines(infos: &[(i32, String)]) -> Vec<String> {
+    let traced: Vec<&(i32, String)> = infos
+        .iter()
+        .filter(|(level, message)| *level >= MIN_LEVEL && !message.trim().is_empty())
+        .collect();
+    let min = traced.iter().map(|(level, _)| *level).min().unwrap_or(0);
+    let mut lines = Vec::new();
+    let mut previous = None;
+    for (level, message) in traced {
+        if previous == Some(message) {
+            continue;
+        }
+        previous = Some(message);
+        let indent = "  ".repeat(((level - min) / 10) as usize);
+        let message_lines = message.lines().filter(|l| !l.trim().is_empty());
+        lines.extend(message_lines.map(|l| format!("{}{}", indent, l.trim_end())));
+    }
+    lines
+}
@@ -1,48 +1,233 @@
-use std::io;
-use std::process::Stdio;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{self, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::future::FutureExt;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
 
-use crate::base::{is_debugging_command, is_debugging_response, InteractionPoint};
-use crate::cmd::{Cmd, IOTCM};
-use crate::resp::{DisplayInfo, Resp};
+use crate::base::{
+    is_debugging_command, is_debugging_response, InteractionPoint, Rewrite, UseForce,
+};
+use crate::borrowed;
+use crate::cmd::{Cmd, GoalInput, IOTCM};
+use crate::compat::Schema;
+use crate::resp::{
+    AllGoalsWarnings, AspectHighlight, Constraint, DisplayInfo, GiveResult, Goal, GoalInfo,
+    InferredType, Intervals, Intro, MakeCase, ModuleContents, Resp, ResponseContextEntry,
+    ScopeExplanation, SearchResult, Solution,
+};
 
 pub const INTERACTION_COMMAND: &str = "--interaction-json";
 pub const START_FAIL: &str = "Failed to start Agda";
 
 pub struct ProcessStdio(pub Child, pub JustStdio);
-pub struct JustStdio(pub ChildStdin, pub ChildStdout);
+pub struct JustStdio(pub ChildStdin, pub ChildStdout, pub ChildStderr);
+
+/// Command line options of Agda that matter for interaction.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AgdaOptions {
+    pub no_libraries: bool,
+    pub local_interfaces: bool,
+    /// `None` for Agda's default.
+    pub caching: Option<bool>,
+    pub exit_on_error: bool,
+    /// `None` for asking `agda --version`.
+    pub schema: Option<Schema>,
+    /// Anything else, passed as is.
+    pub extra: Vec<String>,
+}
+
+impl AgdaOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Don't use any library files (`--no-libraries`).
+    pub fn no_libraries(mut self, no_libraries: bool) -> Self {
+        self.no_libraries = no_libraries;
+        self
+    }
+
+    /// Put interface files next to the source files (`--local-interfaces`).
+    pub fn local_interfaces(mut self, local_interfaces: bool) -> Self {
+        self.local_interfaces = local_interfaces;
+        self
+    }
+
+    /// Read the responses as `schema`, instead of telling it from `agda --version`.
+    pub fn schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// `--caching` or `--no-caching`.
+    pub fn caching(mut self, caching: bool) -> Self {
+        self.caching = Some(caching);
+        self
+    }
+
+    /// Stop at the first error (`--interaction-exit-on-error`),
+    /// the next response is an [`AgdaInternalError`](self::AgdaInternalError) then.
+    pub fn exit_on_error(mut self, exit_on_error: bool) -> Self {
+        self.exit_on_error = exit_on_error;
+        self
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.extra.push(arg.into());
+        self
+    }
+
+    /// The arguments, after [`INTERACTION_COMMAND`](self::INTERACTION_COMMAND).
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.no_libraries {
+            args.push("--no-libraries".to_owned());
+        }
+        if self.local_interfaces {
+            args.push("--local-interfaces".to_owned());
+        }
+        match self.caching {
+            Some(true) => args.push("--caching".to_owned()),
+            Some(false) => args.push("--no-caching".to_owned()),
+            None => {}
+        }
+        if self.exit_on_error {
+            args.push("--interaction-exit-on-error".to_owned());
+        }
+        args.extend(self.extra.iter().cloned());
+        args
+    }
+}
 
 pub fn init_agda_process(agda_program: &str) -> io::Result<ProcessStdio> {
+    init_agda_process_with(agda_program, &Default::default())
+}
+
+pub fn init_agda_process_with(
+    agda_program: &str,
+    options: &AgdaOptions,
+) -> io::Result<ProcessStdio> {
     let mut process = Command::new(agda_program)
         .arg(INTERACTION_COMMAND)
+        .args(options.args())
         .stdout(Stdio::piped())
         .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()?; // cannot spawn
     // These should not panic, because both stdio are piped
     let stdin = process.stdin().take().expect("Failed to pipe stdin");
     let stdout = process.stdout().take().expect("Failed to pipe stdout");
-    Ok(ProcessStdio(process, JustStdio(stdin, stdout)))
+    let stderr = process.stderr().take().expect("Failed to pipe stderr");
+    Ok(ProcessStdio(process, JustStdio(stdin, stdout, stderr)))
 }
 
 /// Start the Agda process and return the stdio handles.
 ///
 /// Note that this function may panic.
 pub fn start_agda(agda_program: &str) -> JustStdio {
-    let ProcessStdio(process, stdio) = init_agda_process(agda_program).expect(START_FAIL);
+    start_agda_with(agda_program, &Default::default())
+}
+
+/// Like [`start_agda`](self::start_agda), with some more options.
+pub fn start_agda_with(agda_program: &str, options: &AgdaOptions) -> JustStdio {
+    spawn_agda(agda_program, options).expect(START_FAIL).0
+}
+
+/// The exit of Agda, or `None` once it's asked to be killed.
+/// Agda isn't killed when the asking side is just gone.
+struct Exit {
+    process: Child,
+    kill: Option<oneshot::Receiver<()>>,
+}
+
+impl Future for Exit {
+    type Output = Option<io::Result<ExitStatus>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(kill) = &mut self.kill {
+            match Pin::new(kill).poll(cx) {
+                Poll::Ready(Ok(())) => return Poll::Ready(None),
+                Poll::Ready(Err(_)) => self.kill = None,
+                Poll::Pending => {}
+            }
+        }
+        Pin::new(&mut self.process).poll(cx).map(Some)
+    }
+}
+
+/// Start Agda, and wait for it to exit in the background.
+/// It's killed when something is sent to the other end.
+fn spawn_agda(
+    agda_program: &str,
+    options: &AgdaOptions,
+) -> io::Result<(JustStdio, oneshot::Sender<()>)> {
+    let ProcessStdio(process, stdio) = init_agda_process_with(agda_program, options)?;
+    let (kill, killed) = oneshot::channel();
+    let exit = Exit {
+        process,
+        kill: Some(killed),
+    };
     tokio::spawn(async {
-        let status = process.await.expect(START_FAIL);
-        println!("Agda exits with status {}.", status);
+        // The process is killed when it's dropped.
+        if let Some(status) = exit.await {
+            let status = status.expect(START_FAIL);
+            // Not on stdout, which may be read by a program.
+            eprintln!("Agda exits with status {}.", status);
+        }
     });
-    stdio
+    Ok((stdio, kill))
 }
 
 /// Deserialize from Agda's command line output.
 pub fn deserialize_agda<'a, T: Deserialize<'a>>(buf: &'a str) -> serde_json::Result<T> {
-    let buf = buf.trim_start_matches("JSON>").trim();
-    serde_json::from_str(buf)
+    deserialize_agda_bytes(buf.as_bytes())
+}
+
+/// Like [`deserialize_agda`](self::deserialize_agda), but works on the raw bytes,
+/// so huge responses (highlighting, goal lists) are not validated
+/// and copied into a `String` first.
+pub fn deserialize_agda_bytes<'a, T: Deserialize<'a>>(buf: &'a [u8]) -> serde_json::Result<T> {
+    const PROMPT: &[u8] = b"JSON>";
+    let mut buf = buf;
+    while buf.starts_with(PROMPT) {
+        buf = &buf[PROMPT.len()..];
+    }
+    let start = buf.iter().position(|b| !b.is_ascii_whitespace());
+    let end = buf.iter().rposition(|b| !b.is_ascii_whitespace());
+    match (start, end) {
+        (Some(start), Some(end)) => serde_json::from_slice(&buf[start..=end]),
+        _ => serde_json::from_slice(&[]),
+    }
+}
+
+/// Like [`deserialize_agda_bytes`](self::deserialize_agda_bytes), for a response
+/// of an Agda whose JSON is in the shape of `schema`.
+pub fn deserialize_agda_with<T: DeserializeOwned>(
+    buf: &[u8],
+    schema: Schema,
+) -> serde_json::Result<T> {
+    if schema == Schema::default() {
+        return deserialize_agda_bytes(buf);
+    }
+    let mut value: serde_json::Value = deserialize_agda_bytes(buf)?;
+    schema.normalize(&mut value);
+    serde_json::from_value(value)
 }
 
 /// Send an [`IOTCM`](crate::cmd::IOTCM) command to Agda.
@@ -51,20 +236,97 @@ pub async fn send_command(stdin: &mut ChildStdin, command: &IOTCM) -> io::Result
     if unsafe { is_debugging_command() } {
         eprint!("[CMD]: {}", string);
     }
-    stdin.write(string.as_bytes()).await?;
+    stdin.write_all(string.as_bytes()).await?;
     stdin.flush().await
 }
 
+enum Input {
+    Command(String),
+    Close,
+}
+
+/// How many commands may wait for the writer task before senders wait too.
+pub const COMMAND_QUEUE_CAPACITY: usize = 64;
+
+/// Sends commands to the writer task, see [`spawn_writer`](self::spawn_writer).
+#[derive(Clone)]
+pub struct CommandSender(Sender<Input>);
+
+fn writer_gone<T>(_: T) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Agda's stdin is closed")
+}
+
+impl CommandSender {
+    /// Queue a command, waiting if the queue is full.
+    pub async fn send(&mut self, command: &IOTCM) -> io::Result<()> {
+        let string = command.to_string();
+        if unsafe { is_debugging_command() } {
+            eprint!("[CMD]: {}", string);
+        }
+        self.0
+            .send(Input::Command(string))
+            .await
+            .map_err(writer_gone)
+    }
+
+    /// Queue a line as it is, like an `IOTCM` someone typed.
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        let string = format!("{}\n", line.trim_end());
+        if unsafe { is_debugging_command() } {
+            eprint!("[CMD]: {}", string);
+        }
+        self.0
+            .send(Input::Command(string))
+            .await
+            .map_err(writer_gone)
+    }
+
+    /// Close the stdin after the queued commands are written.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.0.send(Input::Close).await.map_err(writer_gone)
+    }
+}
+
+/// Write the commands to `stdin` in a task of its own.
+///
+/// Writing a big command would block until Agda reads it, while Agda may
+/// be blocked on writing a response we are not reading yet.
+/// With the writing done elsewhere, the reading can go on.
+pub fn spawn_writer<W: AsyncWrite + Unpin + Send + 'static>(mut stdin: W) -> CommandSender {
+    let (tx, mut rx) = channel(COMMAND_QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(input) = rx.recv().await {
+            let written = match input {
+                Input::Command(string) => match stdin.write_all(string.as_bytes()).await {
+                    Ok(()) => stdin.flush().await,
+                    Err(e) => Err(e),
+                },
+                Input::Close => {
+                    let _ = stdin.shutdown().await;
+                    break;
+                }
+            };
+            if written.is_err() {
+                break;
+            }
+        }
+    });
+    CommandSender(tx)
+}
+
 pub struct AgdaRead {
-    buf: String,
+    buf: Vec<u8>,
     agda: BufReader<ChildStdout>,
+    /// The shape of the responses, see [`compat`](crate::compat).
+    pub schema: Schema,
 }
 
 impl From<BufReader<ChildStdout>> for AgdaRead {
     fn from(agda: BufReader<ChildStdout>) -> Self {
         Self {
             agda,
-            buf: String::with_capacity(2048),
+            buf: Vec::with_capacity(2048),
+            schema: Default::default(),
         }
     }
 }
@@ -76,15 +338,61 @@ impl From<ChildStdout> for AgdaRead {
 }
 
 impl AgdaRead {
+    async fn read_line(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        if self.agda.read_until(b'\n', &mut self.buf).await? == 0 {
+            let message = "Agda's stdout is closed";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message));
+        }
+        Ok(())
+    }
+
     /// Take Agda's response from the next line.
+    /// Lines that can't be read are [`Resp::ParseFailure`](crate::resp::Resp::ParseFailure)s,
+    /// responses we don't know are [`Resp::Unknown`](crate::resp::Resp::Unknown).
     pub async fn response(&mut self) -> io::Result<Resp> {
-        self.agda.read_line(&mut self.buf).await?;
+        self.read_line().await?;
         if unsafe { is_debugging_response() } {
-            eprintln!("[RES]: {}", self.buf);
+            eprintln!("[RES]: {}", String::from_utf8_lossy(&self.buf));
+        }
+        match deserialize_agda_with(&self.buf, self.schema) {
+            Ok(resp) => Ok(resp),
+            Err(e) => Ok(self.parse_failure(e)),
+        }
+    }
+
+    fn parse_failure(&self, error: serde_json::Error) -> Resp {
+        let raw = String::from_utf8_lossy(&self.buf).trim_end().to_owned();
+        let error = error.to_string();
+        Resp::ParseFailure { raw, error }
+    }
+
+    /// Like [`response`](Self::response), but the highlighting information
+    /// borrows from the internal buffer until the next read.
+    /// The response is not [normalized](crate::compat::Schema::normalize).
+    pub async fn response_borrowed(&mut self) -> io::Result<borrowed::Resp<'_>> {
+        self.read_line().await?;
+        if unsafe { is_debugging_response() } {
+            eprintln!("[RES]: {}", String::from_utf8_lossy(&self.buf));
+        }
+        match borrowed::Resp::deserialize(&self.buf) {
+            Ok(resp) => Ok(resp),
+            Err(e) => Ok(borrowed::Resp::Other(self.parse_failure(e))),
+        }
+    }
+}
+
+/// Wait for a burst of change notifications (like file saves) to settle.
+///
+/// Returns the last notification received before `quiet` passed
+/// without any new one, or `None` if the channel is closed.
+pub async fn debounce<T>(changes: &mut Receiver<T>, quiet: Duration) -> Option<T> {
+    let mut last = changes.recv().await?;
+    loop {
+        match changes.recv().timeout(quiet).await {
+            Ok(Some(next)) => last = next,
+            Ok(None) | Err(_) => break Some(last),
         }
-        let resp = deserialize_agda(&self.buf)?;
-        self.buf.clear();
-        Ok(resp)
     }
 }
 
@@ -94,12 +402,140 @@ pub fn load_file(path: String) -> IOTCM {
     IOTCM::simple(path, command)
 }
 
+/// Where [`load_shadow`](ReplState::load_shadow) writes the contents of a file:
+/// a new directory only this user can read, removed when it's dropped.
+#[derive(Debug)]
+pub struct ShadowDir(PathBuf);
+
+static SHADOW_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+impl ShadowDir {
+    /// Make the directory in the temporary directory.
+    /// Paths that are already there (maybe made by someone else) are skipped.
+    pub fn create() -> io::Result<Self> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        loop {
+            let n = SHADOW_DIRS.fetch_add(1, Ordering::Relaxed);
+            let dir = format!("agda-mode-shadow-{}-{}", process::id(), n);
+            let path = std::env::temp_dir().join(dir);
+            match builder.create(&path) {
+                Ok(()) => return Ok(ShadowDir(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The shadow of `file`. The file name stays the same,
+    /// so the module name still matches it.
+    pub fn path(&self, file: &str) -> PathBuf {
+        let name = Path::new(file).file_name().unwrap_or_default();
+        self.0.join(name)
+    }
+}
+
+impl Drop for ShadowDir {
+    fn drop(&mut self) {
+        // It's only the copies, nothing is lost if it stays.
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Agda crashed ("An internal error has occurred") or exited,
+/// nothing it says from now on can be trusted.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AgdaInternalError {
+    pub message: String,
+}
+
+impl AgdaInternalError {
+    /// Whether an error message of Agda is about a bug in Agda itself.
+    pub fn is_internal(message: &str) -> bool {
+        message.contains("An internal error has occurred") || message.contains("__IMPOSSIBLE__")
+    }
+
+    /// The internal error an IO error is made of, if it is.
+    pub fn of(e: &io::Error) -> Option<&Self> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    // `io::Error::other` is too new for us.
+    #[allow(clippy::io_other_error)]
+    fn to_io(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, self.clone())
+    }
+}
+
+impl Display for AgdaInternalError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        write!(f, "Agda is in an unknown state, please restart it.")
+    }
+}
+
+impl Error for AgdaInternalError {}
+
+/// How many lines of Agda's stderr are kept, older ones are dropped.
+pub const STDERR_CAPACITY: usize = 256;
+
+/// The last [`STDERR_CAPACITY`](self::STDERR_CAPACITY) lines of stderr.
+#[derive(Default)]
+pub(crate) struct StderrLines {
+    lines: VecDeque<String>,
+    /// Every line ever pushed, dropped ones too.
+    total: usize,
+}
+
+impl StderrLines {
+    pub(crate) fn push(&mut self, line: String) {
+        if self.lines.len() == STDERR_CAPACITY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        self.total += 1;
+    }
+
+    /// The kept lines after the first `seen` ones.
+    pub(crate) fn since(&self, seen: usize) -> impl Iterator<Item = &String> {
+        let new = self.total.saturating_sub(seen).min(self.lines.len());
+        self.lines.iter().skip(self.lines.len() - new)
+    }
+}
+
 /// Simple REPL state wrapper.
 pub struct ReplState {
-    pub stdin: ChildStdin,
+    pub stdin: CommandSender,
     pub agda: AgdaRead,
     pub file: String,
     iotcm: IOTCM,
+    /// Lines Agda printed to stderr, collected in the background.
+    stderr: Arc<Mutex<StderrLines>>,
+    stderr_seen: usize,
+    /// Responses that can't be read, kept apart from Agda's own stderr.
+    protocol_log: Vec<String>,
+    /// The most recent display info of each kind.
+    last_infos: HashMap<&'static str, DisplayInfo>,
+    /// Once Agda is broken, every response is this error.
+    fatal: Option<AgdaInternalError>,
+    /// The last running info, like `Checking A (/path/A.agda).`
+    running_info: Arc<Mutex<String>>,
+    /// The shadow file, when it's loaded instead of `file`.
+    shadow: Option<String>,
+    /// Where the shadow files are, made on the first one.
+    shadow_dir: Option<ShadowDir>,
+    /// Kills the process, if it's started by the state.
+    kill: Option<oneshot::Sender<()>>,
+    /// The highlighting of the last load.
+    highlighting: Vec<AspectHighlight>,
+    /// Running info above this debug level is not shown, `None` for all of it.
+    verbosity: Option<i32>,
+    /// Where all the running info is written, whatever the verbosity.
+    running_log: Option<File>,
+    /// The running info since [`capture_running_info`](Self::capture_running_info),
+    /// with the debug levels.
+    captured: Option<Vec<(i32, String)>>,
 }
 
 /// An Agda response that is either something good or some error.
@@ -109,50 +545,365 @@ pub type NextResult<T> = io::Result<AgdaResult<T>>;
 
 impl ReplState {
     pub async fn start(agda_program: &str, file: String) -> io::Result<Self> {
-        let JustStdio(stdin, out) = start_agda(agda_program);
-        Self::from_io(stdin, BufReader::new(out), file).await
+        Self::start_with(agda_program, file, &Default::default()).await
+    }
+
+    pub async fn start_with(
+        agda_program: &str,
+        file: String,
+        options: &AgdaOptions,
+    ) -> io::Result<Self> {
+        let schema = match options.schema {
+            Some(schema) => schema,
+            None => Schema::detect(agda_program).await,
+        };
+        let (JustStdio(stdin, out, err), kill) = spawn_agda(agda_program, options)?;
+        let mut state = Self::from_io(stdin, BufReader::new(out), file).await?;
+        state.kill = Some(kill);
+        state.agda.schema = schema;
+        state.capture_stderr(err);
+        Ok(state)
     }
 
     pub async fn from_io(
-        mut stdin: ChildStdin,
+        stdin: ChildStdin,
         stdout: BufReader<ChildStdout>,
         file: String,
     ) -> io::Result<Self> {
         let iotcm = load_file(file.clone());
-        send_command(&mut stdin, &iotcm).await?;
+        let mut stdin = spawn_writer(stdin);
+        stdin.send(&iotcm).await?;
         let agda = AgdaRead::from(stdout);
         Ok(Self {
             file,
             iotcm,
             stdin,
             agda,
+            stderr: Default::default(),
+            stderr_seen: 0,
+            protocol_log: Vec::new(),
+            last_infos: Default::default(),
+            fatal: None,
+            running_info: Default::default(),
+            shadow: None,
+            shadow_dir: None,
+            kill: None,
+            highlighting: Vec::new(),
+            verbosity: None,
+            running_log: None,
+            captured: None,
         })
     }
 
+    /// Collect the lines Agda prints to stderr (warnings, GHC RTS messages)
+    /// in the background, see [`stderr_lines`](Self::stderr_lines).
+    pub fn capture_stderr(&mut self, stderr: ChildStderr) {
+        let lines = self.stderr.clone();
+        tokio::spawn(async move {
+            let mut stderr = BufReader::new(stderr);
+            let mut buf = String::new();
+            while let Ok(n) = stderr.read_line(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+                lines.lock().unwrap().push(buf.trim_end().to_owned());
+                buf.clear();
+            }
+        });
+    }
+
+    /// Only let running info up to the debug level into [`running_info`](Self::running_info),
+    /// Agda floods us with it when its own verbosity is high.
+    pub fn set_verbosity(&mut self, verbosity: Option<i32>) {
+        self.verbosity = verbosity;
+    }
+
+    /// Append all the running info to the file, with the debug levels.
+    /// `None` stops logging.
+    pub fn log_running_info(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.running_log = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Keep all the running info from now on, whatever the verbosity,
+    /// until [`captured_running_info`](Self::captured_running_info) takes it.
+    pub fn capture_running_info(&mut self) {
+        self.captured = Some(Vec::new());
+    }
+
+    /// The running info since [`capture_running_info`](Self::capture_running_info),
+    /// with the debug levels. It's not kept anymore.
+    pub fn captured_running_info(&mut self) -> Vec<(i32, String)> {
+        self.captured.take().unwrap_or_default()
+    }
+
+    /// The last running info of Agda, kept up to date by [`response`](Self::response),
+    /// so another thread can show the progress.
+    pub fn running_info(&self) -> Arc<Mutex<String>> {
+        self.running_info.clone()
+    }
+
+    /// The last [`STDERR_CAPACITY`](self::STDERR_CAPACITY) lines Agda printed to stderr.
+    pub fn stderr_lines(&self) -> Vec<String> {
+        self.stderr.lock().unwrap().lines.iter().cloned().collect()
+    }
+
+    /// The responses that couldn't be read, or were ignored, so far.
+    pub fn protocol_log(&self) -> &[String] {
+        &self.protocol_log
+    }
+
+    /// Lines Agda printed to stderr since the last call.
+    pub fn new_stderr_lines(&mut self) -> Vec<String> {
+        let lines = self.stderr.lock().unwrap();
+        let new = lines.since(self.stderr_seen).cloned().collect();
+        self.stderr_seen = lines.total;
+        new
+    }
+
+    /// Attach the unseen stderr output to an error message.
+    fn error_with_stderr(&mut self, message: Option<String>) -> String {
+        let mut message = message.unwrap_or_else(|| "Unknown error".to_owned());
+        for line in self.new_stderr_lines() {
+            message.push('\n');
+            message.push_str(&line);
+        }
+        message
+    }
+
+    /// Work on another file from now on, it's not loaded yet.
+    pub fn set_file(&mut self, file: String) {
+        self.iotcm = load_file(file.clone());
+        self.file = file;
+        self.shadow = None;
+    }
+
     pub async fn reload_file(&mut self) -> io::Result<()> {
-        self.command(Cmd::load_simple(self.file.clone())).await
+        self.reload_file_with_flags(vec![]).await
+    }
+
+    /// Like [`reload_file`](Self::reload_file), with Agda's command line `flags`.
+    pub async fn reload_file_with_flags(&mut self, flags: Vec<String>) -> io::Result<()> {
+        self.highlighting.clear();
+        if self.shadow.take().is_some() {
+            self.iotcm = load_file(self.file.clone());
+        }
+        let path = self.file.clone();
+        self.command(Cmd::Load { path, flags }).await
+    }
+
+    /// Load `contents` as if they were the file, without touching the file,
+    /// for checking edits that are not confirmed yet.
+    /// They're written to a [`ShadowDir`](self::ShadowDir) instead, and the commands
+    /// are about the shadow until [`reload_file`](Self::reload_file) loads the file again.
+    /// Modules are looked up next to the shadow, so imports of the file's
+    /// neighbours need them to be in the include path.
+    pub async fn load_shadow(&mut self, contents: &str) -> io::Result<()> {
+        self.load_shadow_with_flags(contents, vec![]).await
+    }
+
+    /// Like [`load_shadow`](Self::load_shadow), with Agda's command line `flags`.
+    pub async fn load_shadow_with_flags(
+        &mut self,
+        contents: &str,
+        flags: Vec<String>,
+    ) -> io::Result<()> {
+        if self.shadow_dir.is_none() {
+            self.shadow_dir = Some(ShadowDir::create()?);
+        }
+        let path = self.shadow_dir.as_ref().unwrap().path(&self.file);
+        std::fs::write(&path, contents)?;
+        self.highlighting.clear();
+        let shadow = path.to_string_lossy().into_owned();
+        self.iotcm = load_file(shadow.clone());
+        self.shadow = Some(shadow.clone());
+        self.command(Cmd::Load {
+            path: shadow,
+            flags,
+        })
+        .await
+    }
+
+    /// The shadow file, if it's loaded instead of the file.
+    pub fn shadow(&self) -> Option<&str> {
+        self.shadow.as_deref()
+    }
+
+    /// Like [`reload_file`](Self::reload_file), but abort the previous load first,
+    /// so an obsolete version of the file is not checked to the end.
+    pub async fn reload_file_aborting(&mut self) -> io::Result<()> {
+        self.command(Cmd::Abort).await?;
+        self.reload_file().await
     }
 
     pub async fn command(&mut self, cmd: Cmd) -> io::Result<()> {
         self.iotcm.command = cmd;
-        send_command(&mut self.stdin, &self.iotcm).await
+        self.stdin.send(&self.iotcm).await
     }
 
+    /// Kill Agda, like when it doesn't answer anymore.
+    /// Only Agda started by [`start_with`](Self::start_with) can be killed.
+    pub fn kill(&mut self) {
+        if let Some(kill) = self.kill.take() {
+            // It's gone already if that fails.
+            let _ = kill.send(());
+        }
+    }
+
+    /// Ask Agda to exit, and close its stdin.
     pub async fn shutdown(&mut self) -> io::Result<()> {
-        self.stdin.shutdown().await
+        self.command(Cmd::Exit).await?;
+        self.stdin.close().await
+    }
+
+    /// Check that Agda still answers, by asking for its version.
+    /// Gives up after `patience`, and the state can't be used anymore then.
+    pub async fn ping(&mut self, patience: Duration) -> io::Result<bool> {
+        self.command(Cmd::ShowVersion).await?;
+        let version = async {
+            loop {
+                if let DisplayInfo::Version { .. } = self.next_display_info().await? {
+                    break Ok::<_, io::Error>(());
+                }
+            }
+        };
+        match version.timeout(patience).await {
+            Ok(answered) => answered.map(|()| true),
+            Err(_) => Ok(false),
+        }
     }
 
     /// Await the next Agda response.
+    /// Responses that can't be read are logged in the [protocol log](Self::protocol_log).
+    /// Nested [error objects](crate::resp::ErrorObject) are turned into the `message`.
+    /// Internal errors of Agda, and Agda exiting, are [`AgdaInternalError`](self::AgdaInternalError)s.
     pub async fn response(&mut self) -> io::Result<Resp> {
-        self.agda.response().await
+        if let Some(fatal) = &self.fatal {
+            return Err(fatal.to_io());
+        }
+        let mut resp = match self.agda.response().await {
+            Ok(resp) => resp,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                let message = self.error_with_stderr(Some("Agda exited.".to_owned()));
+                return Err(self.fatal(message));
+            }
+            Err(e) => return Err(e),
+        };
+        if let Resp::DisplayInfo {
+            info:
+                Some(DisplayInfo::Error {
+                    message,
+                    error: Some(error),
+                }),
+        } = &mut resp
+        {
+            *message = Some(error.to_message());
+        }
+        match &resp {
+            Resp::RunningInfo {
+                debug_level,
+                message,
+            } => {
+                if let Some(log) = &mut self.running_log {
+                    // The log is for later, it shouldn't stop the interaction.
+                    let _ = writeln!(log, "[{}] {}", debug_level, message.trim_end());
+                }
+                if let Some(captured) = &mut self.captured {
+                    captured.push((*debug_level, message.clone()));
+                }
+                let shown = match self.verbosity {
+                    Some(verbosity) => *debug_level <= verbosity,
+                    None => true,
+                };
+                if shown {
+                    *self.running_info.lock().unwrap() = message.clone()
+                }
+            }
+            Resp::ClearRunningInfo => self.running_info.lock().unwrap().clear(),
+            Resp::HighlightingInfo {
+                info: Some(info), ..
+            } => {
+                if info.remove {
+                    self.highlighting.clear();
+                }
+                self.highlighting.extend(info.payload.iter().cloned());
+            }
+            Resp::ClearHighlighting { token_based } => {
+                let token_based = *token_based;
+                self.highlighting.retain(|a| !a.is_cleared_by(token_based))
+            }
+            Resp::ParseFailure { raw, error } => {
+                let line = format!("Cannot read a response of Agda ({}): {}", error, raw);
+                self.protocol_log.push(line);
+            }
+            Resp::Unknown => {
+                let raw = String::from_utf8_lossy(&self.agda.buf);
+                let line = format!("Ignoring a response of Agda: {}", raw.trim_end());
+                self.protocol_log.push(line);
+            }
+            _ => {}
+        }
+        if let Resp::DisplayInfo { info: Some(info) } = &resp {
+            if let DisplayInfo::Error {
+                message: Some(message),
+                ..
+            } = info
+            {
+                if AgdaInternalError::is_internal(message) {
+                    let message = self.error_with_stderr(Some(message.clone()));
+                    return Err(self.fatal(message));
+                }
+            }
+            self.last_infos.insert(info.kind(), info.clone());
+        }
+        Ok(resp)
+    }
+
+    fn fatal(&mut self, message: String) -> io::Error {
+        let fatal = AgdaInternalError { message };
+        let e = fatal.to_io();
+        self.fatal = Some(fatal);
+        e
+    }
+
+    /// The highlighting Agda sent since the file was last loaded,
+    /// with the ranges in (1-based) character offsets of the file.
+    pub fn highlighting(&self) -> &[AspectHighlight] {
+        &self.highlighting
+    }
+
+    /// Where the holes of the goals of the last load are,
+    /// see [`AllGoalsWarnings::goal_ranges`].
+    pub fn goal_ranges(&self) -> HashMap<InteractionPoint, Intervals> {
+        match self.last_display_info("AllGoalsWarnings") {
+            Some(DisplayInfo::AllGoalsWarnings(all)) => all.goal_ranges(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// The most recent display info of the given [kind](crate::resp::DisplayInfo::kind),
+    /// like `"Error"` or `"NormalForm"`.
+    pub fn last_display_info(&self, kind: &str) -> Option<&DisplayInfo> {
+        self.last_infos.get(kind)
+    }
+
+    /// How long the last command with a [time](crate::resp::DisplayInfo::Time) took,
+    /// if Agda's format is known.
+    pub fn last_checking_time(&self) -> Option<Duration> {
+        match self.last_display_info("Time") {
+            Some(DisplayInfo::Time { time }) => time.duration,
+            _ => None,
+        }
     }
 
     /// Skip information until the next display info.
     pub async fn next_display_info(&mut self) -> io::Result<DisplayInfo> {
         loop {
-            match self.response().await? {
-                Resp::DisplayInfo { info: Some(info) } => break Ok(info),
-                _ => {}
+            if let Resp::DisplayInfo { info: Some(info) } = self.response().await? {
+                break Ok(info);
             }
         }
     }
@@ -165,10 +916,304 @@ impl ReplState {
             match self.response().await? {
                 InteractionPoints { interaction_points } => break Ok(Ok(interaction_points)),
                 DisplayInfo {
-                    info: Some(DisError { message }),
-                } => break Ok(Err(message.unwrap_or_else(|| "Unknown error".to_owned()))),
+                    info: Some(DisError { message, .. }),
+                } => break Ok(Err(self.error_with_stderr(message))),
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next list of visible goals (with their types).
+    /// This saves us from querying the goals one by one.
+    pub async fn next_visible_goals(&mut self) -> NextResult<Vec<Goal>> {
+        let all = self.next_all_goals_warnings().await?;
+        Ok(all.map(|all| all.visible_goals))
+    }
+
+    /// Skip information until the next goals and warnings list.
+    pub async fn next_all_goals_warnings(&mut self) -> NextResult<AllGoalsWarnings> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::AllGoalsWarnings(all) => break Ok(Ok(all)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next information about a goal,
+    /// like the answer of [`Cmd::goal_type`](crate::cmd::Cmd::goal_type).
+    pub async fn next_goal_info(&mut self) -> NextResult<GoalInfo> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::GoalSpecific { goal_info, .. } => break Ok(Ok(goal_info)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next normal form,
+    /// like the answer of [`Cmd::ComputeToplevel`](crate::cmd::Cmd::ComputeToplevel).
+    pub async fn next_normal_form(&mut self) -> NextResult<String> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::NormalForm { expr, .. } => break Ok(Ok(expr)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The type of `code` in the top level scope.
+    pub async fn infer(&mut self, code: &str) -> NextResult<InferredType> {
+        self.infer_with(code, Default::default()).await
+    }
+
+    /// Like [`infer`](Self::infer), with the type rewritten as `rewrite` says.
+    pub async fn infer_with(&mut self, code: &str, rewrite: Rewrite) -> NextResult<InferredType> {
+        let code = code.to_owned();
+        self.command(Cmd::InferToplevel { rewrite, code }).await?;
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::InferredType(inferred) => break Ok(Ok(inferred)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The constraints of the file, see [`next_constraints`](Self::next_constraints).
+    pub async fn constraints(&mut self) -> NextResult<Vec<Constraint>> {
+        self.command(Cmd::Constraints).await?;
+        self.next_constraints().await
+    }
+
+    /// Why `name` is in scope, in the top level scope.
+    pub async fn why_in_scope(&mut self, name: &str) -> NextResult<ScopeExplanation> {
+        let name = name.to_owned();
+        self.command(Cmd::WhyInScopeToplevel(name)).await?;
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::WhyInScope { message } => {
+                    break Ok(Ok(ScopeExplanation::parse(&message)))
+                }
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The names in scope whose types mention all of the names in `search`,
+    /// like `_+_ Nat`.
+    pub async fn search_about(
+        &mut self,
+        search: &str,
+        rewrite: Rewrite,
+    ) -> NextResult<Vec<SearchResult>> {
+        let search = search.to_owned();
+        self.command(Cmd::SearchAboutToplevel { rewrite, search })
+            .await?;
+        self.next_search_results().await
+    }
+
+    /// The names in the module `module` and their types, in the top level scope.
+    pub async fn module_contents(
+        &mut self,
+        module: &str,
+        rewrite: Rewrite,
+    ) -> NextResult<ModuleContents> {
+        let search = module.to_owned();
+        self.command(Cmd::ShowModuleContentsToplevel { rewrite, search })
+            .await?;
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::ModuleContents(contents) => break Ok(Ok(contents)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next inferred type,
+    /// like the answer of [`Cmd::InferToplevel`](crate::cmd::Cmd::InferToplevel).
+    pub async fn next_inferred_type(&mut self) -> NextResult<String> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::InferredType(inferred) => break Ok(Ok(inferred.expr)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next search results,
+    /// the answer of [`Cmd::SearchAboutToplevel`](crate::cmd::Cmd::SearchAboutToplevel).
+    pub async fn next_search_results(&mut self) -> NextResult<Vec<SearchResult>> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::SearchAbout { results, .. } => break Ok(Ok(results)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skip information until the next constraints,
+    /// the answer of [`Cmd::Constraints`](crate::cmd::Cmd::Constraints).
+    pub async fn next_constraints(&mut self) -> NextResult<Vec<Constraint>> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::Constraints { constraints } => break Ok(Ok(constraints)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Give the text of `input` to its goal. With [`UseForce::WithForce`], Agda skips
+    /// the termination and positivity checks, like `C-u C-c C-SPC` in Emacs.
+    /// The goals come after the result, see [`next_goals`](Self::next_goals).
+    pub async fn give_force(
+        &mut self,
+        input: GoalInput,
+        force: UseForce,
+    ) -> NextResult<GiveResult> {
+        self.command(Cmd::Give { force, input }).await?;
+        loop {
+            match self.response().await? {
+                Resp::GiveAction { give_result, .. } => break Ok(Ok(give_result)),
+                Resp::DisplayInfo {
+                    info: Some(DisplayInfo::Error { message, .. }),
+                } => break Ok(Err(self.error_with_stderr(message))),
+                _ => {}
+            }
+        }
+    }
+
+    /// The answer of [`Cmd::AutoOne`](crate::cmd::Cmd::AutoOne): the solution Auto
+    /// gave to the goal, or `None` if it didn't find one.
+    pub async fn next_auto(&mut self) -> NextResult<Option<String>> {
+        use Resp::*;
+        loop {
+            match self.response().await? {
+                GiveAction { give_result, .. } => {
+                    // Then a goal list or a message, which are not interesting.
+                    self.next_display_info().await?;
+                    break Ok(Ok(give_result.into_either().ok()));
+                }
+                DisplayInfo {
+                    info: Some(crate::resp::DisplayInfo::Auto { .. }),
+                } => break Ok(Ok(None)),
+                DisplayInfo {
+                    info: Some(crate::resp::DisplayInfo::Error { message, .. }),
+                } => break Ok(Err(self.error_with_stderr(message))),
+                _ => {}
+            }
+        }
+    }
+
+    /// The answer of [`Cmd::SolveAll`](crate::cmd::Cmd::SolveAll):
+    /// the goals Agda could solve, each expression is to be given to its goal.
+    pub async fn next_solve_all(&mut self) -> NextResult<Vec<Solution>> {
+        loop {
+            match self.response().await? {
+                Resp::SolveAll { solutions } => break Ok(Ok(solutions)),
+                Resp::DisplayInfo {
+                    info: Some(DisplayInfo::Error { message, .. }),
+                } => break Ok(Err(self.error_with_stderr(message))),
+                _ => {}
+            }
+        }
+    }
+
+    /// The answer of [`Cmd::Intro`](crate::cmd::Cmd::Intro).
+    /// After [`Intro::Given`], the goals come like after a give.
+    pub async fn next_intro(&mut self) -> NextResult<Intro> {
+        use DisplayInfo::*;
+        loop {
+            match self.response().await? {
+                Resp::GiveAction { give_result, .. } => break Ok(Ok(Intro::Given(give_result))),
+                Resp::DisplayInfo { info: Some(info) } => match info {
+                    IntroNotFound {} => break Ok(Ok(Intro::NotFound)),
+                    IntroConstructorUnknown { constructors } => {
+                        break Ok(Ok(Intro::Constructors(constructors)))
+                    }
+                    Error { message, .. } => break Ok(Err(self.error_with_stderr(message))),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// The answer of [`Cmd::MakeCase`](crate::cmd::Cmd::MakeCase):
+    /// the clauses that replace the clause of the goal.
+    pub async fn next_make_case(&mut self) -> NextResult<(MakeCase, Vec<String>)> {
+        loop {
+            match self.response().await? {
+                Resp::MakeCase {
+                    variant, clauses, ..
+                } => break Ok(Ok((variant, clauses))),
+                Resp::DisplayInfo {
+                    info: Some(DisplayInfo::Error { message, .. }),
+                } => break Ok(Err(self.error_with_stderr(message))),
                 _ => {}
             }
         }
     }
+
+    /// Skip information until the next context of a goal,
+    /// the answer of [`Cmd::Context`](crate::cmd::Cmd::Context).
+    pub async fn next_context(&mut self) -> NextResult<Vec<ResponseContextEntry>> {
+        loop {
+            match self.next_display_info().await? {
+                DisplayInfo::Context { context, .. } => break Ok(Ok(context)),
+                DisplayInfo::Error { message, .. } => {
+                    break Ok(Err(self.error_with_stderr(message)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The types of many goals. All the queries are sent before reading
+    /// the answers, so we don't wait for a round trip per goal.
+    pub async fn goal_types(
+        &mut self,
+        goals: &[InteractionPoint],
+        rewrite: Rewrite,
+    ) -> io::Result<Vec<AgdaResult<String>>> {
+        for &goal in goals {
+            let input = GoalInput::simple(goal);
+            self.command(Cmd::GoalType { rewrite, input }).await?;
+        }
+        let mut types = Vec::with_capacity(goals.len());
+        for _ in goals {
+            let info = self.next_goal_info().await?;
+            types.push(info.map(|info| match info.the_type() {
+                Some(the_type) => the_type.to_owned(),
+                None => format!("{:?}", info),
+            }));
+        }
+        Ok(types)
+    }
 }
@@ -1,59 +1,77 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+#[cfg(feature = "json")]
 use serde::{Deserialize, Serialize};
 
 static mut DEBUG_COMMAND: bool = false;
 static mut DEBUG_RESPONSE: bool = false;
 
+/// Toggle printing of the commands sent to Agda.
+///
+/// # Safety
+///
+/// This writes a global flag without synchronization,
+/// call it before starting any Agda process.
 pub unsafe fn debug_command(new_value: bool) {
     DEBUG_COMMAND = new_value;
 }
 
+/// Toggle printing of the responses received from Agda.
+///
+/// # Safety
+///
+/// This writes a global flag without synchronization,
+/// call it before starting any Agda process.
 pub unsafe fn debug_response(new_value: bool) {
     DEBUG_RESPONSE = new_value;
 }
 
+/// # Safety
+///
+/// Reads a global flag, see [`debug_command`](self::debug_command).
 pub unsafe fn is_debugging_command() -> bool {
     DEBUG_COMMAND
 }
 
+/// # Safety
+///
+/// Reads a global flag, see [`debug_response`](self::debug_response).
 pub unsafe fn is_debugging_response() -> bool {
     DEBUG_RESPONSE
 }
 
 /// Modifier for interactive commands,
 /// specifying the amount of normalization in the output.
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Rewrite {
     AsIs,
     Instantiated,
     HeadNormal,
+    #[default]
     Simplified,
     Normalised,
 }
 
-impl Default for Rewrite {
-    fn default() -> Self {
-        Rewrite::Simplified
-    }
-}
-
 /// Modifier for the interactive computation command,
 /// specifying the mode of computation and result display.
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum ComputeMode {
+    #[default]
     DefaultCompute,
     IgnoreAbstract,
     UseShowInstance,
 }
 
-impl Default for ComputeMode {
-    fn default() -> Self {
-        ComputeMode::DefaultCompute
-    }
-}
-
 /// Modifier for interactive commands,
 /// specifying whether safety checks should be ignored.
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum UseForce {
     /// Ignore additional checks, like termination/positivity...
     WithForce,
@@ -61,7 +79,8 @@ pub enum UseForce {
     WithoutForce,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Remove {
     Remove,
     Keep,
@@ -69,19 +88,16 @@ pub enum Remove {
 
 /// Is the highlighting "token-based", i.e. based only on
 /// information from the lexer?
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum TokenBased {
     TokenBased,
+    #[default]
     NotOnlyTokenBased,
 }
 
-impl Default for TokenBased {
-    fn default() -> Self {
-        TokenBased::NotOnlyTokenBased
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum HaskellBool {
     True,
     False,
@@ -97,14 +113,257 @@ impl From<bool> for HaskellBool {
     }
 }
 
-impl Into<bool> for HaskellBool {
-    fn into(self) -> bool {
-        match self {
+impl From<HaskellBool> for bool {
+    fn from(b: HaskellBool) -> Self {
+        match b {
             HaskellBool::True => true,
             HaskellBool::False => false,
         }
     }
 }
 
-pub type Position = i32;
-pub type InteractionPoint = u32;
+/// 1-based offset in a file, in characters.
+pub type Offset = i32;
+
+/// A position in a file, like one end of an [`Interval`](self::Interval).
+/// A bare offset is read as a position whose line and column are `0` (unknown).
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(from = "RawPosition"))]
+pub struct Position {
+    /// 1-based offset in the file, `pos` in Agda's JSON, `0` if unknown.
+    #[cfg_attr(feature = "json", serde(rename = "pos"))]
+    pub offset: Offset,
+    /// 1-based.
+    pub line: u32,
+    /// 1-based.
+    pub col: u32,
+}
+
+#[cfg(feature = "json")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPosition {
+    Offset(Offset),
+    Full {
+        #[serde(default)]
+        pos: Offset,
+        #[serde(default)]
+        line: u32,
+        #[serde(default)]
+        col: u32,
+    },
+}
+
+#[cfg(feature = "json")]
+impl From<RawPosition> for Position {
+    fn from(raw: RawPosition) -> Self {
+        match raw {
+            RawPosition::Offset(pos) => Position::offset(pos),
+            RawPosition::Full { pos, line, col } => Position {
+                offset: pos,
+                line,
+                col,
+            },
+        }
+    }
+}
+
+impl Position {
+    /// A position of which we only know the offset.
+    pub fn offset(offset: Offset) -> Self {
+        Position {
+            offset,
+            line: 0,
+            col: 0,
+        }
+    }
+
+    /// The line and column, if they are known.
+    pub fn line_col(&self) -> Option<(u32, u32)> {
+        if self.line == 0 {
+            None
+        } else {
+            Some((self.line, self.col))
+        }
+    }
+
+    /// The position of a byte index of `text`, which is the text Agda loaded.
+    /// `None` if it's not at a character.
+    pub fn at_byte(text: &str, byte: usize) -> Option<Self> {
+        let before = text.get(..byte)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(Position {
+            offset: before.chars().count() as Offset + 1,
+            line: before.matches('\n').count() as u32 + 1,
+            col: before[line_start..].chars().count() as u32 + 1,
+        })
+    }
+
+    /// The byte index of the position in `text`, from the line and column
+    /// if they are known, from the offset otherwise.
+    pub fn byte_in(&self, text: &str) -> Option<usize> {
+        let (start, skip) = match self.line_col() {
+            Some((line, col)) => {
+                let line_idx = (line - 1) as usize;
+                let start = match line_idx {
+                    0 => 0,
+                    n => text.match_indices('\n').nth(n - 1)?.0 + 1,
+                };
+                (start, (col as usize).checked_sub(1)?)
+            }
+            None => (0, (self.offset as usize).checked_sub(1)?),
+        };
+        let rest = &text[start..];
+        match rest.char_indices().nth(skip) {
+            Some((i, _)) => Some(start + i),
+            None if rest.chars().count() == skip => Some(text.len()),
+            None => None,
+        }
+    }
+}
+
+/// A part of a file, as Agda sends them.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct Interval {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A range as Agda sends them, usually one interval.
+/// Not to be confused with [`cmd::Range`](crate::cmd::Range), the range of a command.
+pub type Intervals = Vec<Interval>;
+
+impl Interval {
+    /// The interval between two offsets, of which the lines and columns are unknown.
+    pub fn offsets(from: Offset, to: Offset) -> Self {
+        Interval {
+            start: Position::offset(from),
+            end: Position::offset(to),
+        }
+    }
+
+    /// The bytes of `text` in the interval, see [`Position::byte_in`](Position::byte_in).
+    pub fn bytes_in(&self, text: &str) -> Option<std::ops::Range<usize>> {
+        Some(self.start.byte_in(text)?..self.end.byte_in(text)?)
+    }
+}
+
+/// An [`Interval`](self::Interval) written as the pair of its offsets,
+/// like in Agda's highlighting.
+#[cfg(feature = "json")]
+pub(crate) mod offsets {
+    use super::{Interval, Offset};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(interval: &Interval, s: S) -> Result<S::Ok, S::Error> {
+        (interval.start.offset, interval.end.offset).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Interval, D::Error> {
+        let (from, to) = <(Offset, Offset)>::deserialize(d)?;
+        Ok(Interval::offsets(from, to))
+    }
+}
+
+/// A goal, by its id, and where its hole is if Agda told (since 2.6.1).
+/// Points are compared by their id only, so a point Agda sent is the same
+/// as the one of a number the user typed.
+#[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "json",
+    serde(from = "RawInteractionPoint", into = "RawInteractionPoint")
+)]
+pub struct InteractionPoint {
+    pub id: u32,
+    /// The first interval of the range of the hole.
+    pub range: Option<Interval>,
+}
+
+/// Agda 2.6.0 sends the id, later versions `{"id": 0, "range": [...]}`.
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawInteractionPoint {
+    Id(u32),
+    Full {
+        id: u32,
+        #[serde(default)]
+        range: Intervals,
+    },
+}
+
+#[cfg(feature = "json")]
+impl From<RawInteractionPoint> for InteractionPoint {
+    fn from(raw: RawInteractionPoint) -> Self {
+        match raw {
+            RawInteractionPoint::Id(id) => id.into(),
+            RawInteractionPoint::Full { id, range } => InteractionPoint {
+                id,
+                range: range.first().copied(),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<InteractionPoint> for RawInteractionPoint {
+    fn from(point: InteractionPoint) -> Self {
+        match point.range {
+            None => RawInteractionPoint::Id(point.id),
+            Some(interval) => RawInteractionPoint::Full {
+                id: point.id,
+                range: vec![interval],
+            },
+        }
+    }
+}
+
+impl From<u32> for InteractionPoint {
+    fn from(id: u32) -> Self {
+        InteractionPoint { id, range: None }
+    }
+}
+
+impl PartialEq for InteractionPoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for InteractionPoint {}
+
+impl Hash for InteractionPoint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl PartialOrd for InteractionPoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InteractionPoint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl Display for InteractionPoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+impl FromStr for InteractionPoint {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(u32::into)
+    }
+}
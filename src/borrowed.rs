@@ -0,0 +1,169 @@
+use std::borrow::Cow;
+use std::fmt::{self, Formatter};
+use std::ops::Deref;
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+use crate::agda::deserialize_agda_bytes;
+use crate::base::{Interval, Offset, TokenBased};
+use crate::resp;
+
+/// A string borrowed from the input, unless it contains escapes.
+///
+/// Plain `Cow<str>` inside `Vec` or `Option` is always copied by serde.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct Str<'a>(#[serde(borrow)] pub Cow<'a, str>);
+
+impl<'a> Deref for Str<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> From<Str<'a>> for String {
+    fn from(s: Str<'a>) -> Self {
+        s.0.into_owned()
+    }
+}
+
+/// Borrowed version of [`resp::AspectHighlight`](crate::resp::AspectHighlight).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct AspectHighlight<'a> {
+    #[serde(with = "crate::base::offsets")]
+    pub range: Interval,
+    #[serde(borrow)]
+    pub atoms: Vec<Str<'a>>,
+    pub token_based: TokenBased,
+    #[serde(borrow)]
+    pub note: Option<Str<'a>>,
+    #[serde(borrow)]
+    pub definition_site: Option<DefinitionSite<'a>>,
+}
+
+/// Borrowed version of [`resp::DefinitionSite`](crate::resp::DefinitionSite).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct DefinitionSite<'a> {
+    #[serde(borrow)]
+    pub filepath: Str<'a>,
+    pub position: Offset,
+}
+
+/// Borrowed version of [`resp::HighlightingInfo`](crate::resp::HighlightingInfo).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightingInfo<'a> {
+    pub remove: bool,
+    #[serde(borrow)]
+    pub payload: Vec<AspectHighlight<'a>>,
+}
+
+/// The highlighting response, borrowed.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Highlighting<'a> {
+    #[serde(borrow)]
+    pub info: Option<HighlightingInfo<'a>>,
+    #[serde(borrow)]
+    pub filepath: Option<Str<'a>>,
+    pub direct: bool,
+}
+
+/// Agda response, with the highlighting information borrowed
+/// from the input line. Strings without escapes are not copied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Resp<'a> {
+    HighlightingInfo(Highlighting<'a>),
+    /// Any other response, which is decoded as usual.
+    Other(resp::Resp),
+}
+
+impl<'a> Resp<'a> {
+    /// Decode a line of Agda's output, in one pass.
+    pub fn deserialize(buf: &'a [u8]) -> serde_json::Result<Self> {
+        deserialize_agda_bytes(buf)
+    }
+}
+
+/// Agda writes the `kind` first, so the rest of a highlighting response
+/// is read straight from the input (buffering it would lose the borrowing).
+/// The fields before the `kind`, if any, and the other responses are buffered.
+struct RespVisitor;
+
+impl<'a> Visitor<'a> for RespVisitor {
+    type Value = Resp<'a>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("a response of Agda")
+    }
+
+    fn visit_map<A: MapAccess<'a>>(self, mut map: A) -> Result<Resp<'a>, A::Error> {
+        let mut fields = Map::new();
+        while let Some(key) = map.next_key::<Str<'a>>()? {
+            if &*key != "kind" {
+                fields.insert(key.into(), map.next_value()?);
+                continue;
+            }
+            let kind: Str<'a> = map.next_value()?;
+            let highlighting = &*kind == "HighlightingInfo";
+            if highlighting && fields.is_empty() {
+                let rest = MapAccessDeserializer::new(map);
+                return Highlighting::deserialize(rest).map(Resp::HighlightingInfo);
+            }
+            fields.insert("kind".to_owned(), Value::String(kind.into()));
+            while let Some((key, value)) = map.next_entry()? {
+                fields.insert(key, value);
+            }
+            let value = Value::Object(fields);
+            return if highlighting {
+                Highlighting::deserialize(value).map(Resp::HighlightingInfo)
+            } else {
+                resp::Resp::deserialize(value).map(Resp::Other)
+            }
+            .map_err(A::Error::custom);
+        }
+        Err(A::Error::missing_field("kind"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Resp<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(RespVisitor)
+    }
+}
+
+impl<'a> From<DefinitionSite<'a>> for resp::DefinitionSite {
+    fn from(site: DefinitionSite<'a>) -> Self {
+        resp::DefinitionSite {
+            filepath: site.filepath.into(),
+            position: site.position,
+        }
+    }
+}
+
+impl<'a> From<AspectHighlight<'a>> for resp::AspectHighlight {
+    fn from(aspect: AspectHighlight<'a>) -> Self {
+        resp::AspectHighlight {
+            range: aspect.range,
+            atoms: aspect.atoms.into_iter().map(From::from).collect(),
+            token_based: aspect.token_based,
+            note: aspect.note.map(From::from),
+            definition_site: aspect.definition_site.map(From::from),
+        }
+    }
+}
+
+impl<'a> From<HighlightingInfo<'a>> for resp::HighlightingInfo {
+    fn from(info: HighlightingInfo<'a>) -> Self {
+        resp::HighlightingInfo {
+            remove: info.remove,
+            payload: info.payload.into_iter().map(From::from).collect(),
+        }
+    }
+}
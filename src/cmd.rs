@@ -1,11 +1,14 @@
 use std::fmt::{Display, Error as FmtError, Formatter};
 
-use crate::base::{ComputeMode, HaskellBool, InteractionPoint, Remove, Rewrite, UseForce};
+use crate::base::{
+    ComputeMode, HaskellBool, InteractionPoint, Interval, Position, Remove, Rewrite, UseForce,
+};
 
 /// How much highlighting should be sent to the user interface?
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
 pub enum HighlightingLevel {
     None,
+    #[default]
     NonInteractive,
     /// This includes both non-interactive highlighting and
     /// interactive highlighting of the expression that is currently
@@ -13,27 +16,16 @@ pub enum HighlightingLevel {
     Interactive,
 }
 
-impl Default for HighlightingLevel {
-    fn default() -> Self {
-        HighlightingLevel::NonInteractive
-    }
-}
-
 /// How should highlighting be sent to the user interface?
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
 pub enum HighlightingMethod {
     /// Via stdout.
+    #[default]
     Direct,
     /// Both via files and via stdout.
     Indirect,
 }
 
-impl Default for HighlightingMethod {
-    fn default() -> Self {
-        HighlightingMethod::Direct
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct IOTCM {
     level: HighlightingLevel,
@@ -62,6 +54,7 @@ impl IOTCM {
     }
 
     /// Convert `self` into a command string.
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
         format!("{}\n", self)
     }
@@ -75,17 +68,40 @@ pub struct Pn {
     pub column: u32,
 }
 
-/// IDK why is this needed, but Emacs passes it to Agda.
+/// Where the goal is in the file, so Agda replaces exactly that text.
 /// It's fine to omit this in the commands.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Range {
+    #[default]
     NoRange,
-    Range { file: String, start: Pn, end: Pn },
+    /// The intervals are `(start, end)`, in the order Agda gives them.
+    Range {
+        file: String,
+        intervals: Vec<(Pn, Pn)>,
+    },
 }
 
-impl Default for Range {
-    fn default() -> Self {
-        Range::NoRange
+impl From<Position> for Pn {
+    fn from(end: Position) -> Self {
+        Pn {
+            offset: end.offset as u32,
+            line: end.line,
+            column: end.col,
+        }
+    }
+}
+
+impl Range {
+    /// A range from Agda, like the [range of a goal](crate::resp::Goal::range).
+    pub fn of(file: String, range: &[Interval]) -> Self {
+        if range.is_empty() {
+            return Range::NoRange;
+        }
+        let intervals = range
+            .iter()
+            .map(|interval| (interval.start.into(), interval.end.into()))
+            .collect();
+        Range::Range { file, intervals }
     }
 }
 
@@ -109,6 +125,161 @@ impl GoalInput {
     pub fn no_range(id: InteractionPoint, code: String) -> Self {
         Self::new(id, Default::default(), code)
     }
+
+    /// Text for the hole at `range` of `file`, the [range of the goal](crate::resp::Goal::range).
+    pub fn with_range(
+        id: InteractionPoint,
+        file: String,
+        range: &[Interval],
+        code: String,
+    ) -> Self {
+        Self::new(id, Range::of(file, range), code)
+    }
+}
+
+/// Options of Auto's proof search, written where the text of the goal goes,
+/// see [`Cmd::auto_one`](Cmd::auto_one).
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AutoOptions {
+    /// In milliseconds (`-t`), `None` for Agda's default.
+    pub timeout: Option<u32>,
+    /// Names Auto may use.
+    pub hints: Vec<String>,
+    /// Split on variables (`-c`).
+    pub case_split: bool,
+    /// Refine the goal with what's found, even if it's not a solution (`-r`).
+    pub refine: bool,
+    /// List the solutions instead of giving one (`-l`).
+    pub list: bool,
+}
+
+impl AutoOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn timeout(mut self, millis: u32) -> Self {
+        self.timeout = Some(millis);
+        self
+    }
+
+    pub fn hint(mut self, name: impl Into<String>) -> Self {
+        self.hints.push(name.into());
+        self
+    }
+
+    pub fn case_split(mut self, case_split: bool) -> Self {
+        self.case_split = case_split;
+        self
+    }
+
+    pub fn refine(mut self, refine: bool) -> Self {
+        self.refine = refine;
+        self
+    }
+
+    pub fn list(mut self, list: bool) -> Self {
+        self.list = list;
+        self
+    }
+}
+
+/// A backend of Agda's compiler, for [`Cmd::Compile`](Cmd::Compile).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Backend {
+    GHC,
+    /// GHC, for a module without a `main`.
+    GHCNoMain,
+    JS,
+    LaTeX,
+    QuickLaTeX,
+    /// A backend of a custom build of Agda, by its name.
+    Custom(BackendName),
+}
+
+/// The name of a custom backend, one word as Agda reads it.
+/// Only made by [`Backend::named`](Backend::named).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct BackendName(String);
+
+impl BackendName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Backend {
+    /// The backend called `name`, the built-in ones by their names.
+    /// Agda reads it as one word, so the name of a custom one is checked for that.
+    pub fn named(name: &str) -> Result<Self, String> {
+        use Backend::*;
+        Ok(match name {
+            "GHC" => GHC,
+            "GHCNoMain" => GHCNoMain,
+            "JS" => JS,
+            "LaTeX" => LaTeX,
+            "QuickLaTeX" => QuickLaTeX,
+            name if is_word(name) => Custom(BackendName(name.to_owned())),
+            name => return Err(format!("`{}` is not a backend name.", name)),
+        })
+    }
+
+    /// The prefix of the command line options of the backend, like `--ghc-` in `--ghc-flag`.
+    pub fn flag_prefix(&self) -> Option<&'static str> {
+        use Backend::*;
+        match self {
+            GHC | GHCNoMain => Some("--ghc-"),
+            JS => Some("--js-"),
+            LaTeX | QuickLaTeX => Some("--latex-"),
+            Custom(_) => None,
+        }
+    }
+
+    /// The options of the other built-in backends don't do anything but confuse,
+    /// like `--js-optimize` when compiling with GHC.
+    pub fn check_flags(&self, flags: &[String]) -> Result<(), String> {
+        use Backend::*;
+        let own = self.flag_prefix();
+        for flag in flags {
+            let other = [GHC, JS, LaTeX]
+                .iter()
+                .filter_map(Backend::flag_prefix)
+                .find(|&prefix| flag.starts_with(prefix) && Some(prefix) != own);
+            if other.is_some() {
+                return Err(format!("`{}` is not an option of {}.", flag, self));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_word(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic())
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '\'')
+}
+
+/// A [`Cmd::Compile`](Cmd::Compile) with flags fine for its backend,
+/// only made by [`Cmd::compile`](Cmd::compile).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Compilation {
+    backend: Backend,
+    path: String,
+    flags: Vec<String>,
+}
+
+impl Compilation {
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn flags(&self) -> &[String] {
+        &self.flags
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -121,11 +292,7 @@ pub enum Cmd {
     },
     /// Compiles the module in file `path` using
     /// the backend `backend`, using `flags` as the command-line options.
-    Compile {
-        backend: String,
-        path: String,
-        flags: Vec<String>,
-    },
+    Compile(Compilation),
     Constraints,
     /// Show unsolved metas. If there are no unsolved metas but
     /// unsolved constraints, show those instead.
@@ -214,6 +381,10 @@ pub enum Cmd {
     ShowImplicitArgs(bool),
     /// Toggle display of implicit arguments.
     ToggleImplicitArgs,
+    /// Tells Agda whether or not to show irrelevant arguments (Agda 2.6.1 and later).
+    ShowIrrelevantArgs(bool),
+    /// Toggle display of irrelevant arguments (Agda 2.6.1 and later).
+    ToggleIrrelevantArgs,
     // Goal commands
     //
     /// If the range is 'noRange', then the string comes from the
@@ -288,10 +459,57 @@ pub enum Cmd {
     /// Abort the current computation.
     /// Does nothing if no computation is in progress.
     Abort,
+    /// Exit the program (Agda 2.6.1 and later).
+    Exit,
 }
 
 type FmtMonad = Result<(), FmtError>;
 
+/// A Haskell string literal, the way Agda `read`s it.
+/// Rust's `{:?}` is close, but it writes escapes like `\u{1b}` Haskell doesn't know.
+pub struct HaskellString<'a>(pub &'a str);
+
+impl Display for HaskellString<'_> {
+    fn fmt(&self, f: &mut Formatter) -> FmtMonad {
+        f.write_str("\"")?;
+        let mut chars = self.0.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\t' => f.write_str("\\t")?,
+                '\r' => f.write_str("\\r")?,
+                c if c.is_control() => {
+                    write!(f, "\\{}", c as u32)?;
+                    // `\&` ends the number if a digit follows.
+                    if matches!(chars.peek(), Some(d) if d.is_ascii_digit()) {
+                        f.write_str("\\&")?;
+                    }
+                }
+                c => write!(f, "{}", c)?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// A Haskell list of strings, like `["--safe"]`.
+pub struct HaskellList<'a>(pub &'a [String]);
+
+impl Display for HaskellList<'_> {
+    fn fmt(&self, f: &mut Formatter) -> FmtMonad {
+        f.write_str("[")?;
+        for (i, s) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", HaskellString(s))?;
+        }
+        f.write_str("]")
+    }
+}
+
 impl Display for Pn {
     fn fmt(&self, f: &mut Formatter) -> FmtMonad {
         write!(
@@ -306,18 +524,33 @@ impl Display for Range {
     fn fmt(&self, f: &mut Formatter) -> FmtMonad {
         match self {
             Range::NoRange => f.write_str("noRange"),
-            Range::Range { file, start, end } => write!(
-                f,
-                "(intervalsToRange (Just (mkAbsolute {:?})) [Interval {} {}])",
-                file, start, end
-            ),
+            Range::Range { file, intervals } => {
+                write!(
+                    f,
+                    "(intervalsToRange (Just (mkAbsolute {})) [",
+                    HaskellString(file)
+                )?;
+                for (i, (start, end)) in intervals.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "Interval {} {}", start, end)?;
+                }
+                f.write_str("])")
+            }
         }
     }
 }
 
 impl Display for GoalInput {
     fn fmt(&self, f: &mut Formatter) -> FmtMonad {
-        write!(f, "{:?} {} {:?}", self.id, self.range, self.code)
+        write!(
+            f,
+            "{} {} {}",
+            self.id,
+            self.range,
+            HaskellString(&self.code)
+        )
     }
 }
 
@@ -325,8 +558,11 @@ impl Display for IOTCM {
     fn fmt(&self, f: &mut Formatter) -> FmtMonad {
         write!(
             f,
-            "IOTCM {:?} {:?} {:?} {}",
-            self.file, self.level, self.method, self.command
+            "IOTCM {} {:?} {:?} {}",
+            HaskellString(&self.file),
+            self.level,
+            self.method,
+            self.command
         )
     }
 }
@@ -361,49 +597,132 @@ impl Cmd {
             input,
         }
     }
+
+    /// Solve the goal by using Auto, with the `options`.
+    pub fn auto_one(id: InteractionPoint, options: &AutoOptions) -> Self {
+        Cmd::AutoOne(GoalInput::no_range(id, options.to_string()))
+    }
+
+    /// Compile the module in file `path`, if the `flags` are fine for the backend,
+    /// see [`Backend::check_flags`](Backend::check_flags).
+    pub fn compile(backend: Backend, path: String, flags: Vec<String>) -> Result<Self, String> {
+        backend.check_flags(&flags)?;
+        Ok(Cmd::Compile(Compilation {
+            backend,
+            path,
+            flags,
+        }))
+    }
+}
+
+impl Display for AutoOptions {
+    fn fmt(&self, f: &mut Formatter) -> FmtMonad {
+        let mut words = Vec::new();
+        if let Some(timeout) = self.timeout {
+            words.push(format!("-t {}", timeout));
+        }
+        let flags = [
+            (self.case_split, "-c"),
+            (self.refine, "-r"),
+            (self.list, "-l"),
+        ];
+        let flags = flags.iter().filter(|(on, _)| *on);
+        words.extend(flags.map(|(_, flag)| (*flag).to_owned()));
+        words.extend(self.hints.iter().cloned());
+        f.write_str(&words.join(" "))
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter) -> FmtMonad {
+        use Backend::*;
+        // Not a string literal, Agda reads one word.
+        match self {
+            GHC => f.write_str("GHC"),
+            GHCNoMain => f.write_str("GHCNoMain"),
+            JS => f.write_str("JS"),
+            LaTeX => f.write_str("LaTeX"),
+            QuickLaTeX => f.write_str("QuickLaTeX"),
+            Custom(name) => f.write_str(name.as_str()),
+        }
+    }
 }
 
 impl Display for Cmd {
     fn fmt(&self, f: &mut Formatter) -> FmtMonad {
         use Cmd::*;
         match self {
-            Load { path, flags } => write!(f, "( Cmd_load {:?} {:?} )", path, flags),
-            Compile {
+            Load { path, flags } => write!(
+                f,
+                "( Cmd_load {} {} )",
+                HaskellString(path),
+                HaskellList(flags)
+            ),
+            Compile(Compilation {
                 backend,
                 path,
                 flags,
-            } => write!(f, "( Cmd_compile {:?} {:?} {:?} )", backend, path, flags),
+            }) => write!(
+                f,
+                "( Cmd_compile {} {} {} )",
+                backend,
+                HaskellString(path),
+                HaskellList(flags)
+            ),
             Constraints => f.write_str("Cmd_constraints"),
             Metas => f.write_str("Cmd_metas"),
             ShowModuleContentsToplevel { rewrite, search } => write!(
                 f,
-                "( Cmd_show_module_contents_toplevel {:?} {:?} )",
-                rewrite, search
+                "( Cmd_show_module_contents_toplevel {:?} {} )",
+                rewrite,
+                HaskellString(search)
             ),
             SearchAboutToplevel { rewrite, search } => write!(
                 f,
-                "( Cmd_search_about_toplevel {:?} {:?} )",
-                rewrite, search
+                "( Cmd_search_about_toplevel {:?} {} )",
+                rewrite,
+                HaskellString(search)
             ),
             SolveAll(rewrite) => write!(f, "( Cmd_solveAll {:?} )", rewrite),
             SolveOne { rewrite, input } => write!(f, "( Cmd_solveOne {:?} {} )", rewrite, input),
             AutoOne(input) => write!(f, "( Cmd_autoOne {} )", input),
             AutoAll => f.write_str("Cmd_autoAll"),
             InferToplevel { rewrite, code } => {
-                write!(f, "( Cmd_infer_toplevel {:?} {:?} )", rewrite, code)
+                write!(
+                    f,
+                    "( Cmd_infer_toplevel {:?} {} )",
+                    rewrite,
+                    HaskellString(code)
+                )
             }
             ComputeToplevel { compute_mode, code } => {
-                write!(f, "( Cmd_compute_toplevel {:?} {:?} )", compute_mode, code)
+                write!(
+                    f,
+                    "( Cmd_compute_toplevel {:?} {} )",
+                    compute_mode,
+                    HaskellString(code)
+                )
+            }
+            LoadHighlightingInfo { path } => {
+                write!(f, "( Cmd_load_highlighting_info {} )", HaskellString(path))
             }
-            LoadHighlightingInfo { path } => write!(f, "( Cmd_load_highlighting_info {:?} )", path),
             TokenHighlighting { path, remove } => {
-                write!(f, "( Cmd_tokenHighlighting {:?} {:?} ", path, remove)
+                write!(
+                    f,
+                    "( Cmd_tokenHighlighting {} {:?} )",
+                    HaskellString(path),
+                    remove
+                )
             }
             Highlight(input) => write!(f, "( Cmd_highlight {} )", input),
             ShowImplicitArgs(show) => {
                 write!(f, "( ShowImplicitArgs {:?} )", HaskellBool::from(*show))
             }
             ToggleImplicitArgs => f.write_str("ToggleImplicitArgs"),
+            ShowIrrelevantArgs(show) => {
+                write!(f, "( ShowIrrelevantArgs {:?} )", HaskellBool::from(*show))
+            }
+            ToggleIrrelevantArgs => f.write_str("ToggleIrrelevantArgs"),
             Give { force, input } => write!(f, "( Cmd_give {:?} {} )", force, input),
             Refine(input) => write!(f, "( Cmd_refine {} )", input),
             Intro { dunno, input } => {
@@ -442,9 +761,12 @@ impl Display for Cmd {
                 input,
             } => write!(f, "( Cmd_compute {:?} {} )", compute_mode, input),
             WhyInScope(input) => write!(f, "( Cmd_why_in_scope {} )", input),
-            WhyInScopeToplevel(name) => write!(f, "( Cmd_why_in_scope_toplevel {:?} )", name),
+            WhyInScopeToplevel(name) => {
+                write!(f, "( Cmd_why_in_scope_toplevel {} )", HaskellString(name))
+            }
             ShowVersion => f.write_str("Cmd_show_version"),
             Abort => f.write_str("Cmd_abort"),
+            Exit => f.write_str("Cmd_exit"),
         }
     }
 }
@@ -0,0 +1,79 @@
+use std::process::Stdio;
+
+use serde_json::{Map, Value};
+use tokio::net::process::Command;
+
+/// The shape of the JSON responses of a generation of Agda.
+/// The types in [`resp`](crate::resp) read both shapes as they are,
+/// [normalizing](Schema::normalize) only copies the range of an interaction point next to it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub enum Schema {
+    /// Agda 2.6.0, interaction points are numbers.
+    #[default]
+    V2_6_0,
+    /// Agda 2.6.1 and later, interaction points are objects like
+    /// `{"id": 0, "range": [...]}`, the range is copied next to the point,
+    /// and the warnings and errors of a goal list are lists of messages.
+    V2_6_1,
+}
+
+/// `2.6.1` out of `Agda version 2.6.1.3`.
+fn version_numbers(text: &str) -> Option<Vec<u32>> {
+    text.split_whitespace().find_map(|word| {
+        let numbers: Result<Vec<u32>, _> = word.split('.').map(str::parse).collect();
+        numbers.ok().filter(|n| n.len() >= 2)
+    })
+}
+
+/// Both shapes of interaction points are read as they are,
+/// [`InteractionPoint`](crate::base::InteractionPoint) keeps the range.
+fn normalize_object(object: &mut Map<String, Value>) {
+    if let Some(point) = object.get("interactionPoint") {
+        // Goals keep the range of their hole.
+        if let Some(range) = point.get("range").cloned() {
+            object.entry("range").or_insert(range);
+        }
+    }
+}
+
+impl Schema {
+    /// The schema of an Agda version, like `Agda version 2.6.1`.
+    pub fn of_version(text: &str) -> Option<Self> {
+        let numbers = version_numbers(text)?;
+        if numbers[..] < [2, 6, 1][..] {
+            Some(Schema::V2_6_0)
+        } else {
+            Some(Schema::V2_6_1)
+        }
+    }
+
+    /// Ask `agda --version`, the default if it can't be told.
+    pub async fn detect(agda_program: &str) -> Self {
+        let output = Command::new(agda_program)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await;
+        let version = match output {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+            Err(_) => return Default::default(),
+        };
+        Self::of_version(&version).unwrap_or_default()
+    }
+
+    /// Copy the range of every interaction point next to it.
+    pub fn normalize(self, value: &mut Value) {
+        if self == Schema::V2_6_0 {
+            return;
+        }
+        match value {
+            Value::Object(object) => {
+                normalize_object(object);
+                object.values_mut().for_each(|v| self.normalize(v));
+            }
+            Value::Array(list) => list.iter_mut().for_each(|v| self.normalize(v)),
+            _ => {}
+        }
+    }
+}
@@ -1,7 +1,7 @@
 /*!
 Accessing Agda's interaction mode via command line.
 This library is created for [agda-tac](https://lib.rs/agda-tac).
-It works with stable rust starting from 1.39.0.
+It works with stable rust starting from 1.62.0.
 
 This crate will work only with master-branch Agda until Agda 2.6.1 is released.
 Tracking issue for the feature is [agda#4183][agda-issue].
@@ -25,6 +25,10 @@ There are more utilities to access Agda, checkout the library documentation to s
 
 This crate deserialize json via `serde_json`,
 and do async process io handling via `tokio`.
+
+Without the `json` feature (on by default), there's no `serde` and no `tokio`:
+only [`cmd`](crate::cmd), [`base`](crate::base) and the plain types of [`resp`](crate::resp)
+are left, enough to build [`IOTCM`](crate::cmd::IOTCM) commands and write them to Agda.
 */
 
 /// Common types (used in both input/output to Agda).
@@ -33,11 +37,25 @@ pub mod base;
 /// Response data types (output of Agda).
 pub mod resp;
 
+/// Borrowed variants of the hot response types,
+/// for embedders processing lots of highlighting data.
+#[cfg(feature = "json")]
+pub mod borrowed;
+
 /// Agda commands (input to Agda).
 pub mod cmd;
 
 /// Invoke Agda in command line and interact with it via stdio.
+#[cfg(feature = "json")]
 pub mod agda;
 
-#[cfg(test)]
+/// Differences between the JSON of Agda versions.
+#[cfg(feature = "json")]
+pub mod compat;
+
+/// Many files over one Agda process.
+#[cfg(feature = "json")]
+pub mod session;
+
+#[cfg(all(test, feature = "json"))]
 mod tests;
@@ -1,38 +1,111 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Error as FmtError, Formatter};
+use std::time::Duration;
 
-use crate::base::{ComputeMode, InteractionPoint, Position, Rewrite, TokenBased};
+#[cfg(feature = "json")]
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq, Hash)]
+use crate::base::{ComputeMode, InteractionPoint, Offset, Rewrite, TokenBased};
+pub use crate::base::{Interval, Intervals, Position};
+
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct Status {
     pub show_implicit_arguments: bool,
     pub checked: bool,
 }
 
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq)]
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct ResponseContextEntry {
     pub original_name: String,
     pub reified_name: String,
+    /// The type, with the [relevance](Self::relevance) and the
+    /// [erasure](Self::is_erased) in front, like `.Nat` or `@0 Nat`.
     pub binding: String,
-    pub in_scope: String,
+    /// `false` if the name is shadowed or otherwise not usable in the goal.
+    #[cfg_attr(feature = "json", serde(deserialize_with = "deserialize_in_scope"))]
+    pub in_scope: bool,
+}
+
+/// Agda 2.6.0 says `"InScope"` or `"NotInScope"`, newer versions use booleans.
+#[cfg(feature = "json")]
+fn deserialize_in_scope<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum InScope {
+        Bool(bool),
+        Name(String),
+    }
+    Ok(match InScope::deserialize(deserializer)? {
+        InScope::Bool(b) => b,
+        InScope::Name(name) => name == "InScope",
+    })
 }
 
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq)]
+/// Whether a binding may be used for computing, see the Agda docs on irrelevance.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Relevance {
+    Relevant,
+    /// `.A`
+    Irrelevant,
+    /// `..A`
+    ShapeIrrelevant,
+}
+
+impl ResponseContextEntry {
+    /// Is there an erasure in front of the binding, and the rest of it.
+    fn erasure(&self) -> (bool, &str) {
+        let binding = self.binding.trim_start();
+        let mut words = binding.splitn(2, ' ');
+        match (words.next(), words.next()) {
+            (Some("@0"), Some(rest)) | (Some("@erased"), Some(rest)) => (true, rest.trim_start()),
+            _ => (false, binding),
+        }
+    }
+
+    pub fn relevance(&self) -> Relevance {
+        let (_, binding) = self.erasure();
+        if binding.starts_with("..") {
+            Relevance::ShapeIrrelevant
+        } else if binding.starts_with('.') {
+            Relevance::Irrelevant
+        } else {
+            Relevance::Relevant
+        }
+    }
+
+    /// Is the binding only usable at compile time, `@0` or `@erased`.
+    pub fn is_erased(&self) -> bool {
+        self.erasure().0
+    }
+
+    /// The type without the relevance and the erasure.
+    pub fn the_type(&self) -> &str {
+        self.erasure().1.trim_start_matches('.')
+    }
+}
+
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct CommandState {
     pub interaction_points: Vec<InteractionPoint>,
     pub current_file: String,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum MakeCase {
     Function,
     ExtendedLambda,
 }
 
-#[serde(tag = "kind")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "kind"))]
 pub enum GoalTypeAux {
     GoalOnly,
     GoalAndHave { expr: String },
@@ -40,46 +113,754 @@ pub enum GoalTypeAux {
 }
 
 /// One goal (visible meta).
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct Goal {
     pub interaction_point: InteractionPoint,
     pub goal_type: String,
+    /// Where the hole is, Agda only tells us since 2.6.1.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub range: Intervals,
 }
 
 /// One unsolved meta (invisible goal).
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct UnsolvedMeta {
     pub pretty_meta: String,
     pub meta_type: String,
 }
 
+impl UnsolvedMeta {
+    /// The meta itself, like `_12`.
+    pub fn name(&self) -> &str {
+        self.pretty_meta.split_whitespace().next().unwrap_or("")
+    }
+}
+
+/// The type of an expression in the top level scope,
+/// the answer of [`Cmd::InferToplevel`](crate::cmd::Cmd::InferToplevel).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
+pub struct InferredType {
+    pub command_state: CommandState,
+    pub time: String,
+    /// The type, despite the name.
+    pub expr: String,
+}
+
+/// What a module has, the answer of
+/// [`Cmd::ShowModuleContentsToplevel`](crate::cmd::Cmd::ShowModuleContentsToplevel).
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct ModuleContents {
+    /// The modules inside the module.
+    pub names: Vec<String>,
+    /// The parameters of the module, like `(A : Set)`.
+    pub telescope: String,
+    /// The names defined in the module, with their types.
+    pub contents: Vec<SearchResult>,
+}
+
+impl ModuleContents {
+    /// The parameters in the [telescope](Self::telescope), one per binder.
+    pub fn parameters(&self) -> Vec<Parameter> {
+        Parameter::parse_telescope(&self.telescope)
+    }
+}
+
+/// How a parameter is given.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Visibility {
+    /// `(A : Set)`
+    Visible,
+    /// `{n : ℕ}`
+    Hidden,
+    /// `⦃ eq : Eq A ⦄` or `{{ eq : Eq A }}`
+    Instance,
+}
+
+/// A binder of a telescope, like `{m n : ℕ}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Parameter {
+    pub names: Vec<String>,
+    pub the_type: String,
+    pub visibility: Visibility,
+}
+
+impl Parameter {
+    /// Split a telescope like `(A : Set) {m n : ℕ}` into its binders.
+    pub fn parse_telescope(text: &str) -> Vec<Self> {
+        let mut params = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            match c {
+                '(' | '{' | '\u{2983}' => {
+                    if depth == 0 {
+                        start = i;
+                    }
+                    depth += 1;
+                }
+                ')' | '}' | '\u{2984}' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        params.extend(Self::parse(&text[start..i + c.len_utf8()]));
+                    }
+                }
+                _ => {}
+            }
+        }
+        params
+    }
+
+    /// One binder, with its brackets.
+    fn parse(binder: &str) -> Option<Self> {
+        let (visibility, open, close) = if binder.starts_with("{{") && binder.ends_with("}}") {
+            (Visibility::Instance, 2, 2)
+        } else if binder.starts_with('\u{2983}') {
+            (
+                Visibility::Instance,
+                '\u{2983}'.len_utf8(),
+                '\u{2984}'.len_utf8(),
+            )
+        } else if binder.starts_with('{') {
+            (Visibility::Hidden, 1, 1)
+        } else {
+            (Visibility::Visible, 1, 1)
+        };
+        let inner = binder.get(open..binder.len().checked_sub(close)?)?;
+        let colon = inner.find(" : ")?;
+        let names: Vec<String> = inner[..colon].split_whitespace().map(From::from).collect();
+        if names.is_empty() {
+            return None;
+        }
+        Some(Parameter {
+            names,
+            the_type: inner[colon + 3..].trim().to_owned(),
+            visibility,
+        })
+    }
+}
+
+impl Display for Parameter {
+    /// Like Agda prints it.
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        let (open, close) = match self.visibility {
+            Visibility::Visible => ("(", ")"),
+            Visibility::Hidden => ("{", "}"),
+            Visibility::Instance => ("\u{2983} ", " \u{2984}"),
+        };
+        let names = self.names.join(" ");
+        write!(f, "{}{} : {}{}", open, names, self.the_type, close)
+    }
+}
+
+/// Goals, warnings and errors, sent after a file is loaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
+pub struct AllGoalsWarnings {
+    pub visible_goals: Vec<Goal>,
+    pub invisible_goals: Vec<UnsolvedMeta>,
+    #[cfg_attr(feature = "json", serde(deserialize_with = "deserialize_warnings"))]
+    pub warnings: Vec<TcWarning>,
+    #[cfg_attr(feature = "json", serde(deserialize_with = "deserialize_warnings"))]
+    pub errors: Vec<TcWarning>,
+}
+
+impl AllGoalsWarnings {
+    /// Where the holes of the goals are, for the goals Agda sent a range for
+    /// (since 2.6.1).
+    pub fn goal_ranges(&self) -> HashMap<InteractionPoint, Intervals> {
+        self.visible_goals
+            .iter()
+            .filter(|g| !g.range.is_empty())
+            .map(|g| (g.interaction_point, g.range.clone()))
+            .collect()
+    }
+
+    /// The messages of the warnings, as Agda 2.6.0 sends them.
+    pub fn warnings_text(&self) -> String {
+        TcWarning::text(&self.warnings)
+    }
+
+    /// The messages of the errors, as Agda 2.6.0 sends them.
+    pub fn errors_text(&self) -> String {
+        TcWarning::text(&self.errors)
+    }
+
+    /// Unsolved constraints mentioned in the warnings and errors.
+    pub fn unsolved_constraints(&self) -> Vec<UnsolvedConstraint> {
+        let mut constraints = UnsolvedConstraint::parse(&self.errors_text());
+        constraints.append(&mut UnsolvedConstraint::parse(&self.warnings_text()));
+        constraints
+    }
+
+    /// Duplicate definitions and shadowing mentioned in the warnings and errors.
+    pub fn duplicate_bindings(&self) -> Vec<DuplicateBinding> {
+        let mut bindings = DuplicateBinding::parse(&self.errors_text());
+        bindings.append(&mut DuplicateBinding::parse(&self.warnings_text()));
+        bindings
+    }
+}
+
+/// One warning (or error) of a load.
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct TcWarning {
+    /// The name of the warning, like `UnreachableClauses`, if Agda tells us.
+    pub kind: Option<String>,
+    /// Where the warning is, if Agda tells us,
+    /// or the location the message of Agda 2.6.0 starts with, like `/path/A.agda:5,7-8`.
+    pub range: Intervals,
+    pub message: String,
+}
+
+impl TcWarning {
+    /// Split Agda 2.6.0's text of warnings, each warning starts at a location line.
+    pub fn parse(text: &str) -> Vec<Self> {
+        let mut warnings: Vec<Vec<&str>> = Vec::new();
+        for line in text.lines() {
+            if line.trim().is_empty() || line.starts_with('\u{2014}') {
+                continue;
+            }
+            match warnings.last_mut() {
+                Some(lines) if parse_range(line.trim()).is_none() => lines.push(line),
+                _ => warnings.push(vec![line]),
+            }
+        }
+        let message = |lines: &[&str]| lines.join("\n").trim_end().to_owned();
+        warnings
+            .into_iter()
+            .map(|lines| TcWarning {
+                range: parse_range(lines[0].trim())
+                    .map(|range| vec![range.interval()])
+                    .unwrap_or_default(),
+                message: message(&lines),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// The messages, one after another.
+    pub fn text(warnings: &[Self]) -> String {
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        messages.join("\n")
+    }
+}
+
+/// Agda 2.6.0 sends the warnings as text, newer versions send a list
+/// of strings or of objects with ranges.
+#[cfg(feature = "json")]
+fn deserialize_warnings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<TcWarning>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Item {
+        Text(String),
+        Structured(TcWarning),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Warnings {
+        Text(String),
+        List(Vec<Item>),
+    }
+    Ok(match Warnings::deserialize(deserializer)? {
+        Warnings::Text(s) => TcWarning::parse(&s),
+        Warnings::List(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Item::Text(message) => TcWarning {
+                    message,
+                    ..Default::default()
+                },
+                Item::Structured(w) => w,
+            })
+            .collect(),
+    })
+}
+
+/// One constraint, the answer of [`Cmd::Constraints`](crate::cmd::Cmd::Constraints)
+/// is a list of them.
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct Constraint {
+    /// Like `_12 := suc ?3`.
+    pub constraint: String,
+    /// Where the constraint comes from, if Agda tells us.
+    pub range: Intervals,
+}
+
+impl Constraint {
+    /// Does the constraint mention the meta, see [`mentions_meta`](self::mentions_meta).
+    pub fn mentions_meta(&self, meta: &str) -> bool {
+        mentions_meta(&self.constraint, meta)
+    }
+}
+
+/// Agda 2.6.0 sends the constraints as text, one per line,
+/// newer versions send a list of strings or of objects with ranges.
+#[cfg(feature = "json")]
+fn deserialize_constraints<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Constraint>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Item {
+        Text(String),
+        Structured(Constraint),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Constraints {
+        Text(String),
+        List(Vec<Item>),
+    }
+    let text = |s: &str| Constraint {
+        constraint: s.trim().to_owned(),
+        range: Vec::new(),
+    };
+    Ok(match Constraints::deserialize(deserializer)? {
+        Constraints::Text(s) => s
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(text)
+            .collect(),
+        Constraints::List(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Item::Text(s) => text(&s),
+                Item::Structured(c) => c,
+            })
+            .collect(),
+    })
+}
+
+/// One unsolved constraint, parsed from Agda's textual diagnostics.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsolvedConstraint {
+    /// Like `/path/A.agda:5,7-8`, if Agda tells us.
+    pub location: Option<String>,
+    pub constraint: String,
+}
+
+/// Metas (`_12`) and interaction points (`?3`) in the text.
+fn meta_tokens(s: &str) -> impl Iterator<Item = &str> {
+    s.split(|c: char| c.is_whitespace() || "(){}[],".contains(c))
+        .filter(|t| {
+            (t.starts_with('_') || t.starts_with('?'))
+                && t.len() > 1
+                && t[1..].chars().all(|c| c.is_ascii_digit())
+        })
+}
+
+/// Does the text mention the meta, like `_12`, or the interaction point, like `?3`.
+pub fn mentions_meta(text: &str, meta: &str) -> bool {
+    meta_tokens(text).any(|t| t == meta)
+}
+
+fn is_constraints_heading(line: &str) -> bool {
+    let line = line.to_lowercase();
+    line.starts_with("unsolved constraints")
+        || line.starts_with("failed to solve the following constraints")
+}
+
+impl UnsolvedConstraint {
+    /// Find the unsolved constraints sections in Agda's diagnostics, which look like:
+    ///
+    /// ```text
+    /// /path/A.agda:5,7-8
+    /// Failed to solve the following constraints:
+    ///   _12 := suc ?3 [blocked on problem 2]
+    /// ```
+    pub fn parse(text: &str) -> Vec<Self> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut constraints = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            if !is_constraints_heading(line.trim()) {
+                continue;
+            }
+            let location = i
+                .checked_sub(1)
+                .map(|j| lines[j].trim())
+                .filter(|l| l.contains(':'))
+                .map(|l| l.to_owned());
+            let body = lines[i + 1..]
+                .iter()
+                .take_while(|l| l.starts_with(char::is_whitespace) && !l.trim().is_empty());
+            let mut indent = None;
+            for line in body {
+                let this_indent = line.len() - line.trim_start().len();
+                match constraints.last_mut() {
+                    Some(UnsolvedConstraint { constraint, .. }) if matches!(indent, Some(i) if this_indent > i) =>
+                    {
+                        constraint.push(' ');
+                        constraint.push_str(line.trim());
+                    }
+                    _ => {
+                        indent = Some(this_indent);
+                        constraints.push(UnsolvedConstraint {
+                            location: location.clone(),
+                            constraint: line.trim().to_owned(),
+                        })
+                    }
+                }
+            }
+        }
+        constraints
+    }
+
+    /// Does the constraint involve this goal?
+    /// Either the goal appears in it directly,
+    /// or they share a meta variable.
+    pub fn mentions_goal(&self, goal: &Goal) -> bool {
+        let this_goal = format!("?{}", goal.interaction_point);
+        let goal_metas: Vec<&str> = meta_tokens(&goal.goal_type).collect();
+        meta_tokens(&self.constraint).any(|t| t == this_goal || goal_metas.contains(&t))
+    }
+}
+
+/// A part of a file an error is about, like
+///
+/// ```text
+/// /path/A.agda:9,1-10,3
+/// Unreachable clause
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct LabeledRange {
+    /// What Agda says about this part.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub label: String,
+    pub file: String,
+    /// 1-based line and column.
+    pub start: (u32, u32),
+    /// 1-based line and column, the column is exclusive.
+    pub end: (u32, u32),
+}
+
+/// Parse `/path/A.agda:9,1-10,3` or `/path/A.agda:5,7-8`.
+fn parse_range(line: &str) -> Option<LabeledRange> {
+    let colon = line.rfind(':')?;
+    let (file, range) = (&line[..colon], &line[colon + 1..]);
+    let number = |s: &str| s.trim().parse::<u32>().ok();
+    let mut ends = range.splitn(2, '-');
+    let mut start = ends.next()?.splitn(2, ',');
+    let start = (number(start.next()?)?, number(start.next()?)?);
+    let end = match ends.next() {
+        None => start,
+        Some(end) => match end.find(',') {
+            Some(comma) => (number(&end[..comma])?, number(&end[comma + 1..])?),
+            None => (start.0, number(end)?),
+        },
+    };
+    if file.is_empty() {
+        return None;
+    }
+    let (label, file) = (String::new(), file.to_owned());
+    Some(LabeledRange {
+        label,
+        file,
+        start,
+        end,
+    })
+}
+
+impl LabeledRange {
+    /// The range as an interval, of which the offsets are unknown (`0`).
+    pub fn interval(&self) -> Interval {
+        let at = |(line, col)| Position {
+            offset: 0,
+            line,
+            col,
+        };
+        Interval {
+            start: at(self.start),
+            end: at(self.end),
+        }
+    }
+
+    /// Every location line in Agda's diagnostics, labeled with the text below it.
+    pub fn parse(text: &str) -> Vec<Self> {
+        let mut ranges: Vec<Self> = Vec::new();
+        for line in text.lines() {
+            if let Some(range) = parse_range(line.trim()) {
+                ranges.push(range);
+            } else if let Some(range) = ranges.last_mut() {
+                if line.trim().is_empty() || line.starts_with('\u{2014}') {
+                    continue;
+                }
+                if !range.label.is_empty() {
+                    range.label.push(' ');
+                }
+                range.label.push_str(line.trim());
+            }
+        }
+        ranges
+    }
+}
+
+impl Display for LabeledRange {
+    /// In the format of Agda's diagnostics, which [`parse`](Self::parse) reads.
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        let ((l1, c1), (l2, c2)) = (self.start, self.end);
+        if l1 == l2 {
+            write!(f, "{}:{},{}-{}", self.file, l1, c1, c2)?;
+        } else {
+            write!(f, "{}:{},{}-{},{}", self.file, l1, c1, l2, c2)?;
+        }
+        if !self.label.is_empty() {
+            write!(f, "\n{}", self.label)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error of [`DisplayInfo::Error`](self::DisplayInfo::Error) in newer versions of Agda.
+/// The message starts with the places of the error, like each of the overlapping clauses,
+/// which [`LabeledRange::parse`](self::LabeledRange::parse) finds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ErrorObject {
+    #[cfg_attr(feature = "json", serde(default))]
+    pub message: String,
+    /// What went wrong, like `TypeError` or `ParseError`, if Agda tells us.
+    #[cfg_attr(
+        feature = "json",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub kind: Option<String>,
+    /// Where the error is, if Agda tells us, one interval for each place.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub range: Intervals,
+}
+
+impl ErrorObject {
+    /// The message in the format of the older versions of Agda, which is the same.
+    pub fn to_message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+/// How a name is bound twice.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DuplicateKind {
+    /// `Multiple definitions of f.`
+    Definition,
+    /// A binding shadows another one.
+    Shadowing,
+}
+
+/// A name bound twice, parsed from Agda's textual diagnostics.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateBinding {
+    pub kind: DuplicateKind,
+    /// What Agda says about it, like `Multiple definitions of f.`
+    pub message: String,
+    /// Where the new binding is, like `/path/A.agda:5,1-2`.
+    pub location: Option<String>,
+    /// Where the original binding is.
+    pub original: Option<String>,
+}
+
+/// Does the line look like `/path/A.agda:5,1-2`.
+fn is_location(line: &str) -> bool {
+    match line.rfind(':') {
+        Some(i) => line[i + 1..].starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Is the line Agda's warning about shadowing, like `Shadowing in telescope: x`
+/// or `x shadows a binding at /path/A.agda:7,5-6`. Names that just contain
+/// `shadow` don't count.
+fn is_shadowing(line: &str) -> bool {
+    if line.starts_with("Shadowing in telescope") {
+        return true;
+    }
+    let mut words = line.split_whitespace();
+    words.next().is_some() && words.next() == Some("shadows")
+}
+
+impl DuplicateBinding {
+    /// Find the messages about duplicate bindings, which look like:
+    ///
+    /// ```text
+    /// /path/A.agda:5,1-2
+    /// Multiple definitions of f. Previous definition at
+    /// /path/A.agda:3,1-2
+    /// ```
+    pub fn parse(text: &str) -> Vec<Self> {
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        let mut bindings = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let kind = if line.starts_with("Multiple definitions of") {
+                DuplicateKind::Definition
+            } else if is_shadowing(line) {
+                DuplicateKind::Shadowing
+            } else {
+                continue;
+            };
+            let location = i
+                .checked_sub(1)
+                .map(|j| lines[j])
+                .filter(|l| is_location(l))
+                .map(|l| l.to_owned());
+            let (message, original) = match line.find(" at ") {
+                Some(at) if is_location(&line[at + 4..]) => (&line[..at], Some(&line[at + 4..])),
+                _ => {
+                    let message = line
+                        .trim_end_matches(" at")
+                        .trim_end_matches(" Previous definition");
+                    let next = lines.get(i + 1).filter(|l| is_location(l));
+                    (message, next.copied())
+                }
+            };
+            bindings.push(DuplicateBinding {
+                kind,
+                message: message.to_owned(),
+                location,
+                original: original.map(|l| l.to_owned()),
+            })
+        }
+        bindings
+    }
+}
+
+impl Display for DuplicateBinding {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        f.write_str(&self.message)?;
+        if let Some(original) = &self.original {
+            write!(f, "\n  {}: first defined here", original)?;
+        }
+        Ok(())
+    }
+}
+
+/// One step of how a name came into scope, like
+/// `the opening of Agda.Builtin.Nat at /path/A.agda:3,13-29`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScopeStep {
+    pub how: String,
+    pub range: Option<LabeledRange>,
+}
+
+/// One of the things a name is in scope as, like `a datatype Agda.Builtin.Nat.Nat`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScopeBinding {
+    pub what: String,
+    /// Where it's bound, for variables.
+    pub range: Option<LabeledRange>,
+    /// How it's brought into scope, through the modules, ending with its definition.
+    pub steps: Vec<ScopeStep>,
+}
+
+impl ScopeBinding {
+    /// Where the name is defined, for jumping there.
+    pub fn definition_site(&self) -> Option<&LabeledRange> {
+        let definition = self.steps.iter().find(|s| s.how == "its definition");
+        match definition {
+            Some(step) => step.range.as_ref(),
+            None => self.range.as_ref(),
+        }
+    }
+}
+
+/// The answer of [`Cmd::WhyInScope`](crate::cmd::Cmd::WhyInScope),
+/// parsed from the message of [`DisplayInfo::WhyInScope`](self::DisplayInfo::WhyInScope).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScopeExplanation {
+    pub name: String,
+    /// Empty if the name is not in scope.
+    pub bindings: Vec<ScopeBinding>,
+}
+
+/// `the opening of M at /path/A.agda:3,13-29` into the text and the range.
+fn split_location(text: &str) -> (String, Option<LabeledRange>) {
+    if let Some(at) = text.rfind(" at ") {
+        if let Some(range) = parse_range(&text[at + 4..]) {
+            return (text[..at].to_owned(), Some(range));
+        }
+    }
+    (text.to_owned(), None)
+}
+
+impl ScopeExplanation {
+    /// Read Agda's explanation, which looks like:
+    ///
+    /// ```text
+    /// Nat is in scope as
+    ///   * a datatype Agda.Builtin.Nat.Nat brought into scope by
+    ///     - the opening of Agda.Builtin.Nat at /path/A.agda:3,13-29
+    ///     - its definition at /lib/Agda/Builtin/Nat.agda:8,6-9
+    /// ```
+    pub fn parse(message: &str) -> Self {
+        let mut lines = message.lines().map(str::trim);
+        let first = lines.next().unwrap_or("");
+        let name = match first.find(" is ") {
+            Some(is) => first[..is].to_owned(),
+            None => first.to_owned(),
+        };
+        let mut bindings: Vec<ScopeBinding> = Vec::new();
+        for line in lines {
+            let mut words = line.splitn(2, ' ');
+            match (words.next(), words.next(), bindings.last_mut()) {
+                (Some("*"), Some(rest), _) => {
+                    let rest = rest.trim_end_matches(" brought into scope by");
+                    let (what, range) = split_location(rest);
+                    let what = what.trim_end_matches(" bound").to_owned();
+                    let steps = Vec::new();
+                    bindings.push(ScopeBinding { what, range, steps });
+                }
+                (Some("-"), Some(rest), Some(binding)) => {
+                    let (how, range) = split_location(rest);
+                    binding.steps.push(ScopeStep { how, range });
+                }
+                _ => {}
+            }
+        }
+        ScopeExplanation { name, bindings }
+    }
+}
+
 /// Information about one goal.
-#[serde(tag = "kind")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "kind"))]
 pub enum GoalInfo {
     HelperFunction {
         signature: String,
     },
     NormalForm {
-        #[serde(rename = "computeMode")]
+        #[cfg_attr(feature = "json", serde(rename = "computeMode"))]
         compute_mode: ComputeMode,
         expr: String,
     },
     GoalType {
         rewrite: Rewrite,
-        #[serde(rename = "typeAux")]
+        #[cfg_attr(feature = "json", serde(rename = "typeAux"))]
         type_aux: GoalTypeAux,
-        #[serde(rename = "type")]
+        #[cfg_attr(feature = "json", serde(rename = "type"))]
         the_type: String,
         entries: Vec<ResponseContextEntry>,
-        #[serde(rename = "outputForms")]
+        #[cfg_attr(feature = "json", serde(rename = "outputForms"))]
         constraints: Vec<String>,
     },
     CurrentGoal {
         rewrite: Rewrite,
-        #[serde(rename = "type")]
+        #[cfg_attr(feature = "json", serde(rename = "type"))]
         the_type: String,
     },
     InferredType {
@@ -87,67 +868,162 @@ pub enum GoalInfo {
     },
 }
 
+impl GoalInfo {
+    /// The type of the goal, if this is an answer to a goal type query.
+    pub fn the_type(&self) -> Option<&str> {
+        match self {
+            GoalInfo::GoalType { the_type, .. } | GoalInfo::CurrentGoal { the_type, .. } => {
+                Some(the_type)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A name found by [`Cmd::SearchAboutToplevel`](crate::cmd::Cmd::SearchAboutToplevel),
+/// or in the [contents of a module](self::DisplayInfo::ModuleContents).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct SearchResult {
+    pub name: String,
+    pub term: String,
+}
+
+/// How long Agda took, as it prints CPU time, like `1,234ms`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(from = "String", into = "String"))]
+pub struct CpuTime {
+    pub raw: String,
+    /// `None` if the text is not in a format we know.
+    pub duration: Option<Duration>,
+}
+
+impl CpuTime {
+    /// Read Agda's format, milliseconds with thousands separators, maybe after `Time:`.
+    pub fn parse(text: &str) -> Option<Duration> {
+        let text = text.trim().trim_start_matches("Time:").trim();
+        if !text.ends_with("ms") {
+            return None;
+        }
+        let millis = text.trim_end_matches("ms").trim().replace(',', "");
+        millis.parse().ok().map(Duration::from_millis)
+    }
+}
+
+impl From<String> for CpuTime {
+    fn from(raw: String) -> Self {
+        let duration = CpuTime::parse(&raw);
+        CpuTime { raw, duration }
+    }
+}
+
+impl From<CpuTime> for String {
+    fn from(time: CpuTime) -> Self {
+        time.raw
+    }
+}
+
+impl Display for CpuTime {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str(&self.raw)
+    }
+}
+
+/// A line of the profile Agda prints as running info with `--profile`,
+/// like `Typing           1,234ms` or, with `--profile=definitions`, `A.f   512ms`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProfileEntry {
+    /// What the time is spent on, a phase of Agda or a definition.
+    pub account: String,
+    pub time: CpuTime,
+}
+
+impl ProfileEntry {
+    pub fn parse(line: &str) -> Option<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // The columns after the time, if any, are percentages.
+        let at = words.iter().position(|w| CpuTime::parse(w).is_some())?;
+        if at == 0 {
+            return None;
+        }
+        Some(ProfileEntry {
+            account: words[..at].join(" "),
+            time: words[at].to_owned().into(),
+        })
+    }
+
+    /// The entries of the profile in `text`, other lines are skipped.
+    pub fn parse_all(text: &str) -> Vec<Self> {
+        text.lines().filter_map(Self::parse).collect()
+    }
+}
+
 /// Something that is displayed in the Emacs mode,
 /// serialized with more details.
-#[serde(tag = "kind")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "kind"))]
 pub enum DisplayInfo {
     CompilationOk {
-        warnings: String,
-        errors: String,
+        #[cfg_attr(feature = "json", serde(deserialize_with = "deserialize_warnings"))]
+        warnings: Vec<TcWarning>,
+        #[cfg_attr(feature = "json", serde(deserialize_with = "deserialize_warnings"))]
+        errors: Vec<TcWarning>,
     },
     Constraints {
-        // TODO
-    },
-    AllGoalsWarnings {
-        #[serde(rename = "visibleGoals")]
-        visible_goals: Vec<Goal>,
-        #[serde(rename = "invisibleGoals")]
-        invisible_goals: Vec<UnsolvedMeta>,
-        warnings: String,
-        errors: String,
+        #[cfg_attr(
+            feature = "json",
+            serde(default, deserialize_with = "deserialize_constraints")
+        )]
+        constraints: Vec<Constraint>,
     },
+    AllGoalsWarnings(AllGoalsWarnings),
     Time {
-        time: String,
+        time: CpuTime,
     },
     Error {
         message: Option<String>,
+        /// Newer versions of Agda nest the error in an object.
+        #[cfg_attr(
+            feature = "json",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        error: Option<ErrorObject>,
     },
-    IntroNotFound {
-        // TODO
-    },
+    /// [`Cmd::Intro`](crate::cmd::Cmd::Intro) has nothing to introduce.
+    IntroNotFound {},
+    /// [`Cmd::Intro`](crate::cmd::Cmd::Intro) doesn't know which constructor to use.
     IntroConstructorUnknown {
-        // TODO
+        #[cfg_attr(feature = "json", serde(default))]
+        constructors: Vec<String>,
     },
     Auto {
         info: String,
     },
-    ModuleContents {
-        // TODO
-    },
+    ModuleContents(ModuleContents),
     SearchAbout {
         search: String,
-        // TODO
+        #[cfg_attr(feature = "json", serde(default))]
+        results: Vec<SearchResult>,
     },
+    /// Agda only sends the explanation as text, see
+    /// [`ScopeExplanation::parse`](self::ScopeExplanation::parse).
     WhyInScope {
-        // TODO
+        #[cfg_attr(feature = "json", serde(default))]
+        message: String,
     },
     NormalForm {
-        #[serde(rename = "computeMode")]
+        #[cfg_attr(feature = "json", serde(rename = "computeMode"))]
         compute_mode: ComputeMode,
-        #[serde(rename = "commandState")]
-        command_state: CommandState,
-        time: String,
-        expr: String,
-    },
-    InferredType {
-        #[serde(rename = "commandState")]
+        #[cfg_attr(feature = "json", serde(rename = "commandState"))]
         command_state: CommandState,
         time: String,
         expr: String,
     },
+    InferredType(InferredType),
     Context {
-        #[serde(rename = "interactionPoint")]
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoint"))]
         interaction_point: InteractionPoint,
         context: Vec<ResponseContextEntry>,
     },
@@ -155,68 +1031,405 @@ pub enum DisplayInfo {
         version: String,
     },
     GoalSpecific {
-        #[serde(rename = "interactionPoint")]
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoint"))]
         interaction_point: InteractionPoint,
-        #[serde(rename = "goalInfo")]
+        #[cfg_attr(feature = "json", serde(rename = "goalInfo"))]
         goal_info: GoalInfo,
     },
 }
 
+impl DisplayInfo {
+    /// The `kind` tag Agda uses for this display info.
+    pub fn kind(&self) -> &'static str {
+        use DisplayInfo::*;
+        match self {
+            CompilationOk { .. } => "CompilationOk",
+            Constraints { .. } => "Constraints",
+            AllGoalsWarnings(..) => "AllGoalsWarnings",
+            Time { .. } => "Time",
+            Error { .. } => "Error",
+            IntroNotFound { .. } => "IntroNotFound",
+            IntroConstructorUnknown { .. } => "IntroConstructorUnknown",
+            Auto { .. } => "Auto",
+            ModuleContents(..) => "ModuleContents",
+            SearchAbout { .. } => "SearchAbout",
+            WhyInScope { .. } => "WhyInScope",
+            NormalForm { .. } => "NormalForm",
+            InferredType(..) => "InferredType",
+            Context { .. } => "Context",
+            Version { .. } => "Version",
+            GoalSpecific { .. } => "GoalSpecific",
+        }
+    }
+}
+
+/// What a token is, one of the [`atoms`](self::AspectHighlight::atoms) Agda highlights it with.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Aspect {
+    // Syntax.
+    Comment,
+    Keyword,
+    String,
+    Number,
+    Hole,
+    Symbol,
+    PrimitiveType,
+    Pragma,
+    Background,
+    Markup,
+
+    // Kinds of names.
+    Bound,
+    Generalizable,
+    InductiveConstructor,
+    CoinductiveConstructor,
+    Datatype,
+    Field,
+    Function,
+    Module,
+    Postulate,
+    Primitive,
+    Record,
+    Argument,
+    Macro,
+    Operator,
+
+    // Problems and other information from the type checker.
+    Error,
+    ErrorWarning,
+    DottedPattern,
+    UnsolvedMeta,
+    UnsolvedConstraint,
+    TerminationProblem,
+    PositivityProblem,
+    Deadcode,
+    ShadowingInTelescope,
+    CoverageProblem,
+    IncompletePattern,
+    TypeChecks,
+    MissingDefinition,
+    CatchallClause,
+    ConfluenceProblem,
+    /// An atom we don't know, from a newer version of Agda.
+    Other(String),
+}
+
+impl Aspect {
+    pub fn parse(atom: &str) -> Self {
+        match atom {
+            "comment" => Aspect::Comment,
+            "keyword" => Aspect::Keyword,
+            "string" => Aspect::String,
+            "number" => Aspect::Number,
+            "hole" => Aspect::Hole,
+            "symbol" => Aspect::Symbol,
+            "primitivetype" => Aspect::PrimitiveType,
+            "pragma" => Aspect::Pragma,
+            "background" => Aspect::Background,
+            "markup" => Aspect::Markup,
+            "bound" => Aspect::Bound,
+            "generalizable" => Aspect::Generalizable,
+            "inductiveconstructor" => Aspect::InductiveConstructor,
+            "coinductiveconstructor" => Aspect::CoinductiveConstructor,
+            "datatype" => Aspect::Datatype,
+            "field" => Aspect::Field,
+            "function" => Aspect::Function,
+            "module" => Aspect::Module,
+            "postulate" => Aspect::Postulate,
+            "primitive" => Aspect::Primitive,
+            "record" => Aspect::Record,
+            "argument" => Aspect::Argument,
+            "macro" => Aspect::Macro,
+            "operator" => Aspect::Operator,
+            "error" => Aspect::Error,
+            "errorwarning" => Aspect::ErrorWarning,
+            "dottedpattern" => Aspect::DottedPattern,
+            "unsolvedmeta" => Aspect::UnsolvedMeta,
+            "unsolvedconstraint" => Aspect::UnsolvedConstraint,
+            "terminationproblem" => Aspect::TerminationProblem,
+            "positivityproblem" => Aspect::PositivityProblem,
+            "deadcode" => Aspect::Deadcode,
+            "shadowingintelescope" => Aspect::ShadowingInTelescope,
+            "coverageproblem" => Aspect::CoverageProblem,
+            "incompletepattern" => Aspect::IncompletePattern,
+            "typechecks" => Aspect::TypeChecks,
+            "missingdefinition" => Aspect::MissingDefinition,
+            "catchallclause" => Aspect::CatchallClause,
+            "confluenceproblem" => Aspect::ConfluenceProblem,
+            atom => Aspect::Other(atom.to_owned()),
+        }
+    }
+
+    /// The atom, as Agda sends it.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Aspect::Comment => "comment",
+            Aspect::Keyword => "keyword",
+            Aspect::String => "string",
+            Aspect::Number => "number",
+            Aspect::Hole => "hole",
+            Aspect::Symbol => "symbol",
+            Aspect::PrimitiveType => "primitivetype",
+            Aspect::Pragma => "pragma",
+            Aspect::Background => "background",
+            Aspect::Markup => "markup",
+            Aspect::Bound => "bound",
+            Aspect::Generalizable => "generalizable",
+            Aspect::InductiveConstructor => "inductiveconstructor",
+            Aspect::CoinductiveConstructor => "coinductiveconstructor",
+            Aspect::Datatype => "datatype",
+            Aspect::Field => "field",
+            Aspect::Function => "function",
+            Aspect::Module => "module",
+            Aspect::Postulate => "postulate",
+            Aspect::Primitive => "primitive",
+            Aspect::Record => "record",
+            Aspect::Argument => "argument",
+            Aspect::Macro => "macro",
+            Aspect::Operator => "operator",
+            Aspect::Error => "error",
+            Aspect::ErrorWarning => "errorwarning",
+            Aspect::DottedPattern => "dottedpattern",
+            Aspect::UnsolvedMeta => "unsolvedmeta",
+            Aspect::UnsolvedConstraint => "unsolvedconstraint",
+            Aspect::TerminationProblem => "terminationproblem",
+            Aspect::PositivityProblem => "positivityproblem",
+            Aspect::Deadcode => "deadcode",
+            Aspect::ShadowingInTelescope => "shadowingintelescope",
+            Aspect::CoverageProblem => "coverageproblem",
+            Aspect::IncompletePattern => "incompletepattern",
+            Aspect::TypeChecks => "typechecks",
+            Aspect::MissingDefinition => "missingdefinition",
+            Aspect::CatchallClause => "catchallclause",
+            Aspect::ConfluenceProblem => "confluenceproblem",
+            Aspect::Other(atom) => atom,
+        }
+    }
+
+    /// Is it the kind of a name, like [`Function`](Self::Function) or [`Bound`](Self::Bound).
+    pub fn is_name(&self) -> bool {
+        use Aspect::*;
+        matches!(
+            self,
+            Bound
+                | Generalizable
+                | InductiveConstructor
+                | CoinductiveConstructor
+                | Datatype
+                | Field
+                | Function
+                | Module
+                | Postulate
+                | Primitive
+                | Record
+                | Argument
+                | Macro
+        )
+    }
+
+    /// Is it a problem Agda found, like [`Error`](Self::Error) or [`UnsolvedMeta`](Self::UnsolvedMeta).
+    pub fn is_problem(&self) -> bool {
+        use Aspect::*;
+        matches!(
+            self,
+            Error
+                | ErrorWarning
+                | UnsolvedMeta
+                | UnsolvedConstraint
+                | TerminationProblem
+                | PositivityProblem
+                | Deadcode
+                | ShadowingInTelescope
+                | CoverageProblem
+                | IncompletePattern
+                | MissingDefinition
+                | ConfluenceProblem
+        )
+    }
+}
+
+impl Display for Aspect {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str(self.as_str())
+    }
+}
+
 /// A token highlighting information.
 /// The token is somehow called `Aspect` in Agda.
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct AspectHighlight {
-    pub range: (Position, Position),
+    /// Agda only sends the offsets.
+    #[cfg_attr(feature = "json", serde(with = "crate::base::offsets"))]
+    pub range: Interval,
     pub atoms: Vec<String>,
     pub token_based: TokenBased,
     pub note: Option<String>,
     pub definition_site: Option<DefinitionSite>,
 }
 
+impl AspectHighlight {
+    /// Does a [`Resp::ClearHighlighting`](self::Resp::ClearHighlighting) remove it.
+    /// `TokenBased` only clears the token-based highlighting,
+    /// `NotOnlyTokenBased` clears everything.
+    pub fn is_cleared_by(&self, token_based: TokenBased) -> bool {
+        match token_based {
+            TokenBased::TokenBased => self.token_based == TokenBased::TokenBased,
+            TokenBased::NotOnlyTokenBased => true,
+        }
+    }
+
+    /// The [atoms](Self::atoms), parsed.
+    pub fn aspects(&self) -> Vec<Aspect> {
+        self.atoms.iter().map(|a| Aspect::parse(a)).collect()
+    }
+
+    /// Is the token highlighted as this aspect.
+    pub fn has_aspect(&self, aspect: &Aspect) -> bool {
+        self.atoms.iter().any(|a| a == aspect.as_str())
+    }
+}
+
 /// Jump to library definition information.
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct DefinitionSite {
     pub filepath: String,
-    pub position: Position,
+    pub position: Offset,
 }
 
 /// A list of token highlighting information.
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
 pub struct HighlightingInfo {
     pub remove: bool,
     pub payload: Vec<AspectHighlight>,
 }
 
-/// Result of a "give" action.
+/// Result of a "give" action, what to put in place of the hole.
 ///
-/// The structure is very mysterious.
-#[serde(rename_all = "camelCase")]
-#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq, Hash)]
-pub struct GiveResult {
-    pub str: Option<String>,
-    pub paren: Option<bool>,
+/// Agda sends `{"str": ..}` or `{"paren": ..}`, a bare string or boolean is fine too.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "json",
+    serde(try_from = "RawGiveResult", into = "RawGiveResult")
+)]
+pub enum GiveResult {
+    /// Agda's own text for the expression.
+    GiveString(String),
+    /// The given expression, in parentheses.
+    Paren,
+    /// The given expression as it is.
+    NoParen,
+}
+
+/// The shapes of [`GiveResult`](self::GiveResult) Agda may send.
+#[cfg(feature = "json")]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawGiveResult {
+    Object {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        str: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        paren: Option<bool>,
+    },
+    Str(String),
+    Paren(bool),
+}
+
+#[cfg(feature = "json")]
+impl std::convert::TryFrom<RawGiveResult> for GiveResult {
+    type Error = &'static str;
+
+    fn try_from(raw: RawGiveResult) -> Result<Self, Self::Error> {
+        match raw {
+            RawGiveResult::Object {
+                str: Some(s),
+                paren: None,
+            }
+            | RawGiveResult::Str(s) => Ok(GiveResult::GiveString(s)),
+            RawGiveResult::Object {
+                str: None,
+                paren: Some(paren),
+            }
+            | RawGiveResult::Paren(paren) => Ok(GiveResult::paren(paren)),
+            RawGiveResult::Object { .. } => Err("expected exactly one of `str` and `paren`"),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<GiveResult> for RawGiveResult {
+    fn from(result: GiveResult) -> Self {
+        let (str, paren) = match result {
+            GiveResult::GiveString(s) => (Some(s), None),
+            GiveResult::Paren => (None, Some(true)),
+            GiveResult::NoParen => (None, Some(false)),
+        };
+        RawGiveResult::Object { str, paren }
+    }
 }
 
 impl GiveResult {
-    /// The return value is not actually a result.
-    /// I just want an `Either` type.
+    /// `Paren` or `NoParen`.
+    pub fn paren(paren: bool) -> Self {
+        if paren {
+            GiveResult::Paren
+        } else {
+            GiveResult::NoParen
+        }
+    }
+
+    /// The text that replaces the hole `expr` was given to.
+    pub fn text(&self, expr: &str) -> String {
+        match self {
+            GiveResult::GiveString(s) => s.clone(),
+            GiveResult::Paren => format!("({})", expr),
+            GiveResult::NoParen => expr.to_owned(),
+        }
+    }
+
+    /// The string, or whether the expression needs parentheses.
     pub fn into_either(self) -> Result<String, bool> {
-        match (self.str, self.paren) {
-            (Some(s), None) => Ok(s),
-            (None, Some(b)) => Err(b),
-            _ => unreachable!(),
+        match self {
+            GiveResult::GiveString(s) => Ok(s),
+            GiveResult::Paren => Err(true),
+            GiveResult::NoParen => Err(false),
         }
     }
 }
 
+/// The answer of [`Cmd::Intro`](crate::cmd::Cmd::Intro).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Intro {
+    /// What to put in the hole, the new goals come after it like after a give.
+    Given(GiveResult),
+    /// There's nothing to introduce.
+    NotFound,
+    /// The constructors it could be, give one of them instead.
+    Constructors(Vec<String>),
+}
+
+/// A goal solved by [`Cmd::SolveAll`](crate::cmd::Cmd::SolveAll).
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
+pub struct Solution {
+    pub interaction_point: InteractionPoint,
+    pub expression: String,
+}
+
 /// Agda response.
 ///
 /// TODO: This enum is incomplete, contribution is welcomed.
-#[serde(tag = "kind")]
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "kind"))]
 pub enum Resp {
     HighlightingInfo {
         info: Option<HighlightingInfo>,
@@ -226,45 +1439,69 @@ pub enum Resp {
     Status {
         status: Status,
     },
+    /// Older versions of Agda only send the offset of the position.
     JumpToError {
         filepath: String,
-        position: i32,
+        position: Position,
     },
     InteractionPoints {
-        #[serde(rename = "interactionPoints")]
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoints"))]
         interaction_points: Vec<InteractionPoint>,
     },
     GiveAction {
-        #[serde(rename = "giveResult")]
+        #[cfg_attr(feature = "json", serde(rename = "giveResult"))]
         give_result: GiveResult,
-        #[serde(rename = "interactionPoint")]
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoint"))]
         interaction_point: InteractionPoint,
     },
     /// Response is list of printed clauses.
     MakeCase {
         variant: MakeCase,
-        #[serde(rename = "interactionPoint")]
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoint"))]
         interaction_point: InteractionPoint,
         clauses: Vec<String>,
     },
     /// Solution for one or more meta-variables.
     SolveAll {
-        // TODO
+        #[cfg_attr(feature = "json", serde(default))]
+        solutions: Vec<Solution>,
     },
     DisplayInfo {
         info: Option<DisplayInfo>,
     },
     /// The integer is the message's debug level.
     RunningInfo {
-        #[serde(rename = "debugLevel")]
+        #[cfg_attr(feature = "json", serde(rename = "debugLevel"))]
         debug_level: i32,
         message: String,
     },
     ClearRunningInfo,
-    /// Clear highlighting of the given kind.
+    /// Clear highlighting of the given kind,
+    /// see [`AspectHighlight::is_cleared_by`](self::AspectHighlight::is_cleared_by).
     ClearHighlighting {
-        // TODO
+        #[cfg_attr(feature = "json", serde(rename = "tokenBased", default))]
+        token_based: TokenBased,
     },
     /// A command sent when an abort command has completed successfully.
     DoneAborting,
+    /// A command sent when an exit command is about to be completed.
+    DoneExiting,
+    /// The term found by Mimer, or `None` if it found nothing.
+    Mimer {
+        #[cfg_attr(feature = "json", serde(rename = "interactionPoint"))]
+        interaction_point: InteractionPoint,
+        #[cfg_attr(feature = "json", serde(default))]
+        solution: Option<String>,
+    },
+    /// A line of Agda's output that we don't understand, the `error` says why.
+    /// It's given instead of failing, so one unknown message doesn't end the session.
+    /// Agda never sends this itself.
+    ParseFailure {
+        raw: String,
+        error: String,
+    },
+    /// A response of a kind we don't know yet, as sent by newer versions of Agda.
+    /// Known kinds in a shape we don't know are errors, not this.
+    #[cfg_attr(feature = "json", serde(other))]
+    Unknown,
 }
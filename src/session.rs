@@ -0,0 +1,88 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::agda::{NextResult, ReplState};
+use crate::resp::AllGoalsWarnings;
+
+/// One Agda process, shared by the sessions of many files.
+///
+/// The state should have no responses pending,
+/// so read the goals of the first load before sharing it.
+#[derive(Clone)]
+pub struct SharedAgda(Arc<Mutex<ReplState>>);
+
+impl SharedAgda {
+    pub fn new(agda: ReplState) -> Self {
+        SharedAgda(Arc::new(Mutex::new(agda)))
+    }
+
+    /// Exclusive access to Agda, with whatever file it loaded last.
+    pub async fn lock(&self) -> SessionGuard<'_> {
+        SessionGuard(self.0.lock().await)
+    }
+
+    /// A session for `file`, which is loaded when it's first used.
+    pub fn session(&self, file: String) -> FileSession {
+        FileSession {
+            agda: self.0.clone(),
+            file,
+        }
+    }
+}
+
+/// Interaction with one file over a [`SharedAgda`](self::SharedAgda).
+///
+/// Agda only knows the interaction points of the file it loaded last,
+/// so a session reloads its file when another one was used in between.
+/// Responses are read while the session holds the process,
+/// so they always belong to the session that sent the command.
+pub struct FileSession {
+    agda: Arc<Mutex<ReplState>>,
+    file: String,
+}
+
+impl FileSession {
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Load the file, even if it's the current one, and wait for the goals.
+    pub async fn load(&self) -> NextResult<AllGoalsWarnings> {
+        let mut agda = self.agda.lock().await;
+        agda.set_file(self.file.clone());
+        agda.reload_file().await?;
+        agda.next_all_goals_warnings().await
+    }
+
+    /// Exclusive access to Agda, with this file loaded.
+    pub async fn lock(&self) -> NextResult<SessionGuard<'_>> {
+        let mut agda = self.agda.lock().await;
+        if agda.file != self.file {
+            agda.set_file(self.file.clone());
+            agda.reload_file().await?;
+            if let Err(e) = agda.next_all_goals_warnings().await? {
+                return Ok(Err(e));
+            }
+        }
+        Ok(Ok(SessionGuard(agda)))
+    }
+}
+
+/// Access to the shared Agda, see [`FileSession::lock`](self::FileSession::lock).
+pub struct SessionGuard<'a>(MutexGuard<'a, ReplState>);
+
+impl<'a> Deref for SessionGuard<'a> {
+    type Target = ReplState;
+
+    fn deref(&self) -> &ReplState {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for SessionGuard<'a> {
+    fn deref_mut(&mut self) -> &mut ReplState {
+        &mut self.0
+    }
+}
@@ -1,4 +1,17 @@
-use crate::resp::{MakeCase, Resp};
+use std::borrow::Cow;
+use std::time::Duration;
+
+use tokio::sync::mpsc::channel;
+
+use crate::agda::{debounce, deserialize_agda, deserialize_agda_bytes, spawn_writer, AgdaOptions};
+use crate::base::{Interval, Remove, TokenBased};
+use crate::borrowed;
+use crate::cmd::{Cmd, GoalInput, IOTCM};
+use crate::resp::{
+    mentions_meta, Aspect, AspectHighlight, Constraint, DisplayInfo, DuplicateBinding,
+    DuplicateKind, ErrorObject, GiveResult, Goal, HighlightingInfo, LabeledRange, MakeCase, Resp,
+    TcWarning, UnsolvedConstraint, UnsolvedMeta,
+};
 
 #[test]
 fn simple_running_info_de() {
@@ -11,12 +24,88 @@ fn simple_make_case_de() {
     let a = Resp::MakeCase {
         variant: MakeCase::Function,
         clauses: vec!["f a = a".to_owned()],
-        interaction_point: 233,
+        interaction_point: 233.into(),
     };
     let json = serde_json::to_string(&a).unwrap();
     println!("{}", json);
 }
 
+#[test]
+fn newer_responses_de() {
+    let resp: Resp = deserialize_agda(r#"{"kind":"DoneExiting"}"#).unwrap();
+    assert_eq!(resp, Resp::DoneExiting);
+    let json = r#"{"kind":"Mimer","interactionPoint":2,"solution":"suc n"}"#;
+    let resp: Resp = deserialize_agda(json).unwrap();
+    let expected = Resp::Mimer {
+        interaction_point: 2.into(),
+        solution: Some("suc n".to_owned()),
+    };
+    assert_eq!(resp, expected);
+    let json = r#"{"kind":"SomethingNew","what":[1]}"#;
+    assert_eq!(deserialize_agda::<Resp>(json).unwrap(), Resp::Unknown);
+    assert!(deserialize_agda::<Resp>(r#"{"kind":"DisplayInfo","info":"#).is_err());
+    // A known kind in a wrong shape is not passed off as unknown.
+    assert!(deserialize_agda::<Resp>(r#"{"kind":"GiveAction","giveResult":[]}"#).is_err());
+    let json = serde_json::to_string(&Resp::DoneAborting).unwrap();
+    assert_eq!(json, r#"{"kind":"DoneAborting"}"#);
+}
+
+#[test]
+fn normal_form_de() {
+    use crate::base::ComputeMode;
+
+    let json = r#"{"kind":"DisplayInfo","info":{"kind":"NormalForm","computeMode":"UseShowInstance","commandState":{"interactionPoints":[0],"currentFile":"/tmp/A.agda"},"time":"0ms","expr":"suc zero"}}"#;
+    match deserialize_agda(json).unwrap() {
+        Resp::DisplayInfo {
+            info:
+                Some(DisplayInfo::NormalForm {
+                    compute_mode,
+                    command_state,
+                    time,
+                    expr,
+                }),
+        } => {
+            assert_eq!(compute_mode, ComputeMode::UseShowInstance);
+            assert_eq!(command_state.current_file, "/tmp/A.agda");
+            assert_eq!((time.as_str(), expr.as_str()), ("0ms", "suc zero"));
+        }
+        resp => panic!("{:?}", resp),
+    }
+}
+
+#[test]
+fn module_contents_de() {
+    let json = r#"{"kind":"DisplayInfo","info":{"kind":"ModuleContents","names":["Nat.Properties"],"telescope":"(A : Set)","contents":[{"name":"zero","term":"Nat"},{"name":"suc","term":"Nat → Nat"}]}}"#;
+    match deserialize_agda(json).unwrap() {
+        Resp::DisplayInfo {
+            info: Some(DisplayInfo::ModuleContents(contents)),
+        } => {
+            assert_eq!(contents.names, ["Nat.Properties"]);
+            assert_eq!(contents.telescope, "(A : Set)");
+            assert_eq!(contents.contents[1].term, "Nat → Nat");
+        }
+        resp => panic!("{:?}", resp),
+    }
+}
+
+#[test]
+fn module_telescope() {
+    use crate::resp::{Parameter, Visibility};
+
+    let text = "(A : Set) {m n : ℕ} ⦃ eq : Eq (List A) ⦄ {{ ord : Ord A }} (f : {x : A} → A)";
+    let params = Parameter::parse_telescope(text);
+    assert_eq!(params.len(), 5);
+    assert_eq!(params[1].names, ["m", "n"]);
+    assert_eq!(params[1].visibility, Visibility::Hidden);
+    assert_eq!(params[2].the_type, "Eq (List A)");
+    assert_eq!(params[3].visibility, Visibility::Instance);
+    assert_eq!(params[4].the_type, "{x : A} → A");
+    let shown: Vec<String> = params.iter().map(ToString::to_string).collect();
+    assert_eq!(shown[1], "{m n : ℕ}");
+    assert_eq!(shown[3], "⦃ ord : Ord A ⦄");
+    assert!(Parameter::parse_telescope("").is_empty());
+}
+
 #[test]
 fn simple_resp_status_de() {
     let a = Resp::Status {
@@ -25,3 +114,1161 @@ fn simple_resp_status_de() {
     let json = serde_json::to_string(&a).unwrap();
     println!("{}", json);
 }
+
+#[tokio::test]
+async fn debounce_takes_the_last() {
+    let (mut tx, mut rx) = channel(8);
+    for i in 0..5 {
+        tx.send(i).await.unwrap();
+    }
+    drop(tx);
+    let last = debounce(&mut rx, Duration::from_millis(10)).await;
+    assert_eq!(last, Some(4));
+    assert_eq!(debounce(&mut rx, Duration::from_millis(10)).await, None);
+}
+
+#[test]
+fn unsolved_constraints() {
+    let text = "———— Errors ————\n\
+                /tmp/A.agda:5,7-8\n\
+                Failed to solve the following constraints:\n  \
+                _12 := suc ?3\n    \
+                [blocked on problem 2]\n  \
+                _14 = zero : Nat\n\
+                Unreachable clause\n";
+    let constraints = UnsolvedConstraint::parse(text);
+    assert_eq!(constraints.len(), 2);
+    assert_eq!(
+        constraints[0].location.as_deref(),
+        Some("/tmp/A.agda:5,7-8")
+    );
+    assert_eq!(
+        constraints[0].constraint,
+        "_12 := suc ?3 [blocked on problem 2]"
+    );
+    let goal = |id: u32, goal_type: &str| Goal {
+        interaction_point: id.into(),
+        goal_type: goal_type.to_owned(),
+        range: vec![],
+    };
+    assert!(constraints[0].mentions_goal(&goal(3, "Nat")));
+    assert!(constraints[1].mentions_goal(&goal(0, "Vec Nat _14")));
+    assert!(!constraints[1].mentions_goal(&goal(1, "Vec Nat _1")));
+}
+
+#[test]
+fn meta_lookup() {
+    let meta = UnsolvedMeta {
+        pretty_meta: "_12  [ at /tmp/A.agda:5,7-8 ]".to_owned(),
+        meta_type: "Nat".to_owned(),
+    };
+    assert_eq!(meta.name(), "_12");
+    assert!(mentions_meta("_12 := suc ?3", "_12"));
+    assert!(mentions_meta("_12 := suc ?3", "?3"));
+    assert!(!mentions_meta("_123 := suc _1", "_12"));
+    let json = r#"{"kind":"Constraints","constraints":"_12 := suc ?3"}"#;
+    let info: DisplayInfo = serde_json::from_str(json).unwrap();
+    let constraints = vec![Constraint {
+        constraint: "_12 := suc ?3".to_owned(),
+        range: vec![],
+    }];
+    assert_eq!(info, DisplayInfo::Constraints { constraints });
+}
+
+#[test]
+fn structured_constraints_de() {
+    let json = r#"{"kind":"Constraints","constraints":[
+        "_12 := suc ?3",
+        {"constraint":"_13 = zero","range":[{"start":{"pos":5,"line":1,"col":5},"end":{"pos":7,"line":1,"col":7}}]}
+    ]}"#;
+    let constraints = match serde_json::from_str(json).unwrap() {
+        DisplayInfo::Constraints { constraints } => constraints,
+        info => panic!("{:?}", info),
+    };
+    assert_eq!(constraints.len(), 2);
+    assert!(constraints[0].mentions_meta("?3"));
+    assert!(constraints[0].range.is_empty());
+    assert_eq!(constraints[1].constraint, "_13 = zero");
+    assert_eq!(constraints[1].range[0].end.col, 7);
+    let lines = r#"{"kind":"Constraints","constraints":"_1 = _2\n\n_3 = zero\n"}"#;
+    match serde_json::from_str(lines).unwrap() {
+        DisplayInfo::Constraints { constraints } => assert_eq!(constraints.len(), 2),
+        info => panic!("{:?}", info),
+    }
+}
+
+#[test]
+fn structured_warnings_de() {
+    let text = "\u{2014}\u{2014}\u{2014}\u{2014} Warnings\n\
+                /tmp/A.agda:3,1-8\nUnreachable clause\n\n\
+                /tmp/A.agda:5,3-4\nx shadows a binding\n  in f\n";
+    let warnings = TcWarning::parse(text);
+    assert_eq!(warnings.len(), 2);
+    let range = &warnings[1].range[0];
+    assert_eq!(range.start.line_col(), Some((5, 3)));
+    assert_eq!(range.end.line_col(), Some((5, 4)));
+    assert_eq!(
+        warnings[1].message,
+        "/tmp/A.agda:5,3-4\nx shadows a binding\n  in f"
+    );
+    let json = r#"{"kind":"CompilationOk","errors":"","warnings":[
+        "Unused import",
+        {"kind":"UnreachableClauses","message":"Unreachable clause","range":[{"start":{"pos":5,"line":3,"col":1},"end":{"pos":12,"line":3,"col":8}}]}
+    ]}"#;
+    let warnings = match serde_json::from_str(json).unwrap() {
+        DisplayInfo::CompilationOk { warnings, errors } => {
+            assert!(errors.is_empty());
+            warnings
+        }
+        info => panic!("{:?}", info),
+    };
+    assert_eq!(warnings[0].message, "Unused import");
+    assert_eq!(warnings[1].kind.as_deref(), Some("UnreachableClauses"));
+    assert_eq!(warnings[1].range[0].start.line, 3);
+    assert_eq!(
+        TcWarning::text(&warnings),
+        "Unused import\nUnreachable clause"
+    );
+}
+
+#[test]
+fn highlighting_aspects() {
+    let json = r#"{"kind":"HighlightingInfo","direct":true,"info":{"remove":false,"payload":[
+        {"range":[1,5],"atoms":["keyword"],"tokenBased":"TokenBased","note":null,"definitionSite":null},
+        {"range":[6,10],"atoms":["inductiveconstructor","operator","shinynewatom"],"tokenBased":"NotOnlyTokenBased","note":null,"definitionSite":null}
+    ]}}"#;
+    let payload = match deserialize_agda(json).unwrap() {
+        Resp::HighlightingInfo {
+            info: Some(info), ..
+        } => info.payload,
+        resp => panic!("{:?}", resp),
+    };
+    assert_eq!(payload[0].aspects(), vec![Aspect::Keyword]);
+    assert_eq!(payload[0].token_based, TokenBased::TokenBased);
+    let aspects = payload[1].aspects();
+    assert!(aspects[0].is_name() && !aspects[1].is_name());
+    assert_eq!(aspects[2], Aspect::Other("shinynewatom".to_owned()));
+    assert_eq!(aspects[2].to_string(), "shinynewatom");
+    assert!(payload[1].has_aspect(&Aspect::Operator));
+    assert!(Aspect::parse("unsolvedmeta").is_problem());
+}
+
+#[test]
+fn huge_highlighting_de() {
+    let aspect = AspectHighlight {
+        range: Interval::offsets(1, 7),
+        atoms: vec!["keyword".to_owned()],
+        ..Default::default()
+    };
+    let resp = Resp::HighlightingInfo {
+        info: Some(HighlightingInfo {
+            remove: false,
+            payload: vec![aspect; 50_000],
+        }),
+        filepath: None,
+        direct: true,
+    };
+    let line = format!("JSON> {}\n", serde_json::to_string(&resp).unwrap());
+    assert!(line.len() > 4_000_000);
+    let from_bytes: Resp = deserialize_agda_bytes(line.as_bytes()).unwrap();
+    assert_eq!(from_bytes, resp);
+    let from_str: Resp = deserialize_agda(&line).unwrap();
+    assert_eq!(from_str, resp);
+}
+
+#[test]
+fn clear_token_based_highlighting() {
+    let json = r#"{"kind":"ClearHighlighting","tokenBased":"TokenBased"}"#;
+    let token_based = match serde_json::from_str(json).unwrap() {
+        Resp::ClearHighlighting { token_based } => token_based,
+        resp => panic!("{:?}", resp),
+    };
+    let token = AspectHighlight {
+        token_based: TokenBased::TokenBased,
+        ..Default::default()
+    };
+    let semantic = AspectHighlight::default();
+    assert!(token.is_cleared_by(token_based));
+    assert!(!semantic.is_cleared_by(token_based));
+    assert!(semantic.is_cleared_by(TokenBased::NotOnlyTokenBased));
+    let old = r#"{"kind":"ClearHighlighting"}"#;
+    match serde_json::from_str(old).unwrap() {
+        Resp::ClearHighlighting { token_based } => {
+            assert_eq!(token_based, TokenBased::NotOnlyTokenBased)
+        }
+        resp => panic!("{:?}", resp),
+    }
+}
+
+#[test]
+fn borrowed_highlighting_de() {
+    let line = r#"JSON> {"kind":"HighlightingInfo","direct":true,"filepath":null,
+        "info":{"remove":false,"payload":[{"range":[1,7],"atoms":["keyword"],
+        "tokenBased":"TokenBased","note":"a \"note\"","definitionSite":null}]}}"#;
+    let resp = borrowed::Resp::deserialize(line.as_bytes()).unwrap();
+    let info = match resp {
+        borrowed::Resp::HighlightingInfo(borrowed::Highlighting {
+            info: Some(info), ..
+        }) => info,
+        _ => panic!("expected highlighting"),
+    };
+    let aspect = &info.payload[0];
+    assert!(matches!(aspect.atoms[0].0, Cow::Borrowed("keyword")));
+    assert_eq!(aspect.note.as_deref(), Some("a \"note\""));
+    let owned: HighlightingInfo = info.into();
+    assert_eq!(owned.payload[0].atoms, ["keyword"]);
+    let other = borrowed::Resp::deserialize(br#"{"kind":"ClearRunningInfo"}"#).unwrap();
+    assert_eq!(other, borrowed::Resp::Other(Resp::ClearRunningInfo));
+    // The `kind` may come after the other fields too.
+    let late = br#"{"direct":false,"kind":"HighlightingInfo","info":null}"#;
+    match borrowed::Resp::deserialize(late).unwrap() {
+        borrowed::Resp::HighlightingInfo(highlighting) => assert!(!highlighting.direct),
+        resp => panic!("{:?}", resp),
+    }
+    let late = br#"{"tokenBased":"TokenBased","kind":"ClearHighlighting"}"#;
+    match borrowed::Resp::deserialize(late).unwrap() {
+        borrowed::Resp::Other(Resp::ClearHighlighting { token_based }) => {
+            assert_eq!(token_based, TokenBased::TokenBased)
+        }
+        resp => panic!("{:?}", resp),
+    }
+    assert!(borrowed::Resp::deserialize(br#"{"direct":true}"#).is_err());
+}
+
+#[test]
+fn agda_options() {
+    assert!(AgdaOptions::new().args().is_empty());
+    let options = AgdaOptions::new()
+        .no_libraries(true)
+        .caching(false)
+        .exit_on_error(true)
+        .arg("--safe");
+    assert_eq!(
+        options.args(),
+        [
+            "--no-libraries",
+            "--no-caching",
+            "--interaction-exit-on-error",
+            "--safe"
+        ]
+    );
+}
+
+#[test]
+fn simple_commands_ser() {
+    let iotcm = |command| IOTCM::simple("A.agda".to_owned(), command).to_string();
+    assert_eq!(
+        iotcm(Cmd::Exit),
+        "IOTCM \"A.agda\" NonInteractive Direct Cmd_exit\n"
+    );
+    let cmd = Cmd::TokenHighlighting {
+        path: "A.agda".to_owned(),
+        remove: Remove::Keep,
+    };
+    assert_eq!(cmd.to_string(), "( Cmd_tokenHighlighting \"A.agda\" Keep )");
+    assert_eq!(Cmd::ShowVersion.to_string(), "Cmd_show_version");
+    assert_eq!(Cmd::Abort.to_string(), "Cmd_abort");
+    assert_eq!(
+        Cmd::ShowIrrelevantArgs(true).to_string(),
+        "( ShowIrrelevantArgs True )"
+    );
+    assert_eq!(
+        Cmd::ToggleIrrelevantArgs.to_string(),
+        "ToggleIrrelevantArgs"
+    );
+}
+
+#[test]
+fn auto_options() {
+    use crate::cmd::AutoOptions;
+
+    let options = AutoOptions::new().timeout(200).case_split(true).list(true);
+    let options = options.hint("+-comm").hint("sym");
+    assert_eq!(options.to_string(), "-t 200 -c -l +-comm sym");
+    assert_eq!(
+        Cmd::auto_one(3.into(), &options).to_string(),
+        "( Cmd_autoOne 3 noRange \"-t 200 -c -l +-comm sym\" )"
+    );
+    assert_eq!(AutoOptions::new().to_string(), "");
+}
+
+#[test]
+fn compile_backends() {
+    use crate::cmd::Backend;
+
+    let flags = vec!["--ghc-flag=-O2".to_owned()];
+    let cmd = Cmd::compile(Backend::GHCNoMain, "A.agda".to_owned(), flags.clone()).unwrap();
+    assert_eq!(
+        cmd.to_string(),
+        "( Cmd_compile GHCNoMain \"A.agda\" [\"--ghc-flag=-O2\"] )"
+    );
+    if let Cmd::Compile(compilation) = &cmd {
+        assert_eq!(compilation.backend(), &Backend::GHCNoMain);
+        assert_eq!(compilation.flags(), &flags[..]);
+    }
+    assert!(Cmd::compile(Backend::JS, "A.agda".to_owned(), flags).is_err());
+    assert_eq!(Backend::named("LaTeX"), Ok(Backend::LaTeX));
+    match Backend::named("Agda2Lambox") {
+        Ok(Backend::Custom(name)) => assert_eq!(name.as_str(), "Agda2Lambox"),
+        backend => panic!("{:?}", backend),
+    }
+    assert!(Backend::named("GHC -O2").is_err());
+    assert!(Backend::named("").is_err());
+}
+
+/// Read a Haskell string literal back, like `read` does in Agda.
+fn read_haskell_string(literal: &str) -> String {
+    assert!(
+        literal.starts_with('"') && literal.ends_with('"'),
+        "{}",
+        literal
+    );
+    let mut chars = literal[1..literal.len() - 1].chars().peekable();
+    let mut s = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next().unwrap() {
+            'n' => s.push('\n'),
+            't' => s.push('\t'),
+            'r' => s.push('\r'),
+            '&' => {}
+            c if c.is_ascii_digit() => {
+                let mut code = c.to_digit(10).unwrap();
+                while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+                    code = code * 10 + d;
+                    chars.next();
+                }
+                s.push(std::char::from_u32(code).unwrap());
+            }
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+#[test]
+fn haskell_strings_round_trip() {
+    use crate::cmd::HaskellString;
+
+    let texts = [
+        "ℕ → ℕ",
+        "say \"hi\" \\ bye",
+        "tab\tline\nreturn\r",
+        "\u{1b}1 and \u{0}",
+        "e\u{301} and \u{7f}",
+    ];
+    for text in &texts {
+        let literal = HaskellString(text).to_string();
+        assert!(!literal.contains("\\u{"), "{}", literal);
+        assert_eq!(read_haskell_string(&literal), *text);
+    }
+    assert_eq!(HaskellString("\u{1b}1").to_string(), r#""\27\&1""#);
+    let cmd = Cmd::Load {
+        path: "/tmp/\"A\".agda".to_owned(),
+        flags: vec!["--safe".to_owned(), "-Wall".to_owned()],
+    };
+    assert_eq!(
+        IOTCM::simple("A.agda".to_owned(), cmd).to_string(),
+        "IOTCM \"A.agda\" NonInteractive Direct ( Cmd_load \"/tmp/\\\"A\\\".agda\" [\"--safe\", \"-Wall\"] )\n"
+    );
+    let give = Cmd::give(GoalInput::no_range(1.into(), "λ x → \u{7}".to_owned()));
+    assert_eq!(
+        give.to_string(),
+        "( Cmd_give WithoutForce 1 noRange \"λ x → \\7\" )"
+    );
+}
+
+/// `cat` plays Agda here: it answers every (big) command with itself.
+/// Writing all commands before reading any answer would fill both pipes.
+#[cfg(unix)]
+#[tokio::test]
+async fn big_commands_dont_deadlock() {
+    use std::process::Stdio;
+    use tokio::future::FutureExt;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::process::Command;
+
+    let mut cat = Command::new("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut commands = spawn_writer(cat.stdin().take().unwrap());
+    let mut answers = BufReader::new(cat.stdout().take().unwrap());
+    let big = " ".repeat(100_000);
+    let count = 50;
+    let test = async {
+        for _ in 0..count {
+            let command = IOTCM::simple(big.clone(), Cmd::ShowVersion);
+            commands.send(&command).await.unwrap();
+        }
+        commands.close().await.unwrap();
+        let mut line = Vec::new();
+        for _ in 0..count {
+            line.clear();
+            answers.read_until(b'\n', &mut line).await.unwrap();
+            assert!(line.len() > big.len());
+        }
+    };
+    test.timeout(Duration::from_secs(20)).await.unwrap();
+}
+
+/// A fake Agda, which answers every command with an empty goal list.
+#[cfg(unix)]
+fn mock_agda(name: &str) -> String {
+    let goals = r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings",
+        "visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}"#;
+    let script = format!(
+        "#!/bin/sh\nwhile read -r line; do echo 'JSON> {}'; done\n",
+        goals.replace('\n', "")
+    );
+    mock_script(name, &script)
+}
+
+#[cfg(unix)]
+fn mock_script(name: &str, script: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, script).unwrap();
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    permissions.set_mode(0o755);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sessions_switch_files() {
+    use crate::agda::ReplState;
+    use crate::session::SharedAgda;
+
+    let program = mock_agda("agda-mode-mock-sessions");
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let shared = SharedAgda::new(agda);
+    let a = shared.session("A.agda".to_owned());
+    let b = shared.session("B.agda".to_owned());
+    assert!(b.load().await.unwrap().is_ok());
+    {
+        let guard = a.lock().await.unwrap().unwrap();
+        assert_eq!(guard.file, "A.agda");
+    }
+    let mut guard = b.lock().await.unwrap().unwrap();
+    assert_eq!(guard.file, "B.agda");
+    guard.command(Cmd::Metas).await.unwrap();
+    assert!(guard.next_all_goals_warnings().await.unwrap().is_ok());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn shadow_files() {
+    use crate::agda::ReplState;
+    use std::os::unix::fs::PermissionsExt;
+
+    let log = std::env::temp_dir().join("agda-mode-mock-shadow.log");
+    let _ = std::fs::remove_file(&log);
+    let script = format!(
+        "#!/bin/sh\nwhile read -r line; do echo \"$line\" >> {}; echo 'JSON> {}'; done\n",
+        log.display(),
+        r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}"#
+    );
+    let program = mock_script("agda-mode-mock-shadow", &script);
+    let mut agda = ReplState::start(&program, "/work/A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    agda.load_shadow("module A where\n").await.unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let shadow = std::path::PathBuf::from(agda.shadow().unwrap());
+    assert_eq!(shadow.file_name().unwrap(), "A.agda");
+    let dir = shadow.parent().unwrap().to_owned();
+    let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+    assert_eq!(
+        std::fs::read_to_string(&shadow).unwrap(),
+        "module A where\n"
+    );
+    agda.reload_file().await.unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    assert_eq!(agda.shadow(), None);
+    let log = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains(shadow.to_str().unwrap()));
+    assert!(!lines[2].contains(shadow.to_str().unwrap()));
+    drop(agda);
+    assert!(!dir.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn auto_answers() {
+    use crate::agda::ReplState;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+read -r line
+echo 'JSON> {"kind":"GiveAction","giveResult":{"str":"zero"},"interactionPoint":0}'
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Auto","info":"Solved"}}'
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Auto","info":"No solution found"}}'
+"#;
+    let program = mock_script("agda-mode-mock-auto", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    agda.command(Cmd::AutoOne(GoalInput::simple(0.into())))
+        .await
+        .unwrap();
+    let solution = agda.next_auto().await.unwrap().unwrap();
+    assert_eq!(solution.as_deref(), Some("zero"));
+    agda.command(Cmd::AutoOne(GoalInput::simple(1.into())))
+        .await
+        .unwrap();
+    assert_eq!(agda.next_auto().await.unwrap().unwrap(), None);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn forced_give() {
+    use crate::agda::ReplState;
+    use crate::base::UseForce;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+while read -r line; do
+  case "$line" in
+    *"Cmd_give WithForce"*) echo 'JSON> {"kind":"GiveAction","giveResult":{"str":"f x"},"interactionPoint":0}' ;;
+    *) echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Error","message":"Termination checking failed"}}' ;;
+  esac
+done
+"#;
+    let program = mock_script("agda-mode-mock-force", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let input = || GoalInput::no_range(0.into(), "f x".to_owned());
+    let refused = agda.give_force(input(), UseForce::WithoutForce).await;
+    assert!(refused.unwrap().unwrap_err().contains("Termination"));
+    let given = agda.give_force(input(), UseForce::WithForce).await;
+    assert_eq!(given.unwrap().unwrap().text("f x"), "f x");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn inferred_types() {
+    use crate::agda::ReplState;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"InferredType","commandState":{"interactionPoints":[],"currentFile":"A.agda"},"time":"","expr":"Nat"}}'
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Error","message":"Not in scope: y"}}'
+"#;
+    let program = mock_script("agda-mode-mock-infer", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let inferred = agda.infer("suc zero").await.unwrap().unwrap();
+    assert_eq!(inferred.expr, "Nat");
+    assert_eq!(inferred.command_state.current_file, "A.agda");
+    let error = agda.infer("y").await.unwrap().unwrap_err();
+    assert!(error.starts_with("Not in scope"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn checking_time() {
+    use crate::agda::ReplState;
+    use crate::resp::{CpuTime, ProfileEntry};
+    use std::time::Duration;
+
+    assert_eq!(CpuTime::parse("1,234ms"), Some(Duration::from_millis(1234)));
+    assert_eq!(CpuTime::parse("Time: 0ms"), Some(Duration::from_millis(0)));
+    assert_eq!(CpuTime::parse("1.2s"), None);
+    let profile = "Total                  2,712ms\n\
+                   Checking A\n\
+                   A.slow                   512ms  (18%)\n";
+    let entries = ProfileEntry::parse_all(profile);
+    let accounts: Vec<_> = entries.iter().map(|e| e.account.as_str()).collect();
+    assert_eq!(accounts, ["Total", "A.slow"]);
+    assert_eq!(entries[1].time.duration, Some(Duration::from_millis(512)));
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Time","time":"2,500ms"}}'
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+"#;
+    let program = mock_script("agda-mode-mock-time", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(agda.last_checking_time(), None);
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    assert_eq!(agda.last_checking_time(), Some(Duration::from_millis(2500)));
+    match agda.last_display_info("Time") {
+        Some(DisplayInfo::Time { time }) => assert_eq!(time.raw, "2,500ms"),
+        info => panic!("{:?}", info),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn solve_all_solutions() {
+    use crate::agda::ReplState;
+    use crate::base::Rewrite;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+read -r line
+echo 'JSON> {"kind":"SolveAll","solutions":[{"interactionPoint":0,"expression":"zero"},{"interactionPoint":2,"expression":"suc n"}]}'
+"#;
+    let program = mock_script("agda-mode-mock-solve-all", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    agda.command(Cmd::SolveAll(Rewrite::Simplified))
+        .await
+        .unwrap();
+    let solutions = agda.next_solve_all().await.unwrap().unwrap();
+    assert_eq!(solutions.len(), 2);
+    assert_eq!(solutions[1].interaction_point.id, 2);
+    assert_eq!(solutions[1].expression, "suc n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn intro_answers() {
+    use crate::agda::ReplState;
+    use crate::resp::Intro;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"IntroConstructorUnknown","constructors":["zero","suc"]}}'
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"IntroNotFound"}}'
+read -r line
+echo 'JSON> {"kind":"GiveAction","giveResult":{"str":"λ x → ?"},"interactionPoint":0}'
+"#;
+    let program = mock_script("agda-mode-mock-intro", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let intro = || Cmd::Intro {
+        dunno: false,
+        input: GoalInput::simple(0.into()),
+    };
+    agda.command(intro()).await.unwrap();
+    let constructors = vec!["zero".to_owned(), "suc".to_owned()];
+    assert_eq!(
+        agda.next_intro().await.unwrap().unwrap(),
+        Intro::Constructors(constructors)
+    );
+    agda.command(intro()).await.unwrap();
+    assert_eq!(agda.next_intro().await.unwrap().unwrap(), Intro::NotFound);
+    agda.command(intro()).await.unwrap();
+    let given = GiveResult::GiveString("λ x → ?".to_owned());
+    assert_eq!(
+        agda.next_intro().await.unwrap().unwrap(),
+        Intro::Given(given)
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn running_info_verbosity() {
+    use crate::agda::ReplState;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"RunningInfo","debugLevel":1,"message":"Checking A (A.agda)."}'
+echo 'JSON> {"kind":"RunningInfo","debugLevel":10,"message":"unifying x with y"}'
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+"#;
+    let program = mock_script("agda-mode-mock-verbosity", script);
+    let log = std::env::temp_dir().join(format!("agda-mode-running-{}.log", std::process::id()));
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.set_verbosity(Some(1));
+    agda.log_running_info(Some(&log)).unwrap();
+    agda.capture_running_info();
+    agda.next_all_goals_warnings().await.unwrap().unwrap();
+    let captured = agda.captured_running_info();
+    assert_eq!(captured[1], (10, "unifying x with y".to_owned()));
+    assert!(agda.captured_running_info().is_empty());
+    let shown = agda.running_info().lock().unwrap().clone();
+    assert_eq!(shown, "Checking A (A.agda).");
+    agda.log_running_info(None).unwrap();
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(logged, "[1] Checking A (A.agda).\n[10] unifying x with y\n");
+    std::fs::remove_file(&log).unwrap();
+}
+
+/// Responses to the same session, in the shapes different versions of Agda print them.
+const TRANSCRIPTS: &[(&str, &[&str])] = &[
+    (
+        "Agda version 2.6.0.1",
+        &[
+            r#"JSON> {"kind":"InteractionPoints","interactionPoints":[0,1]}"#,
+            r#"{"kind":"GiveAction","giveResult":{"str":"zero"},"interactionPoint":0}"#,
+            r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[{"interactionPoint":1,"goalType":"Nat"}],"invisibleGoals":[],"warnings":"W","errors":""}}"#,
+        ],
+    ),
+    (
+        "Agda version 2.6.1",
+        &[
+            r#"JSON> {"kind":"InteractionPoints","interactionPoints":[{"id":0,"range":[]},{"id":1,"range":[]}]}"#,
+            r#"{"kind":"GiveAction","giveResult":{"str":"zero"},"interactionPoint":{"id":0,"range":[]}}"#,
+            r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[{"interactionPoint":{"id":1,"range":[]},"goalType":"Nat"}],"invisibleGoals":[],"warnings":["W"],"errors":[]}}"#,
+        ],
+    ),
+    (
+        "Agda version 2.6.4.3",
+        &[
+            r#"JSON> {"kind":"InteractionPoints","interactionPoints":[{"id":0,"range":[{"start":{"pos":5}}]},{"id":1,"range":[]}]}"#,
+            r#"{"kind":"GiveAction","giveResult":{"str":"zero"},"interactionPoint":{"id":0,"range":[]}}"#,
+            r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[{"interactionPoint":{"id":1,"range":[]},"goalType":"Nat"}],"invisibleGoals":[],"warnings":[{"message":"W"}],"errors":[]}}"#,
+        ],
+    ),
+];
+
+#[test]
+fn interaction_point_shapes() {
+    use crate::base::{InteractionPoint, Interval, Position};
+
+    let point = |json: &str| serde_json::from_str::<InteractionPoint>(json).unwrap();
+    assert_eq!(point("3").id, 3);
+    assert_eq!(point("3").range, None);
+    let interval = Interval {
+        start: Position::offset(5),
+        end: Position::offset(6),
+    };
+    let full = point(r#"{"id":3,"range":[{"start":{"pos":5},"end":{"pos":6}}]}"#);
+    assert_eq!(full.range, Some(interval));
+    assert_eq!(full, InteractionPoint::from(3));
+    assert_eq!(point(r#"{"id":3,"range":[]}"#).range, None);
+    assert_eq!(
+        serde_json::to_string(&InteractionPoint::from(3)).unwrap(),
+        "3"
+    );
+    let json = r#"{"kind":"InteractionPoints","interactionPoints":[0,{"id":1,"range":[{"start":{"pos":5},"end":{"pos":6}}]}]}"#;
+    match serde_json::from_str(json).unwrap() {
+        Resp::InteractionPoints { interaction_points } => {
+            assert_eq!(interaction_points, [0.into(), 1.into()]);
+            assert_eq!(interaction_points[1].range, Some(interval));
+        }
+        resp => panic!("{:?}", resp),
+    }
+}
+
+#[test]
+fn give_result_shapes() {
+    let give = |json: &str| serde_json::from_str::<GiveResult>(json).unwrap();
+    assert_eq!(
+        give(r#"{"str":"suc n"}"#),
+        GiveResult::GiveString("suc n".to_owned())
+    );
+    assert_eq!(
+        give(r#""suc n""#),
+        GiveResult::GiveString("suc n".to_owned())
+    );
+    assert_eq!(give(r#"{"paren":true}"#), GiveResult::Paren);
+    assert_eq!(give("false"), GiveResult::NoParen);
+    assert!(serde_json::from_str::<GiveResult>("{}").is_err());
+    let json = serde_json::to_string(&GiveResult::Paren).unwrap();
+    assert_eq!(json, r#"{"paren":true}"#);
+    assert_eq!(GiveResult::Paren.text("suc n"), "(suc n)");
+}
+
+#[test]
+fn schema_matrix() {
+    use crate::agda::deserialize_agda_with;
+    use crate::compat::Schema;
+    use crate::resp::AllGoalsWarnings;
+
+    assert_eq!(
+        Schema::of_version("Agda version 2.6.0.1"),
+        Some(Schema::V2_6_0)
+    );
+    assert_eq!(
+        Schema::of_version("Agda version 2.7.0"),
+        Some(Schema::V2_6_1)
+    );
+    assert_eq!(Schema::of_version("Agda"), None);
+    let expected = vec![
+        Resp::InteractionPoints {
+            interaction_points: vec![0.into(), 1.into()],
+        },
+        Resp::GiveAction {
+            give_result: GiveResult::GiveString("zero".to_owned()),
+            interaction_point: 0.into(),
+        },
+        Resp::DisplayInfo {
+            info: Some(DisplayInfo::AllGoalsWarnings(AllGoalsWarnings {
+                visible_goals: vec![Goal {
+                    interaction_point: 1.into(),
+                    goal_type: "Nat".to_owned(),
+                    range: vec![],
+                }],
+                invisible_goals: vec![],
+                warnings: vec![TcWarning {
+                    message: "W".to_owned(),
+                    ..Default::default()
+                }],
+                errors: vec![],
+            })),
+        },
+    ];
+    for (version, lines) in TRANSCRIPTS {
+        let schema = Schema::of_version(version).unwrap();
+        let resps: Vec<Resp> = lines
+            .iter()
+            .map(|line| deserialize_agda_with(line.as_bytes(), schema).unwrap())
+            .collect();
+        assert_eq!(resps, expected, "{}", version);
+    }
+}
+
+#[test]
+fn goal_ranges() {
+    use crate::agda::deserialize_agda_with;
+    use crate::compat::Schema;
+    use crate::resp::{Interval, Position};
+
+    let line = r#"{"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[{"interactionPoint":{"id":0,"range":[{"start":{"pos":19,"line":2,"col":5},"end":{"pos":20,"line":2,"col":6}}]},"goalType":"Nat"}],"invisibleGoals":[{"prettyMeta":"_5","metaType":"Nat"}],"warnings":[],"errors":[]}}"#;
+    let all = match deserialize_agda_with(line.as_bytes(), Schema::V2_6_1).unwrap() {
+        Resp::DisplayInfo {
+            info: Some(DisplayInfo::AllGoalsWarnings(all)),
+        } => all,
+        resp => panic!("{:?}", resp),
+    };
+    let end = |offset, col| Position {
+        offset,
+        line: 2,
+        col,
+    };
+    let range = vec![Interval {
+        start: end(19, 5),
+        end: end(20, 6),
+    }];
+    assert_eq!(all.visible_goals[0].range, range);
+    assert_eq!(all.invisible_goals[0].name(), "_5");
+    let ranges = all.goal_ranges();
+    assert_eq!(ranges.len(), 1);
+    assert_eq!(ranges[&0.into()], range);
+}
+
+#[test]
+fn jump_to_error_positions() {
+    use crate::resp::Position;
+
+    let position = |json: &str| match serde_json::from_str(json).unwrap() {
+        Resp::JumpToError { position, .. } => position,
+        resp => panic!("{:?}", resp),
+    };
+    let old = position(r#"{"kind":"JumpToError","filepath":"/tmp/A.agda","position":42}"#);
+    assert_eq!(old, Position::offset(42));
+    assert_eq!(old.line_col(), None);
+    let new = position(
+        r#"{"kind":"JumpToError","filepath":"/tmp/A.agda","position":{"pos":42,"line":3,"col":7}}"#,
+    );
+    assert_eq!(new.offset, 42);
+    assert_eq!(new.line_col(), Some((3, 7)));
+    let json = serde_json::to_string(&new).unwrap();
+    assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), new);
+}
+
+#[test]
+fn positions_in_text() {
+    use crate::base::{Interval, Position};
+    use crate::cmd::Range;
+
+    let text = "f : ℕ\nf = {! !}\n";
+    let hole = text.find('{').unwrap();
+    let start = Position::at_byte(text, hole).unwrap();
+    assert_eq!((start.offset, start.line_col()), (11, Some((2, 5))));
+    assert_eq!(start.byte_in(text), Some(hole));
+    assert_eq!(Position::offset(11).byte_in(text), Some(hole));
+    assert_eq!(Position::at_byte(text, 6), None);
+    let end = Position::at_byte(text, text.len() - 1).unwrap();
+    let interval = Interval { start, end };
+    assert_eq!(&text[interval.bytes_in(text).unwrap()], "{! !}");
+    assert_eq!(Position::offset(100).byte_in(text), None);
+    let range = Range::of("/tmp/A.agda".to_owned(), &[interval]);
+    assert_eq!(
+        range.to_string(),
+        r#"(intervalsToRange (Just (mkAbsolute "/tmp/A.agda")) [Interval (Pn () 11 2 5) (Pn () 16 2 10)])"#
+    );
+    let aspect = AspectHighlight {
+        range: Interval::offsets(11, 16),
+        ..Default::default()
+    };
+    assert_eq!(aspect.range.start.byte_in(text), Some(hole));
+}
+
+#[test]
+fn goal_input_range() {
+    use crate::base::{Interval, Position};
+
+    let text = "f : ℕ\nf = {! !}\n";
+    let at = |b| Position::at_byte(text, b).unwrap();
+    let range = vec![
+        Interval {
+            start: at(12),
+            end: at(14),
+        },
+        Interval {
+            start: at(15),
+            end: at(17),
+        },
+    ];
+    let input = GoalInput::with_range(0.into(), "/tmp/A.agda".to_owned(), &range, "x".to_owned());
+    assert_eq!(
+        Cmd::give(input).to_string(),
+        "( Cmd_give WithoutForce 0 (intervalsToRange (Just (mkAbsolute \"/tmp/A.agda\")) \
+         [Interval (Pn () 11 2 5) (Pn () 13 2 7),Interval (Pn () 14 2 8) (Pn () 16 2 10)]) \"x\" )"
+    );
+    let input = GoalInput::with_range(0.into(), "/tmp/A.agda".to_owned(), &[], "x".to_owned());
+    assert_eq!(input.to_string(), "0 noRange \"x\"");
+}
+
+#[test]
+fn context_entries() {
+    use crate::resp::{Relevance, ResponseContextEntry};
+
+    let line = r#"{"kind":"DisplayInfo","info":{"kind":"Context","interactionPoint":0,"context":[
+        {"originalName":"x","reifiedName":"x","binding":"@0 .Nat","inScope":"InScope"},
+        {"originalName":"y","reifiedName":"y₁","binding":"..Nat","inScope":false}]}}"#;
+    let context: Vec<ResponseContextEntry> = match deserialize_agda(line).unwrap() {
+        Resp::DisplayInfo {
+            info: Some(DisplayInfo::Context { context, .. }),
+        } => context,
+        resp => panic!("{:?}", resp),
+    };
+    assert!(context[0].in_scope && context[0].is_erased());
+    assert_eq!(context[0].relevance(), Relevance::Irrelevant);
+    assert_eq!(context[0].the_type(), "Nat");
+    assert!(!context[1].in_scope && !context[1].is_erased());
+    assert_eq!(context[1].relevance(), Relevance::ShapeIrrelevant);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unknown_responses_are_skipped() {
+    use crate::agda::ReplState;
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"SomethingNew","what":1}'
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"AllGoalsWarnings","visibleGoals":[],"invisibleGoals":[],"warnings":"","errors":""}}'
+echo 'JSON> {"kind":"DisplayInfo","info":'
+"#;
+    let program = mock_script("agda-mode-mock-unknown", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    assert!(agda.next_all_goals_warnings().await.unwrap().is_ok());
+    match agda.response().await.unwrap() {
+        Resp::ParseFailure { raw, .. } => assert!(raw.ends_with(r#""info":"#)),
+        resp => panic!("{:?}", resp),
+    }
+    assert!(agda.stderr_lines().is_empty());
+    let logged = agda.protocol_log();
+    assert_eq!(logged.len(), 2);
+    assert!(logged[0].contains("SomethingNew"));
+}
+
+#[test]
+fn stderr_keeps_the_last_lines() {
+    use crate::agda::{StderrLines, STDERR_CAPACITY};
+
+    let mut lines = StderrLines::default();
+    lines.push("first".to_owned());
+    assert_eq!(lines.since(0).count(), 1);
+    assert_eq!(lines.since(1).count(), 0);
+    for i in 0..STDERR_CAPACITY {
+        lines.push(i.to_string());
+    }
+    assert_eq!(lines.since(0).count(), STDERR_CAPACITY);
+    assert_eq!(lines.since(0).next().unwrap(), "0");
+    let new: Vec<_> = lines.since(STDERR_CAPACITY - 1).collect();
+    assert_eq!(new, ["254", "255"]);
+}
+
+#[test]
+fn why_in_scope() {
+    use crate::resp::ScopeExplanation;
+
+    let message = "Nat is in scope as
+  * a datatype Agda.Builtin.Nat.Nat brought into scope by
+    - the opening of Agda.Builtin.Nat at /tmp/A.agda:3,13-29
+    - its definition at /lib/Agda/Builtin/Nat.agda:8,6-9
+  * a variable bound at /tmp/A.agda:5,3-6";
+    let explanation = ScopeExplanation::parse(message);
+    assert_eq!(explanation.name, "Nat");
+    let datatype = &explanation.bindings[0];
+    assert_eq!(datatype.what, "a datatype Agda.Builtin.Nat.Nat");
+    assert_eq!(datatype.steps[0].how, "the opening of Agda.Builtin.Nat");
+    let site = datatype.definition_site().unwrap();
+    assert_eq!(
+        (site.file.as_str(), site.start),
+        ("/lib/Agda/Builtin/Nat.agda", (8, 6))
+    );
+    let variable = &explanation.bindings[1];
+    assert_eq!(variable.what, "a variable");
+    assert_eq!(variable.definition_site().unwrap().start, (5, 3));
+    let missing = ScopeExplanation::parse("y is not in scope.");
+    assert_eq!(missing.name, "y");
+    assert!(missing.bindings.is_empty());
+}
+
+#[test]
+fn duplicate_bindings() {
+    let text = "———— Error —————————————————————————————————————————————————\n\
+                /tmp/A.agda:5,1-2\n\
+                Multiple definitions of f. Previous definition at\n\
+                /tmp/A.agda:3,1-2\n\
+                when scope checking the declaration\n  \
+                f = zero\n";
+    let bindings = DuplicateBinding::parse(text);
+    assert_eq!(bindings.len(), 1);
+    let binding = &bindings[0];
+    assert_eq!(binding.kind, DuplicateKind::Definition);
+    assert_eq!(binding.message, "Multiple definitions of f.");
+    assert_eq!(binding.location.as_deref(), Some("/tmp/A.agda:5,1-2"));
+    assert_eq!(binding.original.as_deref(), Some("/tmp/A.agda:3,1-2"));
+    assert_eq!(
+        binding.to_string(),
+        "/tmp/A.agda:5,1-2: Multiple definitions of f.\n  /tmp/A.agda:3,1-2: first defined here"
+    );
+    let text = "/tmp/A.agda:7,9-10\nx shadows a binding at /tmp/A.agda:7,5-6\n";
+    let bindings = DuplicateBinding::parse(text);
+    assert_eq!(bindings[0].kind, DuplicateKind::Shadowing);
+    assert_eq!(bindings[0].message, "x shadows a binding");
+    assert_eq!(bindings[0].original.as_deref(), Some("/tmp/A.agda:7,5-6"));
+    let text = "/tmp/A.agda:3,5-11\nShadowing in telescope: x\n";
+    assert_eq!(
+        DuplicateBinding::parse(text)[0].kind,
+        DuplicateKind::Shadowing
+    );
+    let text = "/tmp/A.agda:3,5-14\nNot in scope:\n  shadowNat at /tmp/A.agda:3,5-14\n";
+    assert!(DuplicateBinding::parse(text).is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn internal_errors_are_fatal() {
+    use crate::agda::{AgdaInternalError, ReplState};
+
+    let script = r#"#!/bin/sh
+read -r line
+echo 'JSON> {"kind":"DisplayInfo","info":{"kind":"Error","message":"An internal error has occurred. Please report this as a bug."}}'
+"#;
+    let program = mock_script("agda-mode-mock-internal", script);
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    let e = agda.next_all_goals_warnings().await.unwrap_err();
+    let fatal = AgdaInternalError::of(&e).unwrap().clone();
+    assert!(fatal.message.starts_with("An internal error"));
+    // The stream is not read anymore.
+    let e = agda.response().await.unwrap_err();
+    assert_eq!(AgdaInternalError::of(&e), Some(&fatal));
+
+    let program = mock_script("agda-mode-mock-exit", "#!/bin/sh\nread -r line\n");
+    let mut agda = ReplState::start(&program, "A.agda".to_owned())
+        .await
+        .unwrap();
+    let e = agda.response().await.unwrap_err();
+    assert!(AgdaInternalError::of(&e)
+        .unwrap()
+        .message
+        .starts_with("Agda exited."));
+}
+
+#[test]
+fn error_ranges() {
+    let json = r#"{"kind":"Error","error":{"kind":"TypeError",
+        "message":"/tmp/A.agda:3,1-9\nfirst clause\n/tmp/A.agda:4,1-5,3\nsecond clause","range":[
+        {"start":{"pos":20,"line":3,"col":1},"end":{"pos":28,"line":3,"col":9}},
+        {"start":{"pos":29,"line":4,"col":1},"end":{"pos":40,"line":5,"col":3}}]}}"#;
+    let info: DisplayInfo = serde_json::from_str(json).unwrap();
+    let error = match info {
+        DisplayInfo::Error {
+            error: Some(error), ..
+        } => error,
+        info => panic!("{:?}", info),
+    };
+    let message = error.to_message();
+    // The places in the message are the ones of the range.
+    let ranges = LabeledRange::parse(&message);
+    assert_eq!(ranges.len(), error.range.len());
+    for (labeled, interval) in ranges.iter().zip(&error.range) {
+        assert_eq!(labeled.interval().start.line, interval.start.line);
+        assert_eq!(labeled.interval().end.col, interval.end.col);
+    }
+    assert_eq!(ranges[1].label, "second clause");
+    let error = ErrorObject {
+        message: "Oops".to_owned(),
+        kind: None,
+        range: Vec::new(),
+    };
+    assert_eq!(error.to_message(), "Oops");
+}
+
+#[test]
+fn structured_error_de() {
+    let line = r#"{"kind":"DisplayInfo","info":{"kind":"Error","error":{"kind":"TypeError","message":"Nat != Bool",
+        "range":[{"start":{"pos":20,"line":3,"col":5},"end":{"pos":24,"line":3,"col":9}}]}}}"#;
+    let error = match deserialize_agda(line).unwrap() {
+        Resp::DisplayInfo {
+            info: Some(DisplayInfo::Error {
+                error: Some(error), ..
+            }),
+        } => error,
+        resp => panic!("{:?}", resp),
+    };
+    assert_eq!(error.kind.as_deref(), Some("TypeError"));
+    assert_eq!(error.range[0].start.line, 3);
+    assert_eq!(error.to_message(), "Nat != Bool");
+    let bare = r#"{"kind":"Error","error":{"kind":"ParseError"}}"#;
+    match serde_json::from_str(bare).unwrap() {
+        DisplayInfo::Error {
+            error: Some(error), ..
+        } => assert!(error.message.is_empty()),
+        info => panic!("{:?}", info),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn kill_hung_agda() {
+    use crate::agda::ReplState;
+
+    let program = mock_script(
+        "agda-mode-mock-hung",
+        "#!/bin/sh\n[ \"$1\" = --version ] || exec sleep 100\n",
+    );
+    let mut agda = ReplState::start(&program, "/work/A.agda".to_owned())
+        .await
+        .unwrap();
+    agda.kill();
+    assert!(agda.next_all_goals_warnings().await.is_err());
+}